@@ -8,6 +8,7 @@ SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
 SERVICE_FILE="nettest.service"
 SYSTEMD_DIR="/usr/lib/systemd/system"
 SERVICE_PATH="$SYSTEMD_DIR/$SERVICE_FILE"
+SOCKET_FILE="nettest.socket"
 
 # Check if running as root
 if [ "$EUID" -ne 0 ]; then 
@@ -39,6 +40,13 @@ cp "$SCRIPT_DIR/$SERVICE_FILE" "$SERVICE_PATH"
 # Set proper permissions
 chmod 644 "$SERVICE_PATH"
 
+# Copy the optional socket unit for socket activation
+if [ -f "$SCRIPT_DIR/$SOCKET_FILE" ]; then
+    echo "Copying $SOCKET_FILE to $SYSTEMD_DIR/$SOCKET_FILE..."
+    cp "$SCRIPT_DIR/$SOCKET_FILE" "$SYSTEMD_DIR/$SOCKET_FILE"
+    chmod 644 "$SYSTEMD_DIR/$SOCKET_FILE"
+fi
+
 # Reload systemd daemon
 echo "Reloading systemd daemon..."
 systemctl daemon-reload
@@ -52,4 +60,7 @@ echo "  systemctl stop nettest      # Stop the service"
 echo "  systemctl restart nettest   # Restart the service"
 echo "  systemctl status nettest    # Check service status"
 echo "  systemctl disable nettest   # Disable service from starting on boot"
+echo ""
+echo "Optional socket activation (systemd binds the ports, no root needed):"
+echo "  systemctl enable --now nettest.socket"
 
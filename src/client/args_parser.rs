@@ -1,28 +1,13 @@
+use std::time::Duration;
+
 use log::{debug, LevelFilter};
 
-use crate::{client::{client::ClientConfig, control_server::get_best_measurement_server}, config::FileConfig, logger};
+use crate::{client::{check::parse_speed, client::ClientConfig, durations::parse_duration, export::prometheus::parse_exporter_address, mdns::discover_local_server, measurement::discover_server, schedule::parse_period, source::{parse_source_address, AddressFamily}}, config::FileConfig, logger};
 
 pub async fn parse_args(args: Vec<String>, default_config: FileConfig) -> Result<ClientConfig, anyhow::Error> {
     debug!("Default config: {:?}", default_config);
 
-    let mut config = ClientConfig {
-        use_tls: default_config.client_use_tls,
-        use_websocket: default_config.client_use_websocket,
-        graphs: false,
-        raw_output: false,
-        log: None,
-        thread_count: default_config.client_thread_count,
-        server: None,
-        port: default_config.server_tcp_port.parse().unwrap_or(5005),
-        tls_port: default_config.server_tls_port.unwrap_or("443".to_string()).parse().unwrap(),
-        x_nettest_client: default_config.x_nettest_client,
-        control_server: default_config.control_server,
-        save_results: false,
-        signed_result: default_config.signed_result,
-        client_uuid: default_config.client_uuid,
-        git_hash: None,
-        legacy: false,
-    };
+    let mut config = ClientConfig::from_file_config(&default_config);
 
 
     let mut i = 0;
@@ -31,7 +16,199 @@ pub async fn parse_args(args: Vec<String>, default_config: FileConfig) -> Result
             "-t" => {
                 i += 1;
                 if i < args.len() {
-                    config.thread_count = args[i].parse()?;
+                    if args[i] == "auto" {
+                        config.auto_threads = true;
+                    } else {
+                        config.thread_count = args[i].parse()?;
+                        config.auto_threads = false;
+                    }
+                }
+            }
+            "-duration" => {
+                i += 1;
+                if i < args.len() {
+                    let duration = parse_seconds(&args[i])?;
+                    config.durations.download = duration;
+                    config.durations.upload = duration;
+                }
+            }
+            "-download-duration" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.download = parse_seconds(&args[i])?;
+                }
+            }
+            "-upload-duration" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.upload = parse_seconds(&args[i])?;
+                }
+            }
+            "-pre-download" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.pre_download = parse_seconds(&args[i])?;
+                }
+            }
+            "-ping-duration" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.ping = parse_seconds(&args[i])?;
+                }
+            }
+            "-pings" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.ping_count = args[i].parse()?;
+                }
+            }
+            "-loops" => {
+                i += 1;
+                if i < args.len() {
+                    config.event_loops = args[i].parse()?;
+                }
+            }
+            "-schedule" => {
+                i += 1;
+                if i < args.len() {
+                    config.schedule = Some(args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?);
+                }
+            }
+            "-history" => {
+                i += 1;
+                if i < args.len() {
+                    config.history_file = Some(args[i].clone().into());
+                }
+            }
+            "-no-history" => {
+                config.history_file = None;
+            }
+            "-warn-ping" | "-crit-ping" => {
+                let flag = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let limit = parse_duration(&args[i]).map_err(|e| anyhow::anyhow!("{} for {}", e, flag))?;
+                    match flag.as_str() {
+                        "-warn-ping" => config.thresholds.warn_ping = Some(limit),
+                        _ => config.thresholds.crit_ping = Some(limit),
+                    }
+                }
+            }
+            "-warn-download" | "-crit-download" | "-warn-upload" | "-crit-upload" => {
+                let flag = args[i].clone();
+                i += 1;
+                if i < args.len() {
+                    let limit = parse_speed(&args[i]).map_err(|e| anyhow::anyhow!("{} for {}", e, flag))?;
+                    let thresholds = &mut config.thresholds;
+                    match flag.as_str() {
+                        "-warn-download" => thresholds.warn_download = Some(limit),
+                        "-crit-download" => thresholds.crit_download = Some(limit),
+                        "-warn-upload" => thresholds.warn_upload = Some(limit),
+                        _ => thresholds.crit_upload = Some(limit),
+                    }
+                }
+            }
+            "-probe" => {
+                i += 1;
+                if i < args.len() {
+                    config.probe_servers = args[i].parse()?;
+                }
+            }
+            "-on-net" => {
+                config.server_filter.on_net = true;
+            }
+            "-dedicated" => {
+                config.server_filter.dedicated = true;
+            }
+            "-ipv6" => {
+                config.server_filter.ipv6 = true;
+            }
+            "-country" => {
+                i += 1;
+                if i < args.len() {
+                    config.server_filter.country = Some(args[i].clone());
+                }
+            }
+            "-provider" => {
+                i += 1;
+                if i < args.len() {
+                    config.server_filter.provider = Some(args[i].clone());
+                }
+            }
+            "-min-version" => {
+                i += 1;
+                if i < args.len() {
+                    semver::Version::parse(&args[i])
+                        .map_err(|_| anyhow::anyhow!("Invalid version: {}, expected e.g. 2.0.0", args[i]))?;
+                    config.server_filter.min_version = args[i].clone();
+                }
+            }
+            "-list-servers" => {
+                config.list_servers = true;
+            }
+            "-local" => {
+                config.local = true;
+            }
+            "-I" => {
+                i += 1;
+                if i < args.len() {
+                    config.source.interface = Some(args[i].clone());
+                }
+            }
+            "-B" => {
+                i += 1;
+                if i < args.len() {
+                    config.source.address = Some(parse_source_address(&args[i]).map_err(|e| anyhow::anyhow!(e))?);
+                }
+            }
+            "-4" => {
+                config.source.family = AddressFamily::V4;
+                config.server_filter.ipv4 = true;
+            }
+            "-6" => {
+                config.source.family = AddressFamily::V6;
+                config.server_filter.ipv6 = true;
+            }
+            "-dualstack" => {
+                config.dualstack = true;
+                config.server_filter.ipv4 = true;
+                config.server_filter.ipv6 = true;
+            }
+            "-exporter" => {
+                i += 1;
+                if i < args.len() {
+                    config.exporter = Some(parse_exporter_address(&args[i]).map_err(|e| anyhow::anyhow!(e))?);
+                }
+            }
+            "-exporter-max-age" => {
+                i += 1;
+                if i < args.len() {
+                    config.exporter_max_age = parse_period(&args[i]).map_err(|e| anyhow::anyhow!(e))?;
+                }
+            }
+            "-retries" => {
+                i += 1;
+                if i < args.len() {
+                    config.retries = args[i].parse()?;
+                }
+            }
+            "-thread-thresholds" => {
+                i += 1;
+                if i < args.len() {
+                    config.thread_thresholds =
+                        args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?;
+                }
+            }
+            "-phases" => {
+                i += 1;
+                if i < args.len() {
+                    config.phases = args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?;
+                }
+            }
+            "-warmup" => {
+                i += 1;
+                if i < args.len() {
+                    config.durations.warmup = parse_seconds(&args[i])?;
                 }
             }
             "-c" => {
@@ -60,6 +237,27 @@ pub async fn parse_args(args: Vec<String>, default_config: FileConfig) -> Result
             "-raw" => {
                 config.raw_output = true;
             }
+            "-json" => {
+                config.json_output = true;
+            }
+            "-csv" => {
+                i += 1;
+                if i < args.len() {
+                    config.csv_file = Some(args[i].clone().into());
+                }
+            }
+            "-influx" => {
+                i += 1;
+                if i < args.len() {
+                    config.influx_file = Some(args[i].clone().into());
+                }
+            }
+            "-influx-url" => {
+                i += 1;
+                if i < args.len() {
+                    config.influx_url = Some(args[i].clone());
+                }
+            }
             "-save" => {
                 config.save_results = true;
             }
@@ -110,27 +308,28 @@ pub async fn parse_args(args: Vec<String>, default_config: FileConfig) -> Result
         i += 1;
     }
 
+    config.durations.validate().map_err(|e| anyhow::anyhow!(e))?;
+    if config.dualstack && config.source.family != AddressFamily::Any {
+        return Err(anyhow::anyhow!("-dualstack measures over both address families, leave out -4 and -6"));
+    }
+    if config.dualstack && (config.schedule.is_some() || config.exporter.is_some() || config.is_check() || config.local) {
+        return Err(anyhow::anyhow!("-dualstack can't be combined with -schedule, -exporter, -warn-*/-crit-* or -local"));
+    }
+
     if config.log.is_some() || default_config.logger != LevelFilter::Off {
+        logger::set_log_options(default_config.log_options.clone());
         logger::init_logger(config.log.unwrap_or(default_config.logger)).unwrap();
     }
-    if config.server.is_none()  {
-        debug!("No server address provided, using default");
-        //TODO: verify tls
-        let server = get_best_measurement_server(&config.x_nettest_client, &config.control_server).await?.ok_or_else(|| {
-            anyhow::anyhow!("No server found. Probably no running servers of version 2.0.0 or higher")
-        })?;
-        let address = if server.web_address.is_empty() {
-            server.ip_address.unwrap()
-        } else {
-            server.web_address.clone()
-        };
-        config.server = Some(address);
-        let details = server.server_type_details;
-        let rmbt_details = details.iter().find(|s| s.server_type == "RMBT");
-        if rmbt_details.is_some() {
-            config.port = rmbt_details.unwrap().port as u16;
-            config.tls_port = rmbt_details.unwrap().port_ssl as u16;
-        } 
+    // A server on the local network is preferred over the control server's
+    // nearest one, `-local` insists on it. mDNS yields addresses, while
+    // `-dualstack` needs a host name to resolve in both families.
+    if config.list_servers {
+        return Ok(config);
+    }
+    if (config.local || config.server.is_none())
+        && (config.dualstack || !discover_local_server(&mut config)?)
+    {
+        discover_server(&mut config).await?;
     }
 
     Ok(config)
@@ -139,28 +338,77 @@ pub async fn parse_args(args: Vec<String>, default_config: FileConfig) -> Result
 
 
 
+fn parse_seconds(value: &str) -> anyhow::Result<Duration> {
+    parse_duration(value).map_err(|e| anyhow::anyhow!(e))
+}
+
 pub fn print_help() {
     println!("nettest - Network speed measurement client\n");
     println!("USAGE:");
     println!("    nettest                          Auto-discover server and run test");
     println!("    nettest -c [SERVER] [OPTIONS]    Connect to specific server\n");
     println!("EXAMPLES:");
-    println!("    nettest                          Find nearest server automatically");
+    println!("    nettest                          Find a local or the nearest server automatically");
     println!("    nettest -c 192.168.1.100         Connect to server at 192.168.1.100");
     println!("    nettest -c example.com -tls      Connect using TLS encryption");
-    println!("    nettest -c example.com -tls -ws  Connect using TLS over WebSocket\n");
+    println!("    nettest -c example.com -tls -ws  Connect using TLS over WebSocket");
+    println!("    nettest -c example.com -schedule 15m  Measure every 15 minutes");
+    println!("    nettest -c example.com -crit-download 100M -crit-ping 50ms  Monitoring check");
+    println!("    nettest -c example.com -exporter :9469  Serve Prometheus metrics on port 9469\n");
     println!("OPTIONS:");
     println!("    -c [SERVER]     Run as client, optionally specify server address");
+    println!("    -list-servers   List the servers auto-selection chooses from, fastest first");
+    println!("    -probe N        Probe the N nearest servers and use the fastest (default: 5, 0 for nearest)");
+    println!("    -on-net / -dedicated / -ipv6  Only on-net, dedicated or IPv6 capable servers");
+    println!("    -country CC     Only servers of providers in, or serving, country CC");
+    println!("    -provider NAME  Only servers whose provider name contains NAME");
+    println!("    -min-version V  Only servers of version V or newer (default: 2.0.0)");
+    println!("    -local          Use a server on the local network found over mDNS, see 'nettest discover'");
+    println!("    -I IFACE        Bind all connections, lookups and requests to interface IFACE (Linux)");
+    println!("    -B ADDRESS      Bind all connections, lookups and requests to source ADDRESS");
+    println!("    -4 / -6         Connect over IPv4 / IPv6 only");
+    println!("    -dualstack      Measure over IPv4, then IPv6, and compare the results");
     println!("    -p PORT         Server port (default: 5005 for TCP, 443 for TLS)");
-    println!("    -t THREADS      Number of parallel threads (default: from config)");
+    println!("    -t THREADS      Number of parallel threads or auto (default: from config)");
+    println!("    -thread-thresholds LIST  Threads for -t auto by pre-test speed, MBIT:THREADS,...");
+    println!("                             (default: 0:1,10:3,250:5,1000:8,5000:16)");
+    println!("    -duration SECS  Download and upload duration (default: 7)");
+    println!("    -download-duration SECS  Download duration, whole seconds (default: 7)");
+    println!("    -upload-duration SECS    Upload duration (default: 7)");
+    println!("    -pre-download SECS       Chunk size discovery duration (default: 2)");
+    println!("    -ping-duration SECS      Ping duration (default: 1)");
+    println!("    -pings N                 Maximum number of pings (default: 200)");
+    println!("    -phases LIST             Phases to run: ping,download,upload (default: all)");
+    println!("    -warmup SECS             Start of download/upload left out of the speed (default: 1)");
+    println!("    -retries N               Reconnects per thread to repeat a failed connect or ping (default: 0)");
+    println!("    -loops N                 Event loops driving the threads (default: 0, one per CPU core)");
+    println!("    -schedule SPEC           Keep running, measure every interval (15m, 6h) or on a cron");
+    println!("                             expression in local time (\"*/15 * * * *\", @hourly)");
+    println!("    -history FILE            Append each run to FILE (default: ~/.local/share/nettest/history.jsonl)");
+    println!("    -no-history              Don't record this run, see 'nettest history'");
+    println!("    -warn-ping TIME          Check: WARNING when the ping median is above TIME, e.g. 30ms");
+    println!("    -crit-ping TIME          Check: CRITICAL when the ping median is above TIME");
+    println!("    -warn-download SPEED     Check: WARNING when the download is below SPEED, e.g. 500M, 1G, 800k");
+    println!("    -crit-download SPEED     Check: CRITICAL when the download is below SPEED");
+    println!("    -warn-upload SPEED       Check: WARNING when the upload is below SPEED");
+    println!("    -crit-upload SPEED       Check: CRITICAL when the upload is below SPEED");
+    println!("    -exporter ADDR           Serve the last measurement as Prometheus metrics on ADDR/metrics");
+    println!("    -exporter-max-age PERIOD Without -schedule, measure when a scrape finds an older result (default: 5m)");
     println!("    -tls            Use TLS encryption");
     println!("    -ws             Use WebSocket protocol");
     println!("    -g              Display download/upload graphs");
     println!("    -raw            Output results in parseable format: ping/download/upload");
+    println!("    -json           Output the complete result as JSON (see JSON_OUTPUT.md)");
+    println!("    -csv FILE       Write throughput samples to FILE and a summary to FILE.summary.csv");
+    println!("    -influx FILE    Append InfluxDB line protocol points to FILE");
+    println!("    -influx-url URL POST InfluxDB line protocol points to URL");
     println!("    -save           Save results to control server");
     println!("    -signed         Request signed result from server");
     println!("    -legacy         Use legacy PUT command instead of PUTTIMERESULT");
     println!("    -log LEVEL      Set log level: info, debug, trace");
     println!("    -h, --help      Show this help message");
-    println!("    -v, --version   Print version and exit");
+    println!("    -v, --version   Print version and exit\n");
+    println!("EXIT STATUS:");
+    println!("    0 complete, 1 failed (no phase measured), 2 degraded (threads failed or retried)");
+    println!("    With -warn-*/-crit-*: 0 OK, 1 WARNING, 2 CRITICAL, 3 UNKNOWN, plus a status line with perfdata");
 }
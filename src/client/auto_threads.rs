@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// `-t auto`: number of connections by pre-download speed, as
+/// `(minimum Mbit/s, connections)` pairs in ascending order
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThreadThresholds(Vec<(f64, usize)>);
+
+impl Default for ThreadThresholds {
+    fn default() -> Self {
+        Self(vec![(0.0, 1), (10.0, 3), (250.0, 5), (1000.0, 8), (5000.0, 16)])
+    }
+}
+
+impl ThreadThresholds {
+    /// Connections for a pre-download speed, those of the highest threshold
+    /// not above `mbps`
+    pub fn threads_for(&self, mbps: f64) -> usize {
+        self.0
+            .iter()
+            .take_while(|(min_mbps, _)| *min_mbps <= mbps)
+            .last()
+            .or(self.0.first())
+            .map(|(_, threads)| *threads)
+            .unwrap_or(1)
+    }
+}
+
+impl FromStr for ThreadThresholds {
+    type Err = String;
+
+    /// Comma separated `MBIT:THREADS` pairs, e.g. `0:1,10:3,1000:8`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut thresholds = Vec::new();
+        for pair in value.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+            let parsed = pair.split_once(':').and_then(|(mbps, threads)| {
+                let mbps = mbps.trim().parse::<f64>().ok().filter(|m| m.is_finite() && *m >= 0.0)?;
+                let threads = threads.trim().parse::<usize>().ok().filter(|t| *t > 0)?;
+                Some((mbps, threads))
+            });
+            match parsed {
+                Some(threshold) => thresholds.push(threshold),
+                None => {
+                    return Err(format!(
+                        "invalid thread threshold '{}', expected MBIT:THREADS like 100:5",
+                        pair
+                    ))
+                }
+            }
+        }
+        if thresholds.is_empty() {
+            return Err("at least one MBIT:THREADS threshold is required".to_string());
+        }
+        thresholds.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(Self(thresholds))
+    }
+}
+
+impl fmt::Display for ThreadThresholds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pairs: Vec<String> = self
+            .0
+            .iter()
+            .map(|(mbps, threads)| format!("{}:{}", mbps, threads))
+            .collect();
+        write!(f, "{}", pairs.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_threads_for_pre_download_speed() {
+        let thresholds: ThreadThresholds = "100:5, 0:1, 10:3".parse().unwrap();
+        assert_eq!(thresholds.to_string(), "0:1,10:3,100:5");
+        assert_eq!(thresholds.threads_for(0.5), 1);
+        assert_eq!(thresholds.threads_for(10.0), 3);
+        assert_eq!(thresholds.threads_for(25_000.0), 5);
+        assert_eq!(ThreadThresholds::default().threads_for(900.0), 5);
+    }
+
+    #[test]
+    fn test_speed_below_lowest_threshold() {
+        let fast_only: ThreadThresholds = "50:4".parse().unwrap();
+        assert_eq!(fast_only.threads_for(1.0), 4);
+    }
+
+    #[test]
+    fn test_invalid_thresholds() {
+        assert!("10".parse::<ThreadThresholds>().is_err());
+        assert!("10:0".parse::<ThreadThresholds>().is_err());
+    }
+}
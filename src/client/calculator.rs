@@ -1,12 +1,11 @@
-use crate::client::{client::Measurement};
+use crate::client::{client::ThreadMeasurement};
 
-pub fn calculate_speed_from_measurements(measurements: Vec<Vec<(u64, u64)>>) -> (f64, f64, f64) {
+/// Speed over all threads, leaving out the first `skip_time_ns` of the phase (warm-up)
+pub fn calculate_speed_from_measurements(measurements: Vec<Vec<(u64, u64)>>, skip_time_ns: u64) -> (f64, f64, f64) {
     if measurements.is_empty() {
         return (0.0, 0.0, 0.0);
     }
 
-    let skip_time_ns = 1_000_000_000u64; // 1 second in nanoseconds
-    
     // Find minimum measurement start time
     let min_start_time = measurements
         .iter()
@@ -25,12 +24,12 @@ pub fn calculate_speed_from_measurements(measurements: Vec<Vec<(u64, u64)>>) ->
         return (0.0, 0.0, 0.0);
     }
 
-    // t* accounting for skipping first 2 seconds
-    let t_star = t_star_original - skip_time_ns;
-    // If after skipping 2 seconds time is insufficient, return 0
-    if t_star <= 0 {
+    // If after skipping the warm-up time is insufficient, return 0
+    if t_star_original <= skip_time_ns {
         return (0.0, 0.0, 0.0);
     }
+    // t* accounting for skipping the warm-up
+    let t_star = t_star_original - skip_time_ns;
 
     let mut total_bytes = 0.0;
 
@@ -124,15 +123,15 @@ fn interpolate_bytes_at_time(measurements: &[(u64, u64)], target_time: u64) -> f
 }
 
 
-pub fn calculate_download_speed_from_stats_silent(stats: &Vec<Vec<(u64, u64)>>) -> (f64, f64, f64) {
-    calculate_speed_from_measurements(stats.clone())
+pub fn calculate_download_speed_from_stats_silent(stats: &Vec<Vec<(u64, u64)>>, skip_time_ns: u64) -> (f64, f64, f64) {
+    calculate_speed_from_measurements(stats.clone(), skip_time_ns)
 }
 
-pub fn calculate_upload_speed_from_stats_silent(stats: &Vec<Vec<(u64, u64)>>) -> (f64, f64, f64) {
-    calculate_speed_from_measurements(stats.clone())
+pub fn calculate_upload_speed_from_stats_silent(stats: &Vec<Vec<(u64, u64)>>, skip_time_ns: u64) -> (f64, f64, f64) {
+    calculate_speed_from_measurements(stats.clone(), skip_time_ns)
 }
 
-pub fn calculate_download_speed(states: &Vec<Measurement>) -> (f64, f64, f64) {
+pub fn calculate_download_speed(states: &Vec<ThreadMeasurement>, skip_time_ns: u64) -> (f64, f64, f64) {
     let mut thread_measurements: Vec<Vec<(u64, u64)>> = Vec::new();
     for state in states {
         if state.failed {
@@ -148,5 +147,5 @@ pub fn calculate_download_speed(states: &Vec<Measurement>) -> (f64, f64, f64) {
         );
     }
 
-    calculate_speed_from_measurements(thread_measurements)
+    calculate_speed_from_measurements(thread_measurements, skip_time_ns)
 }
\ No newline at end of file
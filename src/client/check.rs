@@ -0,0 +1,276 @@
+//! `-warn-*` / `-crit-*`: turns a measurement into a Nagios/Icinga style check
+//! with a one-line status, perfdata and the exit codes 0 OK, 1 WARNING,
+//! 2 CRITICAL and 3 UNKNOWN.
+
+use std::fmt::Write as _;
+use std::time::Duration;
+
+use crate::client::measurement::{MeasurementResult, MeasurementStatus};
+
+/// Limits of a check. Ping is critical above its limit, download and upload
+/// below theirs; speeds are in bit/s.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Thresholds {
+    pub warn_ping: Option<Duration>,
+    pub crit_ping: Option<Duration>,
+    pub warn_download: Option<f64>,
+    pub crit_download: Option<f64>,
+    pub warn_upload: Option<f64>,
+    pub crit_upload: Option<f64>,
+}
+
+impl Thresholds {
+    /// Command line options that set a limit
+    pub const FLAGS: [&'static str; 6] = [
+        "-warn-ping",
+        "-crit-ping",
+        "-warn-download",
+        "-crit-download",
+        "-warn-upload",
+        "-crit-upload",
+    ];
+
+    /// Without any limit the client reports as usual
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CheckState {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl CheckState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::Warning => "WARNING",
+            Self::Critical => "CRITICAL",
+            Self::Unknown => "UNKNOWN",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Ok => 0,
+            Self::Warning => 1,
+            Self::Critical => 2,
+            Self::Unknown => 3,
+        }
+    }
+}
+
+pub struct Check {
+    pub state: CheckState,
+    /// `NETTEST <STATE> - <summary> | <perfdata>`
+    pub line: String,
+}
+
+impl Check {
+    /// The measurement did not run, e.g. the server could not be reached
+    pub fn unknown(error: &anyhow::Error) -> Self {
+        Self {
+            state: CheckState::Unknown,
+            line: format!("NETTEST UNKNOWN - {:#}", error).replace('\n', " "),
+        }
+    }
+
+    /// Worst state of the limits. A limited value that was not measured is
+    /// UNKNOWN, as is a measurement without any value; failed or retried
+    /// threads make it at least WARNING.
+    pub fn evaluate(thresholds: &Thresholds, result: &MeasurementResult) -> Self {
+        let mut state = CheckState::Ok;
+        let mut summary = Vec::new();
+        let mut perfdata = Vec::new();
+
+        let ping_ms = result.ping_median_ms();
+        let warn_ping_ms = thresholds.warn_ping.map(|limit| limit.as_secs_f64() * 1000.0);
+        let crit_ping_ms = thresholds.crit_ping.map(|limit| limit.as_secs_f64() * 1000.0);
+        let (ping_state, ping_text) = limit_above(ping_ms, warn_ping_ms, crit_ping_ms);
+        state = state.max(ping_state);
+        summary.push(match ping_ms {
+            Some(ms) => format!("ping {:.2} ms{}", ms, ping_text),
+            None => "ping not measured".to_string(),
+        });
+        perfdata.push(format!(
+            "ping={};{};{};0;",
+            ping_ms.map_or("U".to_string(), |ms| format!("{:.3}ms", ms)),
+            optional(warn_ping_ms),
+            optional(crit_ping_ms)
+        ));
+
+        for (name, speed, warn, crit) in [
+            ("download", result.download, thresholds.warn_download, thresholds.crit_download),
+            ("upload", result.upload, thresholds.warn_upload, thresholds.crit_upload),
+        ] {
+            let bps = speed.map(|speed| speed.bps);
+            let (speed_state, speed_text) = limit_below(bps, warn, crit);
+            state = state.max(speed_state);
+            summary.push(match bps {
+                Some(bps) => format!("{} {:.2} Mbit/s{}", name, bps / 1e6, speed_text),
+                None => format!("{} not measured", name),
+            });
+            // `LIMIT:` is the range syntax for "alert below LIMIT"
+            perfdata.push(format!(
+                "{}={};{};{};0;",
+                name,
+                bps.map_or("U".to_string(), |bps| format!("{:.0}", bps)),
+                warn.map_or(String::new(), |limit| format!("{:.0}:", limit)),
+                crit.map_or(String::new(), |limit| format!("{:.0}:", limit))
+            ));
+        }
+
+        match result.status {
+            MeasurementStatus::Complete => {}
+            MeasurementStatus::Degraded => {
+                state = state.max(CheckState::Warning);
+                summary.push(format!(
+                    "degraded: {} of {} threads failed, {} retries",
+                    result.failed_threads(),
+                    result.thread_count,
+                    result.retries
+                ));
+            }
+            MeasurementStatus::Failed => {
+                state = CheckState::Unknown;
+                summary.push(match result.failed.first() {
+                    Some(thread) => format!("failed: {}", thread.error),
+                    None => "failed".to_string(),
+                });
+            }
+        }
+
+        let mut line = format!("NETTEST {} - {}", state.as_str(), summary.join(", "));
+        let _ = write!(line, " | {}", perfdata.join(" "));
+        Self { state, line }
+    }
+}
+
+/// State and summary suffix of a value that must stay at or below the limits
+fn limit_above(value: Option<f64>, warn: Option<f64>, crit: Option<f64>) -> (CheckState, String) {
+    let Some(value) = value else {
+        return (unmeasured(warn, crit), String::new());
+    };
+    match (warn, crit) {
+        (_, Some(crit)) if value > crit => (CheckState::Critical, format!(" (> {} ms)", crit)),
+        (Some(warn), _) if value > warn => (CheckState::Warning, format!(" (> {} ms)", warn)),
+        _ => (CheckState::Ok, String::new()),
+    }
+}
+
+/// State and summary suffix of a speed in bit/s that must stay at or above
+/// the limits
+fn limit_below(value: Option<f64>, warn: Option<f64>, crit: Option<f64>) -> (CheckState, String) {
+    let Some(value) = value else {
+        return (unmeasured(warn, crit), String::new());
+    };
+    match (warn, crit) {
+        (_, Some(crit)) if value < crit => (CheckState::Critical, format!(" (< {} Mbit/s)", crit / 1e6)),
+        (Some(warn), _) if value < warn => (CheckState::Warning, format!(" (< {} Mbit/s)", warn / 1e6)),
+        _ => (CheckState::Ok, String::new()),
+    }
+}
+
+fn unmeasured(warn: Option<f64>, crit: Option<f64>) -> CheckState {
+    if warn.is_some() || crit.is_some() {
+        CheckState::Unknown
+    } else {
+        CheckState::Ok
+    }
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map_or(String::new(), |value| value.to_string())
+}
+
+/// Speed limit in bit/s: `500M`, `1.5G` or `800k`, a plain number is Mbit/s
+pub fn parse_speed(value: &str) -> Result<f64, String> {
+    let value = value.trim();
+    let (number, unit) = match value.char_indices().last() {
+        Some((i, 'k' | 'K')) => (&value[..i], 1e3),
+        Some((i, 'M')) => (&value[..i], 1e6),
+        Some((i, 'G')) => (&value[..i], 1e9),
+        _ => (value, 1e6),
+    };
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|number| number.is_finite() && *number >= 0.0)
+        .map(|number| number * unit)
+        .ok_or_else(|| format!("invalid speed '{}', expected e.g. 500M, 1.5G or 800k", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::measurement::Speed;
+
+    fn result(ping_ms: u64, download_mbps: f64, upload: Option<f64>) -> MeasurementResult {
+        let speed = |mbps: f64| Speed::from((mbps * 1e6, mbps / 1000.0, mbps));
+        MeasurementResult {
+            thread_count: 4,
+            ping_median_ns: Some(ping_ms * 1_000_000),
+            download: Some(speed(download_mbps)),
+            upload: upload.map(speed),
+            ..MeasurementResult::for_test("probe.example.com", "192.0.2.1:5005")
+        }
+    }
+
+    fn thresholds() -> Thresholds {
+        Thresholds {
+            crit_ping: Some(Duration::from_millis(50)),
+            warn_download: Some(500e6),
+            crit_download: Some(100e6),
+            ..Thresholds::default()
+        }
+    }
+
+    #[test]
+    fn test_parse_speed() {
+        assert_eq!(parse_speed("500M").unwrap(), 500e6);
+        assert_eq!(parse_speed("1.5G").unwrap(), 1.5e9);
+        assert_eq!(parse_speed("800k").unwrap(), 800e3);
+        assert_eq!(parse_speed("100").unwrap(), 100e6);
+        assert!(parse_speed("fast").is_err());
+    }
+
+    #[test]
+    fn test_ok_line_with_perfdata() {
+        let thresholds = thresholds();
+        let check = Check::evaluate(&thresholds, &result(12, 800.0, Some(90.0)));
+        assert_eq!(check.state, CheckState::Ok);
+        assert_eq!(
+            check.line,
+            "NETTEST OK - ping 12.00 ms, download 800.00 Mbit/s, upload 90.00 Mbit/s \
+             | ping=12.000ms;;50;0; download=800000000;500000000:;100000000:;0; upload=90000000;;;0;"
+        );
+    }
+
+    #[test]
+    fn test_warning_below_download_limit() {
+        let check = Check::evaluate(&thresholds(), &result(12, 300.0, None));
+        assert_eq!(check.state, CheckState::Warning);
+        assert!(check.line.contains("download 300.00 Mbit/s (< 500 Mbit/s)"));
+        assert!(check.line.contains(" upload=U;;;0;"));
+    }
+
+    #[test]
+    fn test_critical_above_ping_limit() {
+        let check = Check::evaluate(&thresholds(), &result(80, 50.0, None));
+        assert_eq!(check.state, CheckState::Critical);
+        assert!(check.line.contains("ping 80.00 ms (> 50 ms)"));
+    }
+
+    #[test]
+    fn test_degraded_and_unmeasured_results() {
+        let mut degraded = result(12, 800.0, None);
+        degraded.status = MeasurementStatus::Degraded;
+        assert_eq!(Check::evaluate(&thresholds(), &degraded).state, CheckState::Warning);
+        degraded.download = None;
+        assert_eq!(Check::evaluate(&thresholds(), &degraded).state, CheckState::Unknown);
+    }
+}
@@ -1,12 +1,29 @@
 use crate::client::args_parser::{parse_args, print_help};
 use crate::client::constants::init_max_chunk_size;
+use crate::client::control_server::{MeasurementSaver, ServerFilter};
+use crate::client::auto_threads::ThreadThresholds;
+use crate::client::check::{Check, Thresholds};
+use crate::client::durations::PhaseDurations;
+use crate::client::phases::PhaseSelection;
+use crate::client::export::prometheus::Exporter;
+use crate::client::export::{csv, influx};
+use crate::client::history::{self, HistoryEntry};
+use crate::client::schedule::Schedule;
+use crate::client::setup::ConnectionSetup;
+use crate::client::source::{AddressFamily, SourceBinding};
+use crate::client::print::comparison::{comparison_to_json, print_comparison, raw_comparison, FamilyRun};
 use crate::client::print::graph_service::GraphService;
-use crate::client::print::printer::print_test_header;
-use crate::client::runnner::run_threads;
+use crate::client::print::json_output::JsonPrinter;
+use crate::client::print::printer::{RawPrinter, TablePrinter};
+use crate::client::print::server_list::print_server_list;
+use crate::client::measurement::{list_servers, Measurement, MeasurementResult};
 use crate::config::FileConfig;
+use chrono::Local;
 use log::{info, LevelFilter};
 use std::net::SocketAddr;
-use std::sync::{Arc, Mutex};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 use crate::client::state::TestPhase;
 
 pub struct CommandLineArgs {
@@ -16,14 +33,18 @@ pub struct CommandLineArgs {
     pub use_websocket: bool,
 }
 
-#[derive(Clone)]
-pub struct Measurement {
+/// Samples and outcome of one measurement connection
+#[derive(Clone, Debug)]
+pub struct ThreadMeasurement {
     pub measurements: Vec<(u64, u64)>,
     pub failed: bool,
     pub thread_id: usize,
     pub phase: TestPhase,
     pub upload_measurements: Vec<(u64, u64)>,
     pub envelope: Option<String>,
+    /// Chunk size found in the pre-download phase
+    pub chunk_size: usize,
+    pub setup: ConnectionSetup,
 }
 
 #[derive(Default)]
@@ -38,9 +59,43 @@ pub struct ClientConfig {
     pub use_websocket: bool,
     pub graphs: bool,
     pub raw_output: bool,
+    /// `-json`: print the complete result as JSON
+    pub json_output: bool,
+    /// `-csv FILE`: samples to FILE, summary to `FILE.summary.csv`
+    pub csv_file: Option<PathBuf>,
+    /// `-influx FILE`: InfluxDB line protocol appended to FILE
+    pub influx_file: Option<PathBuf>,
+    /// `-influx-url URL`: InfluxDB line protocol POSTed to URL
+    pub influx_url: Option<String>,
     pub thread_count: usize,
+    /// `-t auto`: `thread_count` is chosen from the pre-download speed
+    pub auto_threads: bool,
+    pub thread_thresholds: ThreadThresholds,
+    pub durations: PhaseDurations,
+    /// `-phases`: phases to run, skipped ones are absent from the result
+    pub phases: PhaseSelection,
+    /// `-retries`: reconnects per connection to repeat a failed phase
+    pub retries: u32,
+    /// `-loops`: event loops driving the connections, 0 for one per CPU core
+    pub event_loops: usize,
+    /// `-schedule`: keep running and measure on this schedule
+    pub schedule: Option<Schedule>,
+    /// History file each run is appended to, `None` with `-no-history`
+    pub history_file: Option<PathBuf>,
+    /// `-warn-*` / `-crit-*`: report as a monitoring plugin when any is set
+    pub thresholds: Thresholds,
+    /// `-exporter ADDR`: serve the last measurement as Prometheus metrics
+    pub exporter: Option<SocketAddr>,
+    /// `-exporter-max-age`: age after which a scrape starts a new measurement
+    pub exporter_max_age: Duration,
     pub log: Option<LevelFilter>,
     pub server: Option<String>,
+    /// Which control server entries auto-selection may use
+    pub server_filter: ServerFilter,
+    /// `-probe N`: nearest servers probed before auto-selecting one
+    pub probe_servers: usize,
+    /// `-list-servers`: print the candidates instead of measuring
+    pub list_servers: bool,
     pub port: u16,
     pub tls_port: u16,
     pub x_nettest_client: String,
@@ -50,9 +105,107 @@ pub struct ClientConfig {
     pub client_uuid: Option<String>,
     pub git_hash: Option<String>,
     pub legacy: bool,
+    /// `-local`: only use a server found over mDNS
+    pub local: bool,
+    /// `-I` / `-B` / `-4` / `-6`: interface, source address and address
+    /// family of every connection
+    pub source: SourceBinding,
+    /// `-dualstack`: measure over IPv4, then IPv6, and compare
+    pub dualstack: bool,
+}
+
+impl ClientConfig {
+    /// Client defaults from the config file, without a server (auto-discovery)
+    pub fn from_file_config(config: &FileConfig) -> Self {
+        Self {
+            use_tls: config.client_use_tls,
+            use_websocket: config.client_use_websocket,
+            graphs: false,
+            raw_output: false,
+            json_output: false,
+            csv_file: None,
+            influx_file: None,
+            influx_url: None,
+            log: None,
+            thread_count: config.client_thread_count,
+            auto_threads: config.client_auto_threads,
+            thread_thresholds: config.client_thread_thresholds.clone(),
+            durations: config.client_durations,
+            phases: config.client_phases,
+            retries: config.client_retries,
+            event_loops: config.client_event_loops,
+            schedule: config.client_schedule.clone(),
+            history_file: config
+                .client_history
+                .then(|| history::history_file(config))
+                .flatten(),
+            thresholds: Thresholds::default(),
+            exporter: config.client_exporter,
+            exporter_max_age: config.client_exporter_max_age,
+            server: None,
+            server_filter: ServerFilter::default(),
+            probe_servers: config.client_probe_servers,
+            list_servers: false,
+            port: config.server_tcp_port,
+            tls_port: config.server_tls_port.unwrap_or(443),
+            x_nettest_client: config.x_nettest_client.clone(),
+            control_server: config.control_server.clone(),
+            save_results: false,
+            signed_result: config.signed_result,
+            client_uuid: config.client_uuid.clone(),
+            git_hash: None,
+            legacy: false,
+            local: false,
+            source: SourceBinding {
+                interface: config.client_interface.clone(),
+                address: config.client_source_address,
+                family: AddressFamily::Any,
+            },
+            dualstack: false,
+        }
+    }
+}
+
+impl ClientConfig {
+    /// Thresholds are set, the check status line replaces the result table
+    pub fn is_check(&self) -> bool {
+        !self.thresholds.is_empty()
+    }
+
+    /// `tcp`, `tls`, `ws` or `wss`
+    pub fn transport(&self) -> &'static str {
+        match (self.use_tls, self.use_websocket) {
+            (false, false) => "tcp",
+            (true, false) => "tls",
+            (false, true) => "ws",
+            (true, true) => "wss",
+        }
+    }
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self::from_file_config(&FileConfig::default())
+    }
 }
 
-pub async fn client_run(args: Vec<String>, dafault_config: FileConfig) -> anyhow::Result<()> {
+/// Runs `nettest -c`. Returns the exit code: 0 for a complete measurement,
+/// 1 when no phase produced a value and 2 for a partial result.
+pub async fn client_run(args: Vec<String>, dafault_config: FileConfig) -> anyhow::Result<i32> {
+    // A check that fails before it measures, e.g. on a bad limit or without
+    // a server, still reports UNKNOWN to the monitoring system
+    let is_check = args.iter().any(|arg| Thresholds::FLAGS.contains(&arg.as_str()));
+    match run_client(args, dafault_config).await {
+        Err(e) if is_check => {
+            let check = Check::unknown(&e);
+            println!("{}", check.line);
+            Ok(check.state.exit_code())
+        }
+        result => result,
+    }
+}
+
+async fn run_client(args: Vec<String>, dafault_config: FileConfig) -> anyhow::Result<i32> {
     info!("Starting measurement client...");
 
     // Initialize MAX_CHUNK_SIZE from config
@@ -60,23 +213,204 @@ pub async fn client_run(args: Vec<String>, dafault_config: FileConfig) -> anyhow
 
     if args.contains(&"-h".to_string()) || args.contains(&"--help".to_string()) {
         print_help();
-        return Ok(());
+        return Ok(0);
     }
 
-    let config = parse_args(args, dafault_config).await?;
+    let mut config = parse_args(args, dafault_config).await?;
+    if config.list_servers {
+        print_server_list(&config, &list_servers(&config).await?);
+        return Ok(0);
+    }
+    let exporting = config.csv_file.is_some() || config.influx_file.is_some() || config.influx_url.is_some();
+    if exporting && config.client_uuid.is_none() {
+        // Tag exports with the same UUID the control server sees
+        config.client_uuid = MeasurementSaver::new(&config).ensure_client_uuid().ok();
+    }
 
-    if !config.raw_output {
-        print_test_header();
+    info!("Config: {:?}", config);
+
+    // Without a schedule the exporter measures when a scrape finds the last
+    // result older than `-exporter-max-age`
+    let exporter = config
+        .exporter
+        .map(|addr| {
+            let max_age = config.schedule.is_none().then_some(config.exporter_max_age);
+            Exporter::start(addr, max_age)
+                .map_err(|e| anyhow::anyhow!("Failed to listen for metrics on {}: {}", addr, e))
+        })
+        .transpose()?;
+    if let Some(exporter) = &exporter {
+        if !config.json_output {
+            println!("Serving metrics on http://{}/metrics", exporter.local_addr());
+        }
     }
 
-    let stats: Arc<Mutex<SharedStats>> = Arc::new(Mutex::new(SharedStats::default()));
+    match (config.schedule.clone(), exporter) {
+        (Some(schedule), exporter) => run_scheduled(&config, &schedule, exporter.as_ref()).await,
+        (None, Some(exporter)) => run_exporter(&config, &exporter).await,
+        (None, None) if config.is_check() => Ok(run_check(&config).await),
+        (None, None) if config.dualstack => run_dualstack(&config).await,
+        (None, None) => Ok(run_once(&config).await?.status.exit_code()),
+    }
+}
 
-    info!("Config: {:?}", config);
+/// A single measurement reported as a monitoring plugin, returns its exit code
+async fn run_check(config: &ClientConfig) -> i32 {
+    let check = match run_once(config).await {
+        Ok(result) => Check::evaluate(&config.thresholds, &result),
+        Err(e) => Check::unknown(&e),
+    };
+    println!("{}", check.line);
+    check.state.exit_code()
+}
 
-    let state_refs = run_threads(config.clone(), stats).await;
+/// `-dualstack`: the full measurement over IPv4, then over IPv6, followed by
+/// the comparison. The exit code is 1 when either failed, else the worse one.
+async fn run_dualstack(config: &ClientConfig) -> anyhow::Result<i32> {
+    let mut runs: Vec<FamilyRun> = Vec::new();
+    for family in [AddressFamily::V4, AddressFamily::V6] {
+        if !config.json_output && !config.raw_output {
+            println!("Measuring over {}", family.as_str());
+        }
+        let mut family_config = config.clone();
+        family_config.source.family = family;
+        family_config.csv_file = config.csv_file.as_deref().map(|path| csv::family_path(path, family));
+        let run = run_once(&family_config).await.map_err(|e| format!("{:#}", e));
+        if let Err(e) = &run {
+            if !config.json_output {
+                eprintln!("Measurement over {} failed: {}", family.as_str(), e);
+            }
+            if config.raw_output {
+                // Keeps one line per family
+                println!("-/-/-");
+            }
+        }
+        runs.push((family, run));
+    }
+
+    let exit_codes: Vec<i32> = runs
+        .iter()
+        .map(|(_, run)| run.as_ref().map_or(1, |result| result.status.exit_code()))
+        .collect();
+    let exit_code = if exit_codes.contains(&1) { 1 } else { exit_codes.into_iter().max().unwrap_or(0) };
+
+    if config.json_output {
+        println!("{}", serde_json::to_string_pretty(&comparison_to_json(config, &runs))?);
+    } else if config.raw_output {
+        println!("{}", raw_comparison(&runs));
+    } else {
+        print_comparison(&runs);
+    }
+    Ok(exit_code)
+}
+
+/// `-schedule`: measures until the process is stopped. A failed run is
+/// reported and the next one still starts on time.
+async fn run_scheduled(
+    config: &ClientConfig,
+    schedule: &Schedule,
+    exporter: Option<&Exporter>,
+) -> anyhow::Result<i32> {
+    let mut last_start = None;
+    loop {
+        let now = Local::now();
+        let Some(next) = schedule.next_start(last_start, now) else {
+            return Err(anyhow::anyhow!("Schedule {} has no further runs", schedule));
+        };
+        if next > now {
+            if !config.json_output {
+                println!("Next measurement at {}", next.format("%Y-%m-%d %H:%M:%S"));
+            }
+            tokio::time::sleep((next - now).to_std().unwrap_or_default()).await;
+        }
+        last_start = Some(next);
+        run_published(config, exporter).await;
+    }
+}
+
+/// `-exporter` without `-schedule`: measures once at startup, then whenever a
+/// scrape asks for a fresh result
+async fn run_exporter(config: &ClientConfig, exporter: &Exporter) -> anyhow::Result<i32> {
+    loop {
+        run_published(config, Some(exporter)).await;
+        exporter.refresh_requested().await;
+    }
+}
 
-    if config.graphs {
-        GraphService::print_graph(&state_refs.unwrap());
+/// Runs a measurement for a long-running client, failures are reported and
+/// do not end it
+async fn run_published(config: &ClientConfig, exporter: Option<&Exporter>) {
+    if let Some(exporter) = exporter {
+        exporter.begin();
+    }
+    match run_once(config).await {
+        Ok(result) => {
+            if config.is_check() {
+                println!("{}", Check::evaluate(&config.thresholds, &result).line);
+            }
+            if let Some(exporter) = exporter {
+                exporter.publish(config, &result);
+            }
+        }
+        Err(e) => {
+            eprintln!("Measurement failed: {:#}", e);
+            if let Some(exporter) = exporter {
+                exporter.publish_error();
+            }
+        }
+    }
+}
+
+/// Runs one measurement with its outputs, exports and history entry
+async fn run_once(config: &ClientConfig) -> anyhow::Result<MeasurementResult> {
+    let mut measurement = Measurement::new(config.clone());
+    measurement = if config.dualstack && config.json_output {
+        // Both results are printed as one document by `run_dualstack`
+        measurement
+    } else if config.json_output {
+        // Failed threads and everything else are part of the document
+        measurement.observer(Arc::new(JsonPrinter::new(config.clone())))
+    } else if config.is_check() {
+        // The status line is printed once the result is evaluated
+        measurement
+    } else if config.raw_output {
+        measurement.observer(Arc::new(RawPrinter))
+    } else {
+        measurement.observer(Arc::new(TablePrinter::new(config.phases)))
+    };
+    if config.graphs && !config.json_output {
+        measurement = measurement.observer(Arc::new(GraphService));
+    }
+    let result = measurement.run().await?;
+
+    if result.is_degraded() && !config.json_output && !config.is_check() {
+        println!(
+            "Measurement {}: {} of {} threads failed, {} retries",
+            result.status.as_str(),
+            result.failed_threads(),
+            result.thread_count,
+            result.retries
+        );
+    }
+    if let Some(e) = &result.save_error {
+        eprintln!("Failed to save measurement: {}", e);
+    }
+    if let Some(path) = &config.history_file {
+        if let Err(e) = history::append(path, &HistoryEntry::from_result(config, &result)) {
+            eprintln!("Failed to write history {}: {}", path.display(), e);
+        }
+    }
+
+    if let Some(path) = &config.csv_file {
+        csv::write_csv(path, config, &result)
+            .map_err(|e| anyhow::anyhow!("Failed to write CSV {}: {}", path.display(), e))?;
+    }
+    if let Some(path) = &config.influx_file {
+        influx::write_file(path, config, &result)
+            .map_err(|e| anyhow::anyhow!("Failed to write InfluxDB file {}: {}", path.display(), e))?;
+    }
+    if let Some(url) = &config.influx_url {
+        influx::post(url, config, &result).await?;
     }
-    Ok(())
+    Ok(result)
 }
@@ -1,5 +1,6 @@
 use lazy_static::lazy_static;
 use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
 
 /// Default buffer size for read operations
 pub const DEFAULT_READ_BUFFER_SIZE: usize = 1024 * 1024;
@@ -45,8 +46,8 @@ pub fn get_max_chunk_size() -> u32 {
     MAX_CHUNK_SIZE.load(Ordering::Relaxed)
 }
 
-/// Pre-download duration in nanoseconds (2 seconds)
-pub const PRE_DOWNLOAD_DURATION_NS: u64 = 2_000_000_000;
+/// Interval between throughput samples sent to observers, per thread
+pub const THROUGHPUT_SAMPLE_INTERVAL: Duration = Duration::from_millis(100);
 
 /// Maximum number of chunks before increasing chunk size
 pub const MAX_CHUNKS_BEFORE_SIZE_INCREASE: u32 = 8;
@@ -1,11 +1,11 @@
 use crate::client::client::{ClientConfig};
+use crate::client::setup::SetupSummary;
 use log::{warn, info};
 use serde_json::json;
 use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
 use std::fs;
-use std::path::PathBuf;
-use std::env;
+use crate::config::paths;
 
 #[derive(Debug, Clone, Copy)]
 pub enum ConnectionType {
@@ -64,7 +64,7 @@ impl MeasurementSaver {
             if crate::client::control_server::servers::is_ip_address(server_addr) {
                 Some(server_addr.clone())
             } else {
-                match crate::client::control_server::servers::resolve_ip_from_web_address(server_addr) {
+                match crate::client::control_server::servers::resolve_ip_from_web_address(server_addr, &self.client_config.source) {
                     Ok(ip) => Some(ip),
                     Err(_) => Some(server_addr.clone()), // Fallback to original if resolution fails
                 }
@@ -74,48 +74,40 @@ impl MeasurementSaver {
         }
     }
 
-    fn ensure_client_uuid(&mut self) -> Result<String, Box<dyn std::error::Error>> {
+    /// Configured UUID, else the one stored by a previous run, else a new one
+    /// that is stored for the next runs
+    pub fn ensure_client_uuid(&mut self) -> Result<String, Box<dyn std::error::Error>> {
         // If client_uuid already exists, return it
         if let Some(uuid) = &self.client_uuid {
             return Ok(uuid.clone());
         }
         
+        // Reuse the UUID stored by a previous run
+        let state_file = paths::user_state_dir().map(|dir| dir.join("client_uuid"));
+        if let Some(uuid) = state_file
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|content| content.trim().to_string())
+            .filter(|uuid| !uuid.is_empty())
+        {
+            self.client_uuid = Some(uuid.clone());
+            return Ok(uuid);
+        }
+
         // Generate new UUID
         let new_uuid = Uuid::new_v4().to_string();
-        
-        // Determine config file path
-        let config_path = if cfg!(target_os = "macos") {
-            let home_dir = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-            PathBuf::from(format!("{}/.config/nettest.conf", home_dir))
-        } else {
-            PathBuf::from("/etc/nettest.conf")
-        };
-        
-        // Read current file content
-        let mut content = if config_path.exists() {
-            fs::read_to_string(&config_path)?
-        } else {
-            String::new()
-        };
-        
-        // Check if client_uuid already exists (regardless of whether it's commented out)
-        let has_uuid = content.lines().any(|line| {
-            let line = line.trim();
-            line.starts_with("client_uuid") && !line.starts_with("#")
-        });
-        
-        // If client_uuid doesn't exist or is commented out, add it
-        if !has_uuid {
-            if !content.is_empty() && !content.ends_with('\n') {
-                content.push('\n');
+
+        match state_file {
+            Some(path) => {
+                if let Some(parent) = path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&path, format!("{}\n", new_uuid))?;
+                info!("Generated and saved new client UUID to {:?}: {}", path, new_uuid);
             }
-            content.push_str(&format!("client_uuid=\"{}\"\n", new_uuid));
-            
-            // Write updated content
-            fs::write(&config_path, content)?;
-            println!("Generated and saved new client UUID: {}", new_uuid);
+            None => warn!("No state directory available, client UUID {} is not persisted", new_uuid),
         }
-        
+
         // Update internal state
         self.client_uuid = Some(new_uuid.clone());
         
@@ -128,6 +120,7 @@ impl MeasurementSaver {
         download_speed_gbps: Option<f64>,
         upload_speed_gbps: Option<f64>,
         signed_data: Vec<Option<String>>,
+        setup: &SetupSummary,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Ensure client_uuid exists
         let client_uuid = self.ensure_client_uuid()?;
@@ -161,6 +154,7 @@ impl MeasurementSaver {
             "threadsNumber": self.threads_number,
             "signedData": if self.client_config.signed_result { Some(signed_data) } else { None },
             "measurementServerIp": self.resolve_server_ip(),
+            "connectionSetup": setup,
         });
 
         // Add commitHash only if git_hash exists in configuration
@@ -175,9 +169,10 @@ impl MeasurementSaver {
         info!("Saving measurement: {:?}", measurement_data);
 
         // Send POST request
-        let client = reqwest::Client::new();
+        let url = format!("{}/measurement/save", self.client_config.control_server);
+        let client = self.client_config.source.http_client(&url)?;
         let response = client
-            .post(&format!("{}/measurement/save", self.client_config.control_server))
+            .post(&url)
             .header("Content-Type", "application/json")
             .header("x-nettest-client", "nt")
             .json(&measurement_data)
@@ -1,8 +1,17 @@
 use log::{debug};
 use serde::{Deserialize, Serialize};
-use reqwest;
 use anyhow::Result;
-use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+use tokio::task::JoinSet;
+
+use crate::client::source::SourceBinding;
+
+/// Servers older than this don't speak the protocol of this client
+pub const MIN_SERVER_VERSION: &str = "2.0.0";
+/// Nearest servers probed by default before choosing one
+pub const DEFAULT_PROBE_COUNT: usize = 5;
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const PROBE_ATTEMPTS: usize = 3;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MeasurementServer {
@@ -36,6 +45,25 @@ pub struct MeasurementServer {
     pub on_net: bool,
 }
 
+impl MeasurementServer {
+    /// Host name, or the IP address when the server has none
+    pub fn address(&self) -> Option<String> {
+        if self.web_address.is_empty() {
+            self.ip_address.clone().filter(|ip| !ip.is_empty())
+        } else {
+            Some(self.web_address.clone())
+        }
+    }
+
+    /// RMBT `(port, tls_port)`
+    pub fn rmbt_ports(&self) -> Option<(u16, u16)> {
+        self.server_type_details
+            .iter()
+            .find(|s| s.server_type == "RMBT")
+            .map(|details| (details.port as u16, details.port_ssl as u16))
+    }
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Provider {
     pub id: i32,
@@ -72,17 +100,15 @@ pub fn is_ip_address(addr: &str) -> bool {
     addr.parse::<std::net::IpAddr>().is_ok()
 }
 
-/// Resolve IP address from web address using DNS
-pub fn resolve_ip_from_web_address(web_address: &str) -> Result<String> {
+/// Resolve IP address from web address using DNS, through `source` when bound
+pub fn resolve_ip_from_web_address(web_address: &str, source: &SourceBinding) -> Result<String> {
     // If it's already an IP address, return it as is
     if is_ip_address(web_address) {
         return Ok(web_address.to_string());
     }
     
     // If it's a hostname, resolve it to IP
-    let socket_addrs: Vec<std::net::SocketAddr> = format!("{}:80", web_address)
-        .to_socket_addrs()?
-        .collect();
+    let socket_addrs = source.resolve(web_address, 80)?;
     
     if let Some(socket_addr) = socket_addrs.first() {
         Ok(socket_addr.ip().to_string())
@@ -91,8 +117,12 @@ pub fn resolve_ip_from_web_address(web_address: &str) -> Result<String> {
     }
 }
 
-pub async fn fetch_measurement_servers(x_nettest_client: &str, control_server: &str) -> Result<Vec<MeasurementServer>> {
-    let client = reqwest::Client::new();
+pub async fn fetch_measurement_servers(
+    x_nettest_client: &str,
+    control_server: &str,
+    source: &SourceBinding,
+) -> Result<Vec<MeasurementServer>> {
+    let client = source.http_client(control_server)?;
     
     let response = client
         .get(format!("{}/measurementServer", control_server))
@@ -106,43 +136,195 @@ pub async fn fetch_measurement_servers(x_nettest_client: &str, control_server: &
     Ok(servers)
 }
 
+fn version_at_least(server: &MeasurementServer, min_version: &str) -> bool {
+    if let Some(version) = &server.version {
+        // Parse version string and compare
+        if let Ok(server_version) = semver::Version::parse(version) {
+            if let Ok(min_ver) = semver::Version::parse(min_version) {
+                return server_version >= min_ver;
+            }
+        }
+    }
+    false
+}
+
 pub fn filter_servers_by_version(servers: Vec<MeasurementServer>, min_version: &str) -> Vec<MeasurementServer> {
     servers
         .into_iter()
-        .filter(|server| {
-            if let Some(version) = &server.version {
-                // Parse version string and compare
-                if let Ok(server_version) = semver::Version::parse(version) {
-                    if let Ok(min_ver) = semver::Version::parse(min_version) {
-                        return server_version >= min_ver;
-                    }
-                }
-            }
-            false
-        })
+        .filter(|server| version_at_least(server, min_version))
         .collect()
 }
 
+/// `-on-net`, `-dedicated`, `-ipv6`, `-country`, `-provider` and `-min-version`;
+/// `-4`, `-6` and `-dualstack` require the families they measure over
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerFilter {
+    pub on_net: bool,
+    pub dedicated: bool,
+    pub ipv4: bool,
+    pub ipv6: bool,
+    /// Country code of the provider or one the server serves, e.g. `AT`
+    pub country: Option<String>,
+    /// Part of the provider name, case-insensitive
+    pub provider: Option<String>,
+    pub min_version: String,
+}
+
+impl Default for ServerFilter {
+    fn default() -> Self {
+        Self {
+            on_net: false,
+            dedicated: false,
+            ipv4: false,
+            ipv6: false,
+            country: None,
+            provider: None,
+            min_version: MIN_SERVER_VERSION.to_string(),
+        }
+    }
+}
+
+impl ServerFilter {
+    pub fn matches(&self, server: &MeasurementServer) -> bool {
+        let provider = server.provider.as_ref();
+        let country = self.country.as_ref().is_none_or(|country| {
+            provider.is_some_and(|provider| provider.country.eq_ignore_ascii_case(country))
+                || server
+                    .countries
+                    .iter()
+                    .flatten()
+                    .any(|served| served.eq_ignore_ascii_case(country))
+        });
+        let provider_name = self.provider.as_ref().is_none_or(|name| {
+            provider.is_some_and(|provider| provider.name.to_lowercase().contains(&name.to_lowercase()))
+        });
+        (!self.on_net || server.on_net)
+            && (!self.dedicated || server.dedicated)
+            && (!self.ipv4 || server.ip_v4_support)
+            && (!self.ipv6 || server.ip_v6_support)
+            && country
+            && provider_name
+            && version_at_least(server, &self.min_version)
+    }
+}
+
+/// Result of probing a server, ordered from best to worst
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Probe {
+    /// Fastest TCP connect to the RMBT port
+    Rtt(Duration),
+    /// Beyond the probed servers, only the distance is known
+    NotProbed,
+    Unreachable,
+}
+
+#[derive(Debug)]
+pub struct ProbedServer {
+    pub server: MeasurementServer,
+    pub probe: Probe,
+}
+
+/// Connects to the RMBT port of the `count` nearest servers and orders all
+/// servers by connect time, then distance. The TLS port is probed for `-tls`.
+pub async fn probe_servers(
+    mut servers: Vec<MeasurementServer>,
+    count: usize,
+    use_tls: bool,
+    source: &SourceBinding,
+) -> Vec<ProbedServer> {
+    servers.sort_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal));
+    let mut probes = JoinSet::new();
+    for (index, server) in servers.iter().enumerate().take(count) {
+        let target = server.address().zip(server.rmbt_ports()).map(|(address, (port, tls_port))| {
+            (address, if use_tls { tls_port } else { port })
+        });
+        let source = source.clone();
+        probes.spawn(async move {
+            let Some(target) = target else {
+                return (index, Probe::Unreachable);
+            };
+            (index, probe_server(target, source).await)
+        });
+    }
+    let mut results = vec![Probe::NotProbed; servers.len()];
+    while let Some(Ok((index, probe))) = probes.join_next().await {
+        results[index] = probe;
+    }
+
+    let mut probed: Vec<ProbedServer> = servers
+        .into_iter()
+        .zip(results)
+        .map(|(server, probe)| ProbedServer { server, probe })
+        .collect();
+    // Stable, equal probes stay ordered by distance
+    probed.sort_by_key(|server| server.probe);
+    probed
+}
+
+/// Fastest of a few TCP connects, name resolution is not included
+async fn probe_server((host, port): (String, u16), source: SourceBinding) -> Probe {
+    let lookup_source = source.clone();
+    let lookup_host = host.clone();
+    let addr = match tokio::task::spawn_blocking(move || lookup_source.resolve(&lookup_host, port)).await {
+        Ok(Ok(addrs)) if !addrs.is_empty() => addrs[0],
+        result => {
+            debug!("Resolving {} failed: {:?}", host, result.map(|r| r.err()));
+            return Probe::Unreachable;
+        }
+    };
+    let mut best: Option<Duration> = None;
+    for _ in 0..PROBE_ATTEMPTS {
+        let started = Instant::now();
+        match tokio::time::timeout(PROBE_TIMEOUT, source.connect_async(addr)).await {
+            Ok(Ok(_)) => {
+                let rtt = started.elapsed();
+                best = Some(best.map_or(rtt, |best| best.min(rtt)));
+            }
+            result => {
+                debug!("Probing {} failed: {:?}", addr, result.map(|r| r.err()));
+                break;
+            }
+        }
+    }
+    best.map_or(Probe::Unreachable, Probe::Rtt)
+}
+
 pub fn find_nearest_server(servers: Vec<MeasurementServer>) -> Option<MeasurementServer> {
     servers
         .into_iter()
         .min_by(|a, b| a.distance.partial_cmp(&b.distance).unwrap_or(std::cmp::Ordering::Equal))
 }
 
-pub async fn get_best_measurement_server(x_nettest_client: &str, control_server: &str) -> Result<Option<MeasurementServer>> {
+/// Server matching `filter` with the fastest connect among the `probe_count`
+/// nearest ones, or simply the nearest one when `probe_count` is 0
+pub async fn get_best_measurement_server(
+    x_nettest_client: &str,
+    control_server: &str,
+    filter: &ServerFilter,
+    probe_count: usize,
+    use_tls: bool,
+    source: &SourceBinding,
+) -> Result<Option<MeasurementServer>> {
     // Fetch all servers
-    let servers = fetch_measurement_servers(x_nettest_client, control_server).await?;
+    let servers = fetch_measurement_servers(x_nettest_client, control_server, source).await?;
     
-    // Filter by version > 2.0.0
-    let filtered_servers = filter_servers_by_version(servers, "2.0.0");
+    let filtered_servers: Vec<MeasurementServer> = servers.into_iter().filter(|server| filter.matches(server)).collect();
     
-    // Find nearest server
-    let mut nearest = find_nearest_server(filtered_servers);
+    // Find the fastest or nearest server
+    let mut nearest = if probe_count == 0 {
+        find_nearest_server(filtered_servers)
+    } else {
+        probe_servers(filtered_servers, probe_count, use_tls, source)
+            .await
+            .into_iter()
+            .next()
+            .map(|probed| probed.server)
+    };
     
     // If we found a server and it has empty IP, resolve it from web_address
     if let Some(ref mut server) = nearest {
         if server.ip_address.is_none() || server.ip_address.as_ref().map(|ip| ip.is_empty()).unwrap_or(true) {
-            if let Ok(ip) = resolve_ip_from_web_address(&server.web_address) {
+            if let Ok(ip) = resolve_ip_from_web_address(&server.web_address, source) {
                 server.ip_address = Some(ip);
             }
         }
@@ -268,4 +450,76 @@ mod tests {
         assert!(nearest.is_some());
         assert_eq!(nearest.unwrap().name, "Near Server");
     }
-} 
\ No newline at end of file
+
+    fn server(name: &str, distance: f64, port: u16) -> MeasurementServer {
+        MeasurementServer {
+            id: 0,
+            uuid: None,
+            name: name.to_string(),
+            web_address: "127.0.0.1".to_string(),
+            provider: Some(Provider {
+                id: 1,
+                name: "Example Telecom".to_string(),
+                country: "AT".to_string(),
+                mno_active: false,
+                isp_active: true,
+                created_date: String::new(),
+                modified_date: String::new(),
+            }),
+            secret_key: String::new(),
+            city: "Vienna".to_string(),
+            email: None,
+            company: None,
+            expiration: None,
+            ip_address: None,
+            comment: None,
+            countries: Some(vec!["DE".to_string()]),
+            location: Location { latitude: 0.0, longitude: 0.0 },
+            distance,
+            server_type_details: vec![ServerTypeDetail {
+                server_type: "RMBT".to_string(),
+                port: port as i32,
+                port_ssl: 443,
+                encrypted: false,
+            }],
+            dedicated: true,
+            ip_v4_support: true,
+            ip_v6_support: false,
+            version: Some("2.0.3".to_string()),
+            on_net: false,
+        }
+    }
+
+    #[test]
+    fn test_server_filter() {
+        let candidate = server("Candidate", 10.0, 5005);
+        assert!(ServerFilter::default().matches(&candidate));
+        let filter = |filter: ServerFilter| filter.matches(&candidate);
+        assert!(filter(ServerFilter { country: Some("at".to_string()), dedicated: true, ..Default::default() }));
+        assert!(filter(ServerFilter { country: Some("DE".to_string()), ..Default::default() }));
+        assert!(filter(ServerFilter { provider: Some("telecom".to_string()), ..Default::default() }));
+        assert!(!filter(ServerFilter { country: Some("CH".to_string()), ..Default::default() }));
+        assert!(!filter(ServerFilter { on_net: true, ..Default::default() }));
+        assert!(!filter(ServerFilter { ipv6: true, ..Default::default() }));
+        assert!(!filter(ServerFilter { min_version: "2.1.0".to_string(), ..Default::default() }));
+    }
+
+    #[tokio::test]
+    async fn test_probe_servers() {
+        // The nearest server refuses connections, the second one answers
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let open_port = listener.local_addr().unwrap().port();
+        let closed_port = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let servers = vec![
+            server("Far", 300.0, open_port),
+            server("Near", 10.0, closed_port),
+            server("Second", 20.0, open_port),
+        ];
+        let probed = probe_servers(servers, 2, false, &SourceBinding::default()).await;
+        let order: Vec<&str> = probed.iter().map(|probed| probed.server.name.as_str()).collect();
+        assert_eq!(order, ["Second", "Far", "Near"]);
+        assert!(matches!(probed[0].probe, Probe::Rtt(_)));
+        assert_eq!(probed[1].probe, Probe::NotProbed);
+        assert_eq!(probed[2].probe, Probe::Unreachable);
+    }
+}
@@ -0,0 +1,334 @@
+//! Stub resolver for `-I` / `-B`. The system resolver can't be told which
+//! interface to use, so with a source binding A and AAAA queries go to the
+//! nameservers of `/etc/resolv.conf`, from a socket bound like the
+//! measurement connections, over UDP and over TCP for truncated answers.
+//! `/etc/hosts` is consulted first, `search` and `ndots` are honoured.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+use log::debug;
+use socket2::Type;
+
+use crate::client::source::SourceBinding;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+const MAX_PACKET: usize = 1232;
+/// systemd-resolved lists its upstream nameservers here, `/etc/resolv.conf`
+/// only names its stub on 127.0.0.53
+const RESOLVED_CONF: &str = "/run/systemd/resolve/resolv.conf";
+
+/// IPv4 addresses of `host` first, then IPv6
+pub fn lookup(host: &str, source: &SourceBinding) -> io::Result<Vec<IpAddr>> {
+    let hosts = fs::read_to_string("/etc/hosts").unwrap_or_default();
+    let addresses = hosts_lookup(&hosts, host.trim_end_matches('.'));
+    if !addresses.is_empty() {
+        return Ok(addresses);
+    }
+
+    let conf = ResolvConf::parse(&fs::read_to_string("/etc/resolv.conf").unwrap_or_default());
+    let mut nameservers = conf.nameservers.clone();
+    if source.interface.is_some() {
+        // A socket bound to an interface can't reach a nameserver on loopback,
+        // such as the stub of systemd-resolved; ask the servers behind it
+        if nameservers.iter().all(|nameserver| nameserver.ip().is_loopback()) {
+            nameservers = ResolvConf::parse(&fs::read_to_string(RESOLVED_CONF).unwrap_or_default()).nameservers;
+        }
+        nameservers.retain(|nameserver| !nameserver.ip().is_loopback());
+    }
+    if nameservers.is_empty() {
+        debug!("No nameserver to query through the source binding, using the system resolver for {}", host);
+        return system_lookup(host);
+    }
+
+    let mut last_error = None;
+    for name in conf.candidates(host) {
+        for nameserver in &nameservers {
+            let mut addresses = Vec::new();
+            let mut answered = true;
+            for qtype in [TYPE_A, TYPE_AAAA] {
+                match query(*nameserver, &name, qtype, source) {
+                    Ok(found) => addresses.extend(found),
+                    Err(e) => {
+                        debug!("DNS query for {} to {} failed: {}", name, nameserver, e);
+                        answered = false;
+                        last_error = Some(e);
+                    }
+                }
+            }
+            if !addresses.is_empty() {
+                return Ok(addresses);
+            }
+            // The name does not exist, the next nameserver won't know better
+            if answered {
+                break;
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("{} has no address", host))
+    }))
+}
+
+/// Lookup that is not bound to the source, IPv4 addresses first
+fn system_lookup(host: &str) -> io::Result<Vec<IpAddr>> {
+    let mut addresses: Vec<IpAddr> = (host, 0).to_socket_addrs()?.map(|address| address.ip()).collect();
+    addresses.sort_by_key(|address| address.is_ipv6());
+    addresses.dedup();
+    Ok(addresses)
+}
+
+fn query(nameserver: SocketAddr, host: &str, qtype: u16, source: &SourceBinding) -> io::Result<Vec<IpAddr>> {
+    let socket = UdpSocket::from(source.socket(&nameserver, Type::DGRAM)?);
+    socket.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    socket.connect(nameserver)?;
+    let id = fastrand::u16(..);
+    let request = encode_query(id, host, qtype)?;
+    socket.send(&request)?;
+
+    let mut packet = [0u8; MAX_PACKET];
+    loop {
+        let n = socket.recv(&mut packet)?;
+        // Stray answers to earlier queries
+        if n >= 2 && u16::from_be_bytes([packet[0], packet[1]]) != id {
+            continue;
+        }
+        if is_truncated(&packet[..n]) {
+            debug!("Truncated DNS answer for {} from {}, asking over TCP", host, nameserver);
+            return query_tcp(nameserver, &request, qtype, source);
+        }
+        return decode_response(&packet[..n], qtype);
+    }
+}
+
+/// Repeats `request` over TCP, where answers are prefixed by their length
+fn query_tcp(nameserver: SocketAddr, request: &[u8], qtype: u16, source: &SourceBinding) -> io::Result<Vec<IpAddr>> {
+    let socket = source.socket(&nameserver, Type::STREAM)?;
+    socket.connect_timeout(&nameserver.into(), QUERY_TIMEOUT)?;
+    let mut stream = TcpStream::from(socket);
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT))?;
+
+    let mut message = (request.len() as u16).to_be_bytes().to_vec();
+    message.extend_from_slice(request);
+    stream.write_all(&message)?;
+    let mut length = [0u8; 2];
+    stream.read_exact(&mut length)?;
+    let mut packet = vec![0u8; u16::from_be_bytes(length) as usize];
+    stream.read_exact(&mut packet)?;
+    if packet.get(..2) != request.get(..2) {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "DNS answer to another query"));
+    }
+    decode_response(&packet, qtype)
+}
+
+/// The settings of `resolv.conf` the stub resolver uses
+#[derive(Debug, PartialEq)]
+struct ResolvConf {
+    nameservers: Vec<SocketAddr>,
+    search: Vec<String>,
+    /// Names with at least this many dots are tried as they are first
+    ndots: usize,
+}
+
+impl ResolvConf {
+    fn parse(text: &str) -> Self {
+        let mut conf = Self {
+            nameservers: Vec::new(),
+            search: Vec::new(),
+            ndots: 1,
+        };
+        for line in text.lines() {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                // Scoped IPv6 nameservers such as fe80::1%eth0 are left out
+                Some("nameserver") => conf.nameservers.extend(
+                    fields
+                        .next()
+                        .and_then(|address| address.parse::<IpAddr>().ok())
+                        .map(|ip| SocketAddr::new(ip, 53)),
+                ),
+                // The last `search` or `domain` line wins
+                Some("search") | Some("domain") => {
+                    conf.search = fields.map(|domain| domain.trim_end_matches('.').to_string()).collect()
+                }
+                Some("options") => {
+                    for option in fields {
+                        if let Some(ndots) = option.strip_prefix("ndots:").and_then(|n| n.parse().ok()) {
+                            conf.ndots = ndots;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        conf
+    }
+
+    /// Names to query for `host`, in order. A trailing dot makes the name
+    /// absolute, names with fewer than `ndots` dots try the search list first.
+    fn candidates(&self, host: &str) -> Vec<String> {
+        if let Some(absolute) = host.strip_suffix('.') {
+            return vec![absolute.to_string()];
+        }
+        let searched = self.search.iter().map(|domain| format!("{}.{}", host, domain));
+        if host.matches('.').count() >= self.ndots {
+            std::iter::once(host.to_string()).chain(searched).collect()
+        } else {
+            searched.chain(std::iter::once(host.to_string())).collect()
+        }
+    }
+}
+
+fn hosts_lookup(hosts: &str, host: &str) -> Vec<IpAddr> {
+    let mut addresses: Vec<IpAddr> = hosts
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let ip = fields.next()?.parse().ok()?;
+            fields.any(|name| name.eq_ignore_ascii_case(host)).then_some(ip)
+        })
+        .collect();
+    addresses.sort_by_key(|address: &IpAddr| address.is_ipv6());
+    addresses
+}
+
+fn encode_query(id: u16, host: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut packet = Vec::with_capacity(host.len() + 18);
+    packet.extend_from_slice(&id.to_be_bytes());
+    // Recursion desired, one question
+    packet.extend_from_slice(&[0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid host name {}", host)));
+        }
+        packet.push(label.len() as u8);
+        packet.extend_from_slice(label.as_bytes());
+    }
+    packet.push(0);
+    packet.extend_from_slice(&qtype.to_be_bytes());
+    packet.extend_from_slice(&1u16.to_be_bytes());
+    Ok(packet)
+}
+
+/// TC bit: the answer did not fit into the UDP packet
+fn is_truncated(packet: &[u8]) -> bool {
+    packet.get(2).is_some_and(|flags| flags & 0x02 != 0)
+}
+
+/// Addresses of type `qtype` in the answer section; CNAMEs are followed by
+/// the nameserver
+fn decode_response(packet: &[u8], qtype: u16) -> io::Result<Vec<IpAddr>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    let read_u16 = |pos: usize| -> io::Result<u16> {
+        packet
+            .get(pos..pos + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(invalid)
+    };
+    let flags = read_u16(2)?;
+    if flags & 0x8000 == 0 {
+        return Err(invalid());
+    }
+    match flags & 0x000f {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        rcode => return Err(io::Error::other(format!("DNS error, rcode {}", rcode))),
+    }
+    let questions = read_u16(4)?;
+    let answers = read_u16(6)?;
+
+    let mut pos = 12;
+    for _ in 0..questions {
+        pos = skip_name(packet, pos).ok_or_else(invalid)? + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        pos = skip_name(packet, pos).ok_or_else(invalid)?;
+        let rtype = read_u16(pos)?;
+        let length = read_u16(pos + 8)? as usize;
+        let data = packet.get(pos + 10..pos + 10 + length).ok_or_else(invalid)?;
+        match (rtype, data.len()) {
+            (TYPE_A, 4) if qtype == TYPE_A => {
+                addresses.push(IpAddr::V4(Ipv4Addr::new(data[0], data[1], data[2], data[3])))
+            }
+            (TYPE_AAAA, 16) if qtype == TYPE_AAAA => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| invalid())?;
+                addresses.push(IpAddr::V6(Ipv6Addr::from(octets)))
+            }
+            _ => {}
+        }
+        pos += 10 + length;
+    }
+    Ok(addresses)
+}
+
+/// Position after the (possibly compressed) name at `pos`
+fn skip_name(packet: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let length = *packet.get(pos)?;
+        match length {
+            0 => return Some(pos + 1),
+            // Compression pointer, ends the name
+            _ if length & 0xc0 == 0xc0 => return Some(pos + 2),
+            _ => pos += 1 + length as usize,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_query() {
+        let query = encode_query(0x1234, "probe.example.com", TYPE_A).unwrap();
+        assert_eq!(&query[..4], &[0x12, 0x34, 0x01, 0x00]);
+        assert_eq!(&query[12..19], b"\x05probe\x07");
+        assert!(encode_query(1, "bad..name", TYPE_A).is_err());
+    }
+
+    #[test]
+    fn test_decode_response() {
+        let query = encode_query(0x1234, "probe.example.com", TYPE_A).unwrap();
+
+        // Answer: a CNAME pointing to the question name, then the A record
+        let mut response = query.clone();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = 2;
+        response.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 12]);
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 7]);
+        assert_eq!(decode_response(&response, TYPE_A).unwrap(), [IpAddr::from([192, 0, 2, 7])]);
+        assert!(decode_response(&response, TYPE_AAAA).unwrap().is_empty());
+        assert!(decode_response(&response[..response.len() - 2], TYPE_A).is_err());
+
+        response[2] |= 0x02;
+        assert!(is_truncated(&response));
+    }
+
+    #[test]
+    fn test_hosts_lookup() {
+        let hosts = "127.0.0.1 localhost\n::1 localhost ip6-localhost # loopback\n192.0.2.9 probe\n";
+        assert_eq!(hosts_lookup(hosts, "localhost"), ["127.0.0.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]);
+        assert!(hosts_lookup(hosts, "other").is_empty());
+    }
+
+    #[test]
+    fn test_resolv_conf_search_and_ndots() {
+        let conf = ResolvConf::parse(
+            "# generated\nnameserver 192.0.2.53\nnameserver fe80::1%eth0\nsearch corp.example lan.\noptions edns0 ndots:2\n",
+        );
+        assert_eq!(conf.nameservers, ["192.0.2.53:53".parse().unwrap()]);
+        assert_eq!(conf.ndots, 2);
+        assert_eq!(conf.candidates("probe"), ["probe.corp.example", "probe.lan", "probe"]);
+        assert_eq!(conf.candidates("probe.example.com"), ["probe.example.com", "probe.example.com.corp.example", "probe.example.com.lan"]);
+        assert_eq!(conf.candidates("probe."), ["probe"]);
+        assert_eq!(ResolvConf::parse("nameserver 127.0.0.53\n").candidates("probe.example.com"), ["probe.example.com"]);
+    }
+}
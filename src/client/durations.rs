@@ -0,0 +1,150 @@
+use std::time::Duration;
+
+/// Added to a phase duration to get the time after which a silent connection
+/// is considered failed
+const TIMEOUT_MARGIN: Duration = Duration::from_secs(5);
+
+/// Greeting and signed result don't depend on the measurement durations
+pub const GREETING_TIMEOUT: Duration = Duration::from_secs(50);
+pub const SIGNED_RESULT_TIMEOUT: Duration = Duration::from_secs(12);
+
+/// Longest duration accepted for a phase
+pub const MAX_PHASE_DURATION: Duration = Duration::from_secs(3600);
+
+/// How long each phase of a measurement runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseDurations {
+    /// GETCHUNKS rounds stop growing the chunk size once a round takes this long
+    pub pre_download: Duration,
+    pub ping: Duration,
+    /// Pings end after `ping` or this many samples, whichever comes first
+    pub ping_count: u32,
+    /// GETTIME duration, sent to the server in whole seconds
+    pub download: Duration,
+    pub upload: Duration,
+    /// Start of download and upload left out of the speed calculation
+    pub warmup: Duration,
+}
+
+impl Default for PhaseDurations {
+    fn default() -> Self {
+        Self {
+            pre_download: Duration::from_secs(2),
+            ping: Duration::from_secs(1),
+            ping_count: 200,
+            download: Duration::from_secs(7),
+            upload: Duration::from_secs(7),
+            warmup: Duration::from_secs(1),
+        }
+    }
+}
+
+impl PhaseDurations {
+    /// Rejects combinations the server or the speed calculation can't handle
+    pub fn validate(&self) -> Result<(), String> {
+        let longest = [self.pre_download, self.ping, self.download, self.upload, self.warmup]
+            .into_iter()
+            .max()
+            .unwrap_or_default();
+        if longest > MAX_PHASE_DURATION {
+            return Err(format!(
+                "durations must not exceed {} seconds",
+                MAX_PHASE_DURATION.as_secs()
+            ));
+        }
+        if self.download.as_secs() == 0 || self.download.subsec_nanos() != 0 {
+            return Err("download duration must be a whole number of seconds".to_string());
+        }
+        if self.upload.is_zero() {
+            return Err("upload duration must be greater than 0".to_string());
+        }
+        if self.ping_count == 0 {
+            return Err("ping count must be greater than 0".to_string());
+        }
+        if self.warmup >= self.download || self.warmup >= self.upload {
+            return Err("warm-up must be shorter than download and upload".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn pre_download_timeout(&self) -> Duration {
+        with_margin(self.pre_download)
+    }
+
+    pub fn ping_timeout(&self) -> Duration {
+        with_margin(self.ping)
+    }
+
+    pub fn download_timeout(&self) -> Duration {
+        with_margin(self.download)
+    }
+
+    pub fn upload_timeout(&self) -> Duration {
+        with_margin(self.upload)
+    }
+}
+
+fn with_margin(duration: Duration) -> Duration {
+    duration.checked_add(TIMEOUT_MARGIN).unwrap_or(Duration::MAX)
+}
+
+/// Parses seconds such as `7`, `0.5` or `500ms`
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let parsed = match value.strip_suffix("ms") {
+        Some(ms) => ms.parse::<u64>().ok().map(Duration::from_millis),
+        None => value
+            .strip_suffix('s')
+            .unwrap_or(value)
+            .parse::<f64>()
+            .ok()
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok()),
+    };
+    parsed.ok_or_else(|| format!("invalid duration '{}', expected seconds like 7, 0.5 or 500ms", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("0.5").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("250ms").unwrap(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_parse_duration_invalid() {
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("soon").is_err());
+        assert!(parse_duration("1e20").is_err());
+        assert!(parse_duration("inf").is_err());
+    }
+
+    #[test]
+    fn test_download_timeout() {
+        let durations = PhaseDurations {
+            download: Duration::from_secs(30),
+            ..PhaseDurations::default()
+        };
+        assert_eq!(durations.download_timeout(), Duration::from_secs(35));
+        assert!(durations.validate().is_ok());
+        assert_eq!(with_margin(Duration::MAX), Duration::MAX);
+    }
+
+    #[test]
+    fn test_validate_whole_seconds_and_cap() {
+        let fractional = PhaseDurations {
+            download: Duration::from_millis(2500),
+            ..PhaseDurations::default()
+        };
+        assert!(fractional.validate().is_err());
+
+        let endless = PhaseDurations {
+            upload: Duration::from_secs(1_000_000),
+            ..PhaseDurations::default()
+        };
+        assert!(endless.validate().is_err());
+    }
+}
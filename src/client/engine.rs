@@ -0,0 +1,312 @@
+//! Event-loop client engine. One mio `Poll` drives any number of measurement
+//! connections, each a `MeasurementState` driven by the phase handlers, so
+//! high connection counts don't need an OS thread each. `run_threads` spreads the connections
+//! over `-loops` of these loops and keeps the loops in step.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use anyhow::Result;
+use log::info;
+use mio::{Events, Poll, Token};
+
+use crate::client::client::ClientConfig;
+use crate::client::observer::{MeasurementObserver, MeasurementPhase};
+use crate::client::state::{MeasurementState, ProgressReporter, TestPhase};
+
+/// Connection that failed; it was closed and removed from its loop
+#[derive(Debug)]
+pub struct ConnectionFailure {
+    pub thread_id: usize,
+    /// Protocol state the connection was in
+    pub phase: TestPhase,
+    pub error: String,
+}
+
+struct Connection {
+    state: MeasurementState,
+    progress: ProgressReporter,
+    /// State that completes the phase in progress, `None` while idle
+    target: Option<TestPhase>,
+}
+
+/// Measurement connections sharing one mio event loop, keyed by thread id
+pub struct EventLoop {
+    poll: Poll,
+    events: Events,
+    connections: BTreeMap<usize, Connection>,
+}
+
+impl EventLoop {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            poll: Poll::new()?,
+            events: Events::with_capacity(2048),
+            connections: BTreeMap::new(),
+        })
+    }
+
+    /// Opens connection `thread_id`. Its greeting runs with the next
+    /// `run_phase(MeasurementPhase::Connect)`.
+    pub fn connect(
+        &mut self,
+        thread_id: usize,
+        addr: SocketAddr,
+        config: &ClientConfig,
+        observer: Option<Arc<dyn MeasurementObserver>>,
+        chunk_size: Option<usize>,
+    ) -> Result<(), ConnectionFailure> {
+        let mut state = MeasurementState::connect(
+            addr,
+            &config.source,
+            config.use_tls,
+            config.use_websocket,
+            Token(thread_id),
+            &self.poll,
+            None,
+            None,
+        )
+        .map_err(|e| ConnectionFailure {
+            thread_id,
+            phase: TestPhase::GreetingSendConnectionType,
+            error: e.to_string(),
+        })?;
+        state.durations = config.durations;
+        if let Some(chunk_size) = chunk_size {
+            state.chunk_size = chunk_size;
+        }
+        self.connections.insert(
+            thread_id,
+            Connection {
+                state,
+                progress: ProgressReporter::new(observer),
+                target: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Thread ids of the open connections, ascending
+    pub fn thread_ids(&self) -> Vec<usize> {
+        self.connections.keys().copied().collect()
+    }
+
+    pub fn contains(&self, thread_id: usize) -> bool {
+        self.connections.contains_key(&thread_id)
+    }
+
+    pub fn state(&self, thread_id: usize) -> Option<&MeasurementState> {
+        self.connections.get(&thread_id).map(|connection| &connection.state)
+    }
+
+    /// Runs `phase` on the given connections at the same time, until each of
+    /// them completed it or failed. Failed connections are closed and returned.
+    pub fn run_phase(
+        &mut self,
+        thread_ids: &[usize],
+        phase: MeasurementPhase,
+        legacy: bool,
+    ) -> Vec<ConnectionFailure> {
+        let mut failed = Vec::new();
+        for thread_id in thread_ids {
+            let Some(connection) = self.connections.get_mut(thread_id) else {
+                continue;
+            };
+            match connection.state.begin(&self.poll, phase, legacy) {
+                Ok(target) => {
+                    connection.target = Some(target);
+                    connection.progress.restart();
+                }
+                Err(e) => failed.push((*thread_id, e.to_string())),
+            }
+        }
+        let mut failures = self.close_all(failed);
+
+        loop {
+            let mut deadline: Option<Instant> = None;
+            for connection in self.connections.values_mut() {
+                if connection.target.is_some() {
+                    connection.progress.report(&connection.state);
+                    deadline = match (deadline, connection.state.phase_deadline) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                }
+            }
+            if !self.connections.values().any(|connection| connection.target.is_some()) {
+                break;
+            }
+
+            let timeout = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            if let Err(e) = self.poll.poll(&mut self.events, timeout) {
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                let pending = self.pending().into_iter().map(|id| (id, e.to_string())).collect();
+                failures.extend(self.close_all(pending));
+                break;
+            }
+
+            let mut failed = Vec::new();
+
+            for event in self.events.iter() {
+                let thread_id = event.token().0;
+                let Some(connection) = self.connections.get_mut(&thread_id) else {
+                    continue;
+                };
+                if connection.target.is_none() || connection.state.failed {
+                    continue;
+                }
+                if let Err(e) = connection.state.handle_event(&self.poll, event) {
+                    failed.push((thread_id, e.to_string()));
+                } else if connection.target.as_ref() == Some(&connection.state.phase) {
+                    connection.target = None;
+                    connection.progress.report(&connection.state);
+                }
+            }
+
+            // Checked on every wakeup, a closed connection may keep reporting
+            // events without making progress
+            for (thread_id, connection) in &mut self.connections {
+                if connection.target.is_some() && !connection.state.failed && connection.state.timed_out() {
+                    info!(
+                        "Test duration exceeded {:?} for token {:?}",
+                        connection.state.phase, connection.state.token
+                    );
+                    connection.state.failed = true;
+                    failed.push((*thread_id, "Test duration exceeded".to_string()));
+                }
+            }
+            failures.extend(self.close_all(failed));
+        }
+        failures
+    }
+
+    /// Closes the connections still running a phase, after the phase was
+    /// aborted by a panic
+    pub fn abort_phase(&mut self, error: &str) -> Vec<ConnectionFailure> {
+        let pending = self.pending().into_iter().map(|id| (id, error.to_string())).collect();
+        self.close_all(pending)
+    }
+
+    fn pending(&self) -> Vec<usize> {
+        self.connections
+            .iter()
+            .filter(|(_, connection)| connection.target.is_some())
+            .map(|(thread_id, _)| *thread_id)
+            .collect()
+    }
+
+    fn close_all(&mut self, failed: Vec<(usize, String)>) -> Vec<ConnectionFailure> {
+        failed
+            .into_iter()
+            .filter_map(|(thread_id, error)| {
+                let connection = self.connections.remove(&thread_id)?;
+                Some(ConnectionFailure {
+                    thread_id,
+                    phase: connection.state.phase,
+                    error,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Event loops for `thread_count` connections: `configured`, or one per CPU
+/// core when 0, but never more loops than connections
+pub fn loop_count(configured: usize, thread_count: usize) -> usize {
+    let loops = if configured == 0 {
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    } else {
+        configured
+    };
+    loops.clamp(1, thread_count.max(1))
+}
+
+/// Thread ids of loop `index` out of `loops`, spread round-robin so the
+/// loops carry an equal share
+pub fn loop_thread_ids(index: usize, loops: usize, thread_count: usize) -> Vec<usize> {
+    (index..thread_count).step_by(loops.max(1)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    use crate::client::durations::PhaseDurations;
+    use crate::mioserver::ServerBuilder;
+
+    #[test]
+    fn test_loop_count() {
+        assert_eq!(loop_count(4, 64), 4);
+        assert_eq!(loop_count(8, 3), 3);
+        assert_eq!(loop_count(1, 0), 1);
+        assert!(loop_count(0, 64) >= 1);
+    }
+
+    #[test]
+    fn test_loop_thread_ids() {
+        assert_eq!(loop_thread_ids(0, 3, 8), vec![0, 3, 6]);
+        assert_eq!(loop_thread_ids(2, 3, 8), vec![2, 5]);
+        assert!(loop_thread_ids(3, 4, 2).is_empty());
+    }
+
+    #[test]
+    fn test_event_loop_drives_connections_against_local_server() {
+        // A server worker serves one connection at a time
+        let mut server = ServerBuilder::new()
+            .tcp_addresses(vec!["127.0.0.1:0".parse().unwrap()])
+            .workers(3)
+            .build()
+            .unwrap();
+        let addr = server.tcp_local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        let running = thread::spawn(move || server.run());
+        // Nothing listens here any more, its connect fails in the greeting
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let config = ClientConfig {
+            durations: PhaseDurations {
+                pre_download: Duration::from_millis(200),
+                ping_count: 5,
+                download: Duration::from_secs(1),
+                upload: Duration::from_secs(1),
+                warmup: Duration::from_millis(100),
+                ..PhaseDurations::default()
+            },
+            ..ClientConfig::default()
+        };
+        let mut event_loop = EventLoop::new().unwrap();
+        for thread_id in 0..3 {
+            event_loop.connect(thread_id, addr, &config, None, None).unwrap();
+        }
+        event_loop.connect(3, closed, &config, None, None).unwrap();
+
+        let failures = event_loop.run_phase(&[0, 1, 2, 3], MeasurementPhase::Connect, false);
+        assert_eq!(failures.len(), 1, "{:?}", failures);
+        assert_eq!(failures[0].thread_id, 3);
+        assert_eq!(event_loop.thread_ids(), vec![0, 1, 2]);
+
+        assert!(event_loop.run_phase(&[0, 1, 2], MeasurementPhase::PreDownload, false).is_empty());
+        assert!(event_loop.run_phase(&[0], MeasurementPhase::Ping, false).is_empty());
+        assert!(event_loop.run_phase(&[0, 1, 2], MeasurementPhase::Download, false).is_empty());
+        assert!(event_loop.run_phase(&[0, 1, 2], MeasurementPhase::Upload, false).is_empty());
+
+        assert!(!event_loop.state(0).unwrap().ping_times.is_empty());
+        for thread_id in [0, 1, 2] {
+            let state = event_loop.state(thread_id).unwrap();
+            assert_eq!(state.phase, TestPhase::PerfCompleted);
+            assert!(!state.download_measurements.is_empty());
+            assert!(!state.upload_measurements.is_empty());
+            assert!(state.bytes_received > 0 && state.bytes_sent > 0);
+        }
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::client::client::ClientConfig;
+use crate::client::export::thread_series;
+use crate::client::measurement::MeasurementResult;
+use crate::client::source::AddressFamily;
+
+/// Writes one `thread_id,phase,t_ns,bytes` row per sample to `path` and the
+/// summary to `summary_path(path)`
+pub fn write_csv(path: &Path, config: &ClientConfig, result: &MeasurementResult) -> io::Result<()> {
+    let mut samples = BufWriter::new(File::create(path)?);
+    writeln!(samples, "thread_id,phase,t_ns,bytes")?;
+    for thread in &result.threads {
+        for (phase, series) in thread_series(thread) {
+            for (t_ns, bytes) in series {
+                writeln!(
+                    samples,
+                    "{},{},{},{}",
+                    thread.thread_id,
+                    phase.as_str(),
+                    t_ns,
+                    bytes
+                )?;
+            }
+        }
+    }
+    samples.flush()?;
+
+    let mut summary = BufWriter::new(File::create(summary_path(path))?);
+    writeln!(
+        summary,
+        "started_at,server,address,transport,client_uuid,threads,failed_threads,ping_median_ns,download_bps,upload_bps"
+    )?;
+    writeln!(
+        summary,
+        "{},{},{},{},{},{},{},{},{},{}",
+        result
+            .started_at
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        field(&result.server_host),
+        result.server,
+        config.transport(),
+        field(config.client_uuid.as_deref().unwrap_or("")),
+        result.thread_count,
+        result.failed_threads(),
+        optional(result.ping_median_ns),
+        optional(result.download.map(|speed| speed.bps)),
+        optional(result.upload.map(|speed| speed.bps)),
+    )?;
+    summary.flush()
+}
+
+/// `results.csv` -> `results.summary.csv`
+pub fn summary_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.summary.csv", stem))
+}
+
+/// `out.csv` becomes `out.ipv4.csv`, so both runs of `-dualstack` are kept
+pub fn family_path(path: &Path, family: AddressFamily) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let family = family.as_str().to_lowercase();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{}.{}.{}", stem, family, extension.to_string_lossy())),
+        None => path.with_file_name(format!("{}.{}", stem, family)),
+    }
+}
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|value| value.to_string()).unwrap_or_default()
+}
+
+/// Quotes values containing separators, quotes or line breaks
+fn field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::ThreadMeasurement;
+    use crate::client::measurement::Speed;
+    use crate::client::setup::ConnectionSetup;
+    use crate::client::state::TestPhase;
+    use std::fs;
+
+    #[test]
+    fn test_write_csv() {
+        let dir = std::env::temp_dir().join(format!("nettest-csv-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("results.csv");
+
+        let config = ClientConfig {
+            client_uuid: Some("c0ffee".to_string()),
+            ..ClientConfig::default()
+        };
+        let result = MeasurementResult {
+            thread_count: 2,
+            ping_median_ns: Some(1_500_000),
+            download: Some(Speed::from((8e6, 0.008, 8.0))),
+            threads: vec![ThreadMeasurement {
+                measurements: vec![(1_000_000_000, 1_000_000), (2_000_000_000, 2_000_000)],
+                failed: false,
+                thread_id: 1,
+                phase: TestPhase::PerfCompleted,
+                upload_measurements: vec![(1_500_000_000, 500_000)],
+                envelope: None,
+                chunk_size: 4096,
+                setup: ConnectionSetup::default(),
+            }],
+            ..MeasurementResult::for_test("lab \"a\", rack 2", "192.0.2.1:5005")
+        };
+
+        write_csv(&path, &config, &result).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "thread_id,phase,t_ns,bytes\n\
+             1,download,1000000000,1000000\n\
+             1,download,2000000000,2000000\n\
+             1,upload,1500000000,500000\n"
+        );
+        let summary = fs::read_to_string(dir.join("results.summary.csv")).unwrap();
+        let lines: Vec<&str> = summary.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("started_at,server,address,"));
+        assert_eq!(
+            lines[1],
+            "2026-03-02T10:00:00.000Z,\"lab \"\"a\"\", rack 2\",192.0.2.1:5005,tcp,c0ffee,2,1,1500000,8000000,"
+        );
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_output_paths() {
+        let path = Path::new("/tmp/out.csv");
+        assert_eq!(summary_path(path), Path::new("/tmp/out.summary.csv"));
+        assert_eq!(family_path(path, AddressFamily::V4), Path::new("/tmp/out.ipv4.csv"));
+        assert_eq!(family_path(Path::new("out"), AddressFamily::V6), Path::new("out.ipv6"));
+    }
+
+    #[test]
+    fn test_field_quoting() {
+        assert_eq!(field("plain"), "plain");
+        assert_eq!(field("a,b"), "\"a,b\"");
+        assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(field("two\nlines"), "\"two\nlines\"");
+    }
+}
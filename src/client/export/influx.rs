@@ -0,0 +1,272 @@
+//! InfluxDB line protocol: one `nettest_result` point per measurement and
+//! `nettest_throughput` points for every full second of download and upload,
+//! tagged with server, transport, client UUID and the `-I` / `-B` interface.
+
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::client::client::ClientConfig;
+use crate::client::export::thread_series;
+use crate::client::measurement::MeasurementResult;
+use crate::client::observer::MeasurementPhase;
+
+const NS_PER_SECOND: u64 = 1_000_000_000;
+
+/// Appends the points to `path`, so one file can collect several runs
+pub fn write_file(
+    path: &Path,
+    config: &ClientConfig,
+    result: &MeasurementResult,
+) -> io::Result<()> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(to_line_protocol(config, result).as_bytes())
+}
+
+/// POSTs the points to an HTTP write endpoint, e.g.
+/// `http://localhost:8086/write?db=nettest` (InfluxDB 1.x) or
+/// `http://localhost:8086/api/v2/write?org=o&bucket=b&precision=ns` (2.x)
+pub async fn post(
+    url: &str,
+    config: &ClientConfig,
+    result: &MeasurementResult,
+) -> anyhow::Result<()> {
+    let response = config
+        .source
+        .http_client(url)?
+        .post(url)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(to_line_protocol(config, result))
+        .send()
+        .await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let body = response.text().await.unwrap_or_default();
+        return Err(anyhow::anyhow!(
+            "InfluxDB write to {} failed: {} {}",
+            url,
+            status,
+            body.trim()
+        ));
+    }
+    Ok(())
+}
+
+pub fn to_line_protocol(config: &ClientConfig, result: &MeasurementResult) -> String {
+    let mut tags = format!(
+        "server={},transport={}",
+        escape_tag(&result.server_host),
+        config.transport()
+    );
+    if let Some(uuid) = &config.client_uuid {
+        tags.push_str(&format!(",client_uuid={}", escape_tag(uuid)));
+    }
+    if let Some(interface) = &result.interface {
+        tags.push_str(&format!(",interface={}", escape_tag(interface)));
+    }
+    let started_ns = result.started_at.timestamp_nanos_opt().unwrap_or(0) as u64;
+
+    let mut fields = vec![
+        format!("threads={}i", result.thread_count),
+        format!("failed_threads={}i", result.failed_threads()),
+    ];
+    if let Some(ping) = result.ping_median_ns {
+        fields.push(format!("ping_median_ns={}i", ping));
+    }
+    if let Some(download) = result.download {
+        fields.push(format!("download_bps={}", download.bps));
+    }
+    if let Some(upload) = result.upload {
+        fields.push(format!("upload_bps={}", upload.bps));
+    }
+    let mut lines = format!(
+        "nettest_result,{} {} {}\n",
+        tags,
+        fields.join(","),
+        started_ns
+    );
+
+    for phase in [MeasurementPhase::Download, MeasurementPhase::Upload] {
+        let series: Vec<&[(u64, u64)]> = result
+            .threads
+            .iter()
+            .flat_map(thread_series)
+            .filter(|(series_phase, _)| *series_phase == phase)
+            .map(|(_, series)| series)
+            .collect();
+        let phase_start_ns = result
+            .timings
+            .iter()
+            .find(|timing| timing.phase == phase)
+            .map(|timing| timing.start_ns)
+            .unwrap_or(0);
+        for (second, bps) in per_second_throughput(&series) {
+            lines.push_str(&format!(
+                "nettest_throughput,{},phase={} bps={},second={}i {}\n",
+                tags,
+                phase.as_str(),
+                bps,
+                second,
+                started_ns + phase_start_ns + second * NS_PER_SECOND
+            ));
+        }
+    }
+    lines
+}
+
+/// Throughput of all threads together in every full second, as
+/// `(second, bits per second)` with `second` counted from 1
+pub fn per_second_throughput(series: &[&[(u64, u64)]]) -> Vec<(u64, f64)> {
+    let seconds = series
+        .iter()
+        .filter_map(|samples| samples.last())
+        .map(|(t_ns, _)| t_ns / NS_PER_SECOND)
+        .max()
+        .unwrap_or(0);
+    let total_at =
+        |t_ns: u64| -> f64 { series.iter().map(|samples| bytes_at(samples, t_ns)).sum() };
+
+    (1..=seconds)
+        .map(|second| {
+            let bytes = total_at(second * NS_PER_SECOND) - total_at((second - 1) * NS_PER_SECOND);
+            (second, bytes * 8.0)
+        })
+        .collect()
+}
+
+/// Cumulative bytes at `t_ns`, interpolated between samples
+fn bytes_at(samples: &[(u64, u64)], t_ns: u64) -> f64 {
+    match samples.iter().position(|(time, _)| *time >= t_ns) {
+        Some(0) => {
+            let (time, bytes) = samples[0];
+            bytes as f64 * t_ns as f64 / time.max(1) as f64
+        }
+        Some(index) => {
+            let (t0, b0) = samples[index - 1];
+            let (t1, b1) = samples[index];
+            b0 as f64 + (b1 - b0) as f64 * (t_ns - t0) as f64 / (t1 - t0).max(1) as f64
+        }
+        None => samples
+            .last()
+            .map(|(_, bytes)| *bytes as f64)
+            .unwrap_or(0.0),
+    }
+}
+
+/// Tag values escape commas, spaces and equals signs
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ',' | ' ' | '=') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::ThreadMeasurement;
+    use crate::client::measurement::{PhaseTiming, Speed};
+    use crate::client::setup::ConnectionSetup;
+    use crate::client::state::TestPhase;
+    use std::io::Read;
+
+    #[test]
+    fn test_per_second_throughput() {
+        // 1 MB/s and 2 MB/s over two seconds
+        let first = [
+            (500_000_000, 500_000),
+            (1_000_000_000, 1_000_000),
+            (2_000_000_000, 2_000_000),
+        ];
+        let second = [(1_000_000_000, 2_000_000), (2_100_000_000, 4_200_000)];
+
+        let throughput = per_second_throughput(&[&first, &second]);
+
+        assert_eq!(throughput.len(), 2);
+        assert_eq!(throughput[0], (1, 24_000_000.0));
+        assert_eq!(throughput[1], (2, 24_000_000.0));
+    }
+
+    #[test]
+    fn test_escape_tag() {
+        assert_eq!(escape_tag("my server,eu=1"), "my\\ server\\,eu\\=1");
+    }
+
+    #[tokio::test]
+    async fn test_post_to_write_endpoint() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/write?db=nettest", listener.local_addr().unwrap());
+        // Stand-in for InfluxDB: accepts one write and answers 204
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 4096];
+            loop {
+                let read = stream.read(&mut buffer).unwrap();
+                request.extend_from_slice(&buffer[..read]);
+                let text = String::from_utf8_lossy(&request).to_string();
+                if let Some(end) = text.find("\r\n\r\n") {
+                    let length = text
+                        .lines()
+                        .find_map(|line| {
+                            line.to_ascii_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().to_string())
+                        })
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .unwrap_or(0);
+                    if request.len() >= end + 4 + length {
+                        stream
+                            .write_all(b"HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n")
+                            .unwrap();
+                        return text;
+                    }
+                }
+                if read == 0 {
+                    return text;
+                }
+            }
+        });
+
+        let config = ClientConfig {
+            client_uuid: Some("c0ffee".to_string()),
+            ..ClientConfig::default()
+        };
+        let result = MeasurementResult {
+            ping_median_ns: Some(1_500_000),
+            ping_samples_ns: vec![1_500_000],
+            download: Some(Speed::from((8e6, 0.008, 8.0))),
+            threads: vec![ThreadMeasurement {
+                measurements: vec![(1_000_000_000, 1_000_000), (2_000_000_000, 2_000_000)],
+                failed: false,
+                thread_id: 0,
+                phase: TestPhase::PerfCompleted,
+                upload_measurements: vec![],
+                envelope: None,
+                chunk_size: 4096,
+                setup: ConnectionSetup::default(),
+            }],
+            timings: vec![PhaseTiming {
+                phase: MeasurementPhase::Download,
+                start_ns: 5_000_000_000,
+                duration_ns: Some(2_000_000_000),
+            }],
+            ..MeasurementResult::for_test("example.com", "192.0.2.1:5005")
+        };
+
+        post(&url, &config, &result).await.unwrap();
+        let request = server.join().unwrap();
+
+        assert!(request.starts_with("POST /write?db=nettest "));
+        assert!(
+            request.contains("nettest_result,server=example.com,transport=tcp,client_uuid=c0ffee ")
+        );
+        assert!(request.contains("ping_median_ns=1500000i"));
+        assert_eq!(request.matches("nettest_throughput,").count(), 2);
+        assert!(request.contains("phase=download bps=8000000,second=2i"));
+    }
+}
@@ -0,0 +1,22 @@
+//! Result exporters for dashboards, written after the measurement finished:
+//! `-csv` and `-influx` / `-influx-url`, or served to Prometheus with
+//! `-exporter`.
+
+pub mod csv;
+pub mod influx;
+pub mod prometheus;
+
+use crate::client::client::ThreadMeasurement;
+use crate::client::observer::MeasurementPhase;
+
+/// `(phase, series)` of a thread: cumulative `(t_ns, bytes)` samples for
+/// download and upload
+pub(crate) fn thread_series(thread: &ThreadMeasurement) -> [(MeasurementPhase, &[(u64, u64)]); 2] {
+    [
+        (MeasurementPhase::Download, thread.measurements.as_slice()),
+        (
+            MeasurementPhase::Upload,
+            thread.upload_measurements.as_slice(),
+        ),
+    ]
+}
@@ -0,0 +1,349 @@
+//! `-exporter ADDR`: serves the last measurement on `/metrics` in the
+//! Prometheus text format. Measurements run on `-schedule`, or otherwise when a
+//! scrape finds the cached one older than `-exporter-max-age`.
+
+use std::fmt::{Display, Write as _};
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use tokio::sync::Notify;
+
+use crate::client::client::ClientConfig;
+use crate::client::measurement::{MeasurementResult, MeasurementStatus};
+use crate::config::parser::parse_listen_address;
+
+/// Scrapes are answered on one thread, a stalled client must not block it
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 8 * 1024;
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const PING_QUANTILES: [f64; 5] = [0.0, 0.5, 0.9, 0.99, 1.0];
+
+/// `:9469`, `9469`, `127.0.0.1:9469` or `[::1]:9469`; without an address all
+/// interfaces are used
+pub fn parse_exporter_address(value: &str) -> Result<SocketAddr, String> {
+    let value = value.trim();
+    parse_listen_address(value.strip_prefix(':').unwrap_or(value))
+        .map_err(|_| format!("invalid exporter address '{}', expected e.g. :9469", value))
+}
+
+/// What `/metrics` serves, shared by the measurement loop and the HTTP thread
+#[derive(Default)]
+struct Cache {
+    /// Metrics of the last successful measurement
+    result: Option<String>,
+    finished_at: Option<Instant>,
+    running: bool,
+    measurements: u64,
+    errors: u64,
+}
+
+pub struct Exporter {
+    cache: Arc<Mutex<Cache>>,
+    refresh: Arc<Notify>,
+    local_addr: SocketAddr,
+}
+
+impl Exporter {
+    /// Binds `addr` and answers scrapes from a background thread. With
+    /// `max_age`, a scrape while the last measurement is older than that asks
+    /// for a new one through `refresh_requested`; it is still answered from
+    /// the cache right away.
+    pub fn start(addr: SocketAddr, max_age: Option<Duration>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let cache = Arc::new(Mutex::new(Cache::default()));
+        let refresh = Arc::new(Notify::new());
+
+        let thread_cache = cache.clone();
+        let thread_refresh = refresh.clone();
+        thread::Builder::new()
+            .name("nettest-exporter".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    if let Err(e) = serve(stream, &thread_cache, &thread_refresh, max_age) {
+                        debug!("Exporter request failed: {}", e);
+                    }
+                }
+            })?;
+        info!("Serving metrics on http://{}/metrics", local_addr);
+        Ok(Self {
+            cache,
+            refresh,
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Waits until a scrape finds the cached measurement too old
+    pub async fn refresh_requested(&self) {
+        self.refresh.notified().await;
+    }
+
+    pub fn begin(&self) {
+        self.cache.lock().unwrap().running = true;
+    }
+
+    pub fn publish(&self, config: &ClientConfig, result: &MeasurementResult) {
+        let metrics = render(config, result);
+        let mut cache = self.cache.lock().unwrap();
+        cache.result = Some(metrics);
+        cache.finished_at = Some(Instant::now());
+        cache.running = false;
+        cache.measurements += 1;
+    }
+
+    /// A measurement that produced no result; the previous one stays served
+    pub fn publish_error(&self) {
+        let mut cache = self.cache.lock().unwrap();
+        cache.finished_at = Some(Instant::now());
+        cache.running = false;
+        cache.errors += 1;
+    }
+}
+
+fn serve(
+    mut stream: TcpStream,
+    cache: &Mutex<Cache>,
+    refresh: &Notify,
+    max_age: Option<Duration>,
+) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buf)?;
+        if n == 0 || request.len() + n > MAX_REQUEST_SIZE {
+            break;
+        }
+        request.extend_from_slice(&buf[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut request_line = request.lines().next().unwrap_or_default().split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default();
+
+    let (status, body) = match (method, path) {
+        ("GET", "/metrics") => ("200 OK", scrape(cache, refresh, max_age)),
+        ("GET", "/") => ("200 OK", "nettest exporter, metrics are on /metrics\n".to_string()),
+        ("GET", _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => ("405 Method Not Allowed", "Only GET is supported\n".to_string()),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\
+         \r\n",
+        status,
+        CONTENT_TYPE,
+        body.len()
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.write_all(body.as_bytes())?;
+    stream.flush()
+}
+
+/// Metrics for one scrape, asking for a new measurement when the last one is
+/// older than `max_age`
+fn scrape(cache: &Mutex<Cache>, refresh: &Notify, max_age: Option<Duration>) -> String {
+    let mut cache = cache.lock().unwrap();
+    let stale = cache
+        .finished_at
+        .is_none_or(|finished_at| max_age.is_some_and(|max_age| finished_at.elapsed() >= max_age));
+    if max_age.is_some() && stale && !cache.running {
+        cache.running = true;
+        refresh.notify_one();
+    }
+
+    let mut out = String::new();
+    metric(&mut out, "nettest_measurements_total", "counter", "Measurements completed since the exporter started");
+    sample(&mut out, "nettest_measurements_total", "", cache.measurements);
+    metric(&mut out, "nettest_measurement_errors_total", "counter", "Measurements that produced no result");
+    sample(&mut out, "nettest_measurement_errors_total", "", cache.errors);
+    metric(&mut out, "nettest_measurement_running", "gauge", "1 while a measurement is in progress");
+    sample(&mut out, "nettest_measurement_running", "", u8::from(cache.running));
+    if let Some(result) = &cache.result {
+        out.push_str(result);
+    }
+    out
+}
+
+/// Metrics of a finished measurement, in the Prometheus text format
+pub fn render(config: &ClientConfig, result: &MeasurementResult) -> String {
+    let mut out = String::new();
+
+    metric(&mut out, "nettest_info", "gauge", "Server, transport and interface of the last measurement");
+    let labels = format!(
+        "version=\"{}\",server=\"{}\",address=\"{}\",transport=\"{}\",tls=\"{}\",websocket=\"{}\",interface=\"{}\"",
+        env!("CARGO_PKG_VERSION"),
+        escape(&result.server_host),
+        result.server,
+        config.transport(),
+        config.use_tls,
+        config.use_websocket,
+        escape(result.interface.as_deref().unwrap_or(""))
+    );
+    sample(&mut out, "nettest_info", &labels, 1);
+
+    metric(&mut out, "nettest_tls", "gauge", "1 when the measurement used TLS");
+    sample(&mut out, "nettest_tls", "", u8::from(config.use_tls));
+    metric(&mut out, "nettest_websocket", "gauge", "1 when the measurement used WebSocket");
+    sample(&mut out, "nettest_websocket", "", u8::from(config.use_websocket));
+
+    metric(&mut out, "nettest_last_measurement_timestamp_seconds", "gauge", "Start of the last measurement, Unix time");
+    let started = result.started_at.timestamp_millis() as f64 / 1000.0;
+    sample(&mut out, "nettest_last_measurement_timestamp_seconds", "", started);
+    metric(&mut out, "nettest_last_measurement_duration_seconds", "gauge", "Duration of the last measurement");
+    sample(&mut out, "nettest_last_measurement_duration_seconds", "", result.duration.as_secs_f64());
+
+    metric(&mut out, "nettest_status", "gauge", "1 for the status of the last measurement");
+    for status in [MeasurementStatus::Complete, MeasurementStatus::Degraded, MeasurementStatus::Failed] {
+        let labels = format!("status=\"{}\"", status.as_str());
+        sample(&mut out, "nettest_status", &labels, u8::from(result.status == status));
+    }
+
+    if let Some(median_ns) = result.ping_median_ns {
+        metric(&mut out, "nettest_ping_median_seconds", "gauge", "Median ping round trip time");
+        sample(&mut out, "nettest_ping_median_seconds", "", seconds(median_ns));
+    }
+    if !result.ping_samples_ns.is_empty() {
+        let mut pings = result.ping_samples_ns.clone();
+        pings.sort_unstable();
+        metric(&mut out, "nettest_ping_seconds", "summary", "Ping round trip times of the last measurement");
+        for quantile in PING_QUANTILES {
+            // Nearest rank
+            let index = ((quantile * pings.len() as f64).ceil() as usize).clamp(1, pings.len()) - 1;
+            let labels = format!("quantile=\"{}\"", quantile);
+            sample(&mut out, "nettest_ping_seconds", &labels, seconds(pings[index]));
+        }
+        let sum: u64 = pings.iter().sum();
+        sample(&mut out, "nettest_ping_seconds_sum", "", seconds(sum));
+        sample(&mut out, "nettest_ping_seconds_count", "", pings.len());
+    }
+
+    if let Some(download) = result.download {
+        metric(&mut out, "nettest_download_bits_per_second", "gauge", "Download speed over all threads");
+        sample(&mut out, "nettest_download_bits_per_second", "", download.bps);
+    }
+    if let Some(upload) = result.upload {
+        metric(&mut out, "nettest_upload_bits_per_second", "gauge", "Upload speed over all threads");
+        sample(&mut out, "nettest_upload_bits_per_second", "", upload.bps);
+    }
+
+    metric(&mut out, "nettest_threads", "gauge", "Connections the measurement was started with");
+    sample(&mut out, "nettest_threads", "", result.thread_count);
+    metric(&mut out, "nettest_failed_threads", "gauge", "Connections that did not complete the measurement");
+    sample(&mut out, "nettest_failed_threads", "", result.failed_threads());
+    metric(&mut out, "nettest_retries", "gauge", "Phases repeated on a new connection");
+    sample(&mut out, "nettest_retries", "", result.retries);
+    out
+}
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl Display) {
+    if labels.is_empty() {
+        let _ = writeln!(out, "{} {}", name, value);
+    } else {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, value);
+    }
+}
+
+fn seconds(ns: u64) -> f64 {
+    ns as f64 / 1_000_000_000.0
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::measurement::Speed;
+    use std::time::Duration;
+
+    fn start() -> Exporter {
+        Exporter::start("127.0.0.1:0".parse().unwrap(), Some(Duration::from_secs(60))).unwrap()
+    }
+
+    fn get(exporter: &Exporter, path: &str) -> String {
+        let mut stream = TcpStream::connect(exporter.local_addr()).unwrap();
+        write!(stream, "GET {} HTTP/1.1\r\nHost: probe\r\n\r\n", path).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_parse_exporter_address() {
+        assert_eq!(parse_exporter_address(":9469").unwrap(), "0.0.0.0:9469".parse().unwrap());
+        assert_eq!(parse_exporter_address("127.0.0.1:9469").unwrap(), "127.0.0.1:9469".parse().unwrap());
+        assert!(parse_exporter_address("probe").is_err());
+    }
+
+    #[test]
+    fn test_scrape_without_result_requests_measurement() {
+        let exporter = start();
+        let response = get(&exporter, "/metrics");
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("\nnettest_measurement_running 1\n"));
+        assert!(!response.contains("nettest_download_bits_per_second"));
+    }
+
+    #[test]
+    fn test_unknown_path_not_found() {
+        let exporter = start();
+        assert!(get(&exporter, "/other").starts_with("HTTP/1.1 404"));
+    }
+
+    #[test]
+    fn test_scrape_published_result() {
+        let exporter = start();
+        let config = ClientConfig {
+            use_tls: true,
+            ..ClientConfig::default()
+        };
+        let result = MeasurementResult {
+            thread_count: 3,
+            ping_median_ns: Some(2_000_000),
+            ping_samples_ns: vec![3_000_000, 1_000_000, 2_000_000],
+            download: Some(Speed::from((1e9, 1.0, 1000.0))),
+            status: MeasurementStatus::Degraded,
+            retries: 1,
+            ..MeasurementResult::for_test("probe.example.com", "192.0.2.1:443")
+        };
+        exporter.begin();
+        exporter.publish(&config, &result);
+
+        let response = get(&exporter, "/metrics");
+        assert!(response.contains("\nnettest_measurements_total 1\n"));
+        assert!(response.contains("\nnettest_measurement_running 0\n"));
+        assert!(response.contains("transport=\"tls\",tls=\"true\",websocket=\"false\",interface=\"\"} 1\n"));
+        assert!(response.contains("\nnettest_status{status=\"degraded\"} 1\n"));
+        assert!(response.contains("\nnettest_ping_seconds{quantile=\"0.5\"} 0.002\n"));
+        assert!(response.contains("\nnettest_ping_seconds_count 3\n"));
+        assert!(response.contains("\nnettest_download_bits_per_second 1000000000\n"));
+        assert!(response.contains("\nnettest_failed_threads 3\n"));
+        assert!(response.contains("\nnettest_last_measurement_timestamp_seconds 1772445600\n"));
+        assert!(!response.contains("nettest_upload_bits_per_second"));
+    }
+}
@@ -1,13 +1,13 @@
 use crate::client::state::TestPhase;
 use crate::client::constants::{
     ACCEPT_GETCHUNKS_STRING, MAX_CHUNKS_BEFORE_SIZE_INCREASE, OK_COMMAND,
-    PRE_DOWNLOAD_DURATION_NS,
     get_max_chunk_size,
 };
 use crate::client::state::MeasurementState;
 use anyhow::Result;
 use log::debug;
 use mio::{Interest, Poll};
+use crate::client::handlers::read_stream;
 
 pub fn handle_get_chunks_receive_time(
     poll: &Poll,
@@ -15,15 +15,19 @@ pub fn handle_get_chunks_receive_time(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_get_chunks_receive_time token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         let buffer_str = String::from_utf8_lossy(&state.read_buffer[..state.read_pos]);
 
         if buffer_str.contains(ACCEPT_GETCHUNKS_STRING) {
             if let Some(time_ns) = parse_time_response(&buffer_str) {
-                if time_ns < PRE_DOWNLOAD_DURATION_NS && state.chunk_size < get_max_chunk_size() as usize
+                state.pre_download_bytes = state.total_chunks as u64 * state.chunk_size as u64;
+                state.pre_download_time_ns = time_ns;
+                if time_ns < state.durations.pre_download.as_nanos() as u64 && state.chunk_size < get_max_chunk_size() as usize
                 {
                     increase_chunk_size(state);
                     state.phase = TestPhase::GetChunksSendChunksCommand;
@@ -109,9 +113,11 @@ pub fn handle_get_chunks_receive_chunk(
     debug!("handle_get_chunks_receive_chunk token {:?}", state.token);
 
     loop {
-        let n = state
-            .stream
-            .read(&mut state.chunk_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.chunk_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         if state.read_pos == state.chunk_size as usize {
             if state.chunk_buffer[state.read_pos - 1] == 0x00 {
@@ -5,8 +5,7 @@ use std::time::Instant;
 
 use crate::client::constants::ACCEPT_GETCHUNKS_STRING;
 use crate::client::state::{MeasurementState, TestPhase};
-
-const TEST_DURATION_NS: u64 = 7_000_000_000; // 7 seconds
+use crate::client::handlers::read_stream;
 
 pub fn handle_get_time_send_ok(
     poll: &Poll,
@@ -41,7 +40,7 @@ pub fn handle_get_time_send_command(
 
     let command = format!(
         "GETTIME {} {}\n",
-        TEST_DURATION_NS / 1_000_000_000,
+        state.durations.download.as_secs(),
         state.chunk_size
     );
     if state.write_pos == 0 {
@@ -73,9 +72,11 @@ pub fn handle_get_time_receive_chunk(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_get_time_receive_chunk token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.chunk_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.chunk_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         if state.read_pos == state.chunk_size {
             state.bytes_received += state.chunk_size as u64;
@@ -101,9 +102,11 @@ pub fn handle_get_time_receive_time(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_get_time_receive_time token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         let buffer_str = String::from_utf8_lossy(&state.read_buffer[..state.read_pos]);
 
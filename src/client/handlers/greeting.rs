@@ -3,6 +3,8 @@ use crate::stream::stream::Stream;
 use anyhow::Result;
 use log::{debug};
 use mio::{Interest, Poll};
+use std::time::Instant;
+use crate::client::handlers::read_stream;
 
 
 pub fn handle_greeting_send_connection_type(
@@ -93,9 +95,14 @@ pub fn handle_greeting_receive_greeting(
     debug!("handle_greeting_receive_greeting token {:?}", state.token);
     loop {
         debug!("handle_greeting_receive_greeting read 1");
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
+        if n > 0 {
+            state.setup.first_byte.get_or_insert_with(Instant::now);
+        }
         state.read_pos += n;
         let end = b"ACCEPT TOKEN QUIT\n";
         if n > 0 && state.read_pos >= end.len() && state.read_buffer[state.read_pos - end.len()..state.read_pos] == *end {
@@ -115,9 +122,15 @@ pub fn handle_greeting_receive_response(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_greeting_receive_response token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
+        // WebSocket streams skip the greeting phase, their greeting may arrive here
+        if n > 0 {
+            state.setup.first_byte.get_or_insert_with(Instant::now);
+        }
         state.read_pos += n;
         let end = b"ACCEPT GETCHUNKS GETTIME PUT PUTNORESULT PING QUIT\n";
         if n > 0
@@ -6,3 +6,31 @@ pub mod get_time;
 pub mod put;
 pub mod puttimeresult;
 pub mod signed_result;
+
+use std::io;
+use std::time::Instant;
+
+use crate::stream::stream::Stream;
+
+/// Bytes the upload writes per writable event before it re-arms the
+/// registration and lets the other connections of its event loop run
+pub const MAX_WRITE_PER_EVENT: usize = 4 * 1024 * 1024;
+
+/// Reads into `buf`. Plain TCP only returns 0 bytes once the server closed the
+/// connection. TLS and WebSocket also do for records or frames without payload,
+/// so there a read without progress fails only after `deadline`.
+pub fn read_stream(stream: &mut Stream, buf: &mut [u8], deadline: Option<Instant>) -> io::Result<usize> {
+    let n = stream.read(buf)?;
+    if n == 0 && !buf.is_empty() {
+        if matches!(stream, Stream::Tcp(_)) {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed by server",
+            ));
+        }
+        if deadline.is_some_and(|deadline| Instant::now() > deadline) {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "no data from server"));
+        }
+    }
+    Ok(n)
+}
@@ -5,9 +5,8 @@ use anyhow::Result;
 use log::debug;
 use mio::{Interest, Poll};
 use std::time::Instant;
+use crate::client::handlers::read_stream;
 
-const MAX_PINGS: u32 = 200;
-const PING_DURATION_NS: u64 = 1_000_000_000; // 1 second
 const PONG_RESPONSE: &[u8] = b"PONG\n";
 
 pub fn handle_ping_send_ok(poll: &Poll, state: &mut MeasurementState) -> Result<usize, std::io::Error> {
@@ -64,9 +63,11 @@ pub fn handle_ping_receive_pong(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_ping_receive_pong token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..PONG_RESPONSE.len()])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..PONG_RESPONSE.len()],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         if state.read_pos == PONG_RESPONSE.len() {
             state.read_pos = 0;
@@ -85,9 +86,11 @@ pub fn handle_ping_receive_time(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_ping_receive_time token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         if state.read_pos >= ACCEPT_GETCHUNKS_STRING.len()
             && state.read_buffer[state.read_pos - ACCEPT_GETCHUNKS_STRING.len()..state.read_pos]
@@ -103,8 +106,8 @@ pub fn handle_ping_receive_time(
                         state.ping_times.push(time_ns);
                         let pings_sent = state.ping_times.len();
 
-                        if elapsed.as_nanos() < PING_DURATION_NS as u128
-                            && pings_sent < MAX_PINGS as usize
+                        if elapsed < state.durations.ping
+                            && pings_sent < state.durations.ping_count as usize
                         {
                             state.phase = TestPhase::PingSendPing;
                             state
@@ -6,9 +6,7 @@ use std::time::Instant;
 use crate::client::constants::OK_COMMAND;
 use crate::client::globals::{CHUNK_STORAGE, CHUNK_TERMINATION_STORAGE};
 use crate::client::state::{MeasurementState, TestPhase};
-
-/// Nominal duration of uplink measurement in nanoseconds (7 seconds)
-const UPLINK_DURATION_NS: u64 = 7_000_000_000;
+use crate::client::handlers::read_stream;
 
 pub fn handle_put_send_command(
     poll: &Poll,
@@ -42,16 +40,18 @@ pub fn handle_put_receive_ok(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_put_receive_ok token {:?}", state.token);
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..state.read_pos + OK_COMMAND.len()])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..state.read_pos + OK_COMMAND.len()],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
         if state.read_pos >= OK_COMMAND.len() {
             let received = &state.read_buffer[..state.read_pos];
             if received.starts_with(OK_COMMAND) {
                 state.phase = TestPhase::PutSendChunks;
                 state.phase_start_time = Some(Instant::now());
-                debug!("PUT test started, phase_start_time set, target duration: {} ns ({} seconds)", UPLINK_DURATION_NS, UPLINK_DURATION_NS as f64 / 1_000_000_000.0);
+                debug!("PUT test started, phase_start_time set, target duration: {} ns ({} seconds)", state.durations.upload.as_nanos(), state.durations.upload.as_secs_f64());
                 state
                     .stream
                     .reregister(&poll, state.token, Interest::WRITABLE)?;
@@ -82,7 +82,7 @@ pub fn handle_put_send_chunks(
         loop {
             // Check time before writing to determine if this should be the last chunk
             let elapsed_ns = start_time.elapsed().as_nanos();
-            let is_last = elapsed_ns >= UPLINK_DURATION_NS as u128;
+            let is_last = elapsed_ns >= state.durations.upload.as_nanos();
             
             if is_last && state.write_pos == 0 {
                 // Time limit reached before starting to write this chunk, switch to sending last chunk
@@ -92,7 +92,7 @@ pub fn handle_put_send_chunks(
                     state.token,
                     Interest::WRITABLE,
                 )?;
-                debug!("Time limit reached before chunk start ({} ns >= {} ns), switching to last chunk", elapsed_ns, UPLINK_DURATION_NS);
+                debug!("Time limit reached before chunk start ({} ns >= {} ns), switching to last chunk", elapsed_ns, state.durations.upload.as_nanos());
                 // Return Ok(1) to indicate successful phase switch (not Ok(0) which would be treated as error)
                 return Ok(1);
             }
@@ -109,11 +109,11 @@ pub fn handle_put_send_chunks(
             if state.write_pos == state.chunk_size {
                 // Chunk completed, check time again
                 let tt = start_time.elapsed().as_nanos();
-                let is_last_after_chunk = tt >= UPLINK_DURATION_NS as u128;
+                let is_last_after_chunk = tt >= state.durations.upload.as_nanos();
 
                 debug!("Chunk completed: elapsed={} ns ({} s), target={} ns ({} s), is_last={}", 
                     tt, tt as f64 / 1_000_000_000.0, 
-                    UPLINK_DURATION_NS, UPLINK_DURATION_NS as f64 / 1_000_000_000.0,
+                    state.durations.upload.as_nanos(), state.durations.upload.as_secs_f64(),
                     is_last_after_chunk);
 
                 if is_last_after_chunk {
@@ -124,7 +124,7 @@ pub fn handle_put_send_chunks(
                         Interest::WRITABLE,
                     )?;
                     state.write_pos = 0;
-                    debug!("Time limit reached after chunk completion ({} ns >= {} ns), switching to last chunk, written: {}", tt, UPLINK_DURATION_NS, written);
+                    debug!("Time limit reached after chunk completion ({} ns >= {} ns), switching to last chunk, written: {}", tt, state.durations.upload.as_nanos(), written);
                     return Ok(written);
                 } else {
                     state.write_pos = 0;
@@ -164,7 +164,11 @@ pub fn handle_put_receive_time_bytes(
 
     loop {
         debug!("reading time bytes, read_pos: {}", state.read_pos);
-        let n = match state.stream.read(&mut state.read_buffer[state.read_pos..]) {
+        let n = match read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        ) {
             Ok(n) => n,
             Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
                 // No data available yet, return to allow poll() to check again
@@ -252,9 +256,11 @@ pub fn handle_put_receive_final_time(
     trace!("handle_put_receive_final_time token {:?}", state.token);
 
     loop {
-        let n = state
-            .stream
-            .read(&mut state.read_buffer[state.read_pos..])?;
+        let n = read_stream(
+            &mut state.stream,
+            &mut state.read_buffer[state.read_pos..],
+            state.phase_deadline,
+        )?;
         state.read_pos += n;
 
         let buffer_str = String::from_utf8_lossy(&state.read_buffer[..state.read_pos]);
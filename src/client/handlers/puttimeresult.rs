@@ -5,8 +5,7 @@ use std::time::Instant;
 
 use crate::client::globals::{CHUNK_STORAGE, CHUNK_TERMINATION_STORAGE};
 use crate::client::state::{MeasurementState, TestPhase};
-
-const TEST_DURATION_NS: u64 = 7_000_000_000; 
+use crate::client::handlers::{read_stream, MAX_WRITE_PER_EVENT};
 
 pub fn handle_put_time_result_receive_ok(
     poll: &Poll,
@@ -14,9 +13,11 @@ pub fn handle_put_time_result_receive_ok(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_perf_receive_ok token {:?}", measurement_state.token);
     loop {
-        let n = measurement_state
-            .stream
-            .read(&mut measurement_state.read_buffer[measurement_state.read_pos..b"OK\n".len()])?;
+        let n = read_stream(
+            &mut measurement_state.stream,
+            &mut measurement_state.read_buffer[measurement_state.read_pos..b"OK\n".len()],
+            measurement_state.phase_deadline,
+        )?;
         if n == b"OK\n".len() {
             measurement_state.phase = TestPhase::PerfSendChunks;
             measurement_state.stream.reregister(
@@ -36,16 +37,19 @@ pub fn handle_put_time_result_receive_time(
 ) -> Result<usize, std::io::Error> {
     debug!("handle_put_time_result_receive_time token {:?}", measurement_state.token);
     loop {
-        let n = measurement_state
-            .stream
-            .read(&mut measurement_state.read_buffer[measurement_state.read_pos..])?;
+        let n = read_stream(
+            &mut measurement_state.stream,
+            &mut measurement_state.read_buffer[measurement_state.read_pos..],
+            measurement_state.phase_deadline,
+        )?;
         measurement_state.time_result_buffer.extend_from_slice(&measurement_state.read_buffer[..n]);
 
-        let time_line = String::from_utf8_lossy(&measurement_state.time_result_buffer);
-        
         let end = "ACCEPT GETCHUNKS GETTIME PUT PUTNORESULT PING QUIT\n";
 
-        if time_line.ends_with(end) {
+        // The reply carries one pair per chunk and can be megabytes long, decode
+        // it once it is complete rather than on every read
+        if measurement_state.time_result_buffer.ends_with(end.as_bytes()) {
+            let time_line = String::from_utf8_lossy(&measurement_state.time_result_buffer);
             // Check if this is a TIMERESULT message
             if time_line.starts_with("TIMERESULT ") {
                 let data_part = &time_line[11..]; // Remove "TIMERESULT "
@@ -132,20 +136,31 @@ pub fn handle_put_time_result_send_chunks(
         let buffer = CHUNK_STORAGE
             .get(&(measurement_state.chunk_size as u64))
             .unwrap();
+        let mut written_this_event = 0;
         loop {
+            if written_this_event >= MAX_WRITE_PER_EVENT {
+                // Still writable, re-registering reports it again on the next poll
+                measurement_state.stream.reregister(
+                    poll,
+                    measurement_state.token,
+                    Interest::WRITABLE,
+                )?;
+                return Ok(written_this_event);
+            }
             // Write from current position
             let written = measurement_state.stream.write( &buffer[measurement_state.write_pos..])?;
             if written == 0 {
                 info!("No data to write");
                 return Ok(0);
             }
+            written_this_event += written;
             measurement_state.bytes_sent += written as u64;
             measurement_state.write_pos += written;
 
             // debug!("Sent {} bytes token {:?}", measurement_state.bytes_sent, measurement_state.token);
             if measurement_state.write_pos == measurement_state.chunk_size  {
                 let tt = start_time.elapsed().as_nanos();
-                let is_last = tt >= TEST_DURATION_NS as u128;
+                let is_last = tt >= measurement_state.durations.upload.as_nanos();
 
                 if is_last {
                     measurement_state.phase = TestPhase::PerfSendLastChunk;
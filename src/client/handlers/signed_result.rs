@@ -2,6 +2,7 @@ use log::debug;
 use mio::{Interest, Poll};
 
 use crate::client::state::{MeasurementState, TestPhase};
+use crate::client::handlers::read_stream;
 
 pub fn handle_signed_result_command(
     poll: &Poll,
@@ -33,7 +34,7 @@ pub fn handle_signed_result_receive(
     debug!("handle_signed_result_receive");
     loop {
         let mut buf = [0; 1024];
-        let n = state.stream.read(&mut buf)?;
+        let n = read_stream(&mut state.stream, &mut buf, state.phase_deadline)?;
         debug!("{}", String::from_utf8_lossy(&buf[0..n]));
         debug!("{}", state.read_pos);
         state.read_buffer[state.read_pos..state.read_pos + n].copy_from_slice(&buf[0..n]);
@@ -0,0 +1,363 @@
+//! Local measurement history: every client run appends one JSON line to
+//! `$XDG_DATA_HOME/nettest/history.jsonl` (`client_history_file`), and
+//! `nettest history` lists, filters and summarises the runs.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local, Utc};
+use log::warn;
+use prettytable::format::consts::FORMAT_BOX_CHARS;
+use prettytable::{row, Table};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::client::client::ClientConfig;
+use crate::client::measurement::MeasurementResult;
+use crate::client::schedule::parse_period;
+use crate::config::{paths, FileConfig};
+
+/// Runs listed by `nettest history` unless `-limit` says otherwise
+const DEFAULT_LIST_LIMIT: usize = 20;
+
+/// One line of the history file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub started_at: DateTime<Utc>,
+    /// Server as configured, host name or IP address
+    pub server: String,
+    pub address: String,
+    pub transport: String,
+    /// `complete`, `degraded` or `failed`
+    pub status: String,
+    pub thread_count: usize,
+    pub ping_ms: Option<f64>,
+    pub download_mbps: Option<f64>,
+    pub upload_mbps: Option<f64>,
+    pub retries: usize,
+}
+
+impl HistoryEntry {
+    pub fn from_result(config: &ClientConfig, result: &MeasurementResult) -> Self {
+        Self {
+            started_at: result.started_at,
+            server: result.server_host.clone(),
+            address: result.server.to_string(),
+            transport: config.transport().to_string(),
+            status: result.status.as_str().to_string(),
+            thread_count: result.thread_count,
+            ping_ms: result.ping_median_ms(),
+            download_mbps: result.download.map(|speed| speed.mbps),
+            upload_mbps: result.upload.map(|speed| speed.mbps),
+            retries: result.retries,
+        }
+    }
+}
+
+/// `client_history_file`, else `history.jsonl` in the user data directory.
+/// `nettest history` reads it even when `client_history` is off.
+pub fn history_file(config: &FileConfig) -> Option<PathBuf> {
+    config
+        .client_history_file
+        .clone()
+        .or_else(|| paths::user_data_dir().map(|dir| dir.join("history.jsonl")))
+}
+
+pub fn append(path: &Path, entry: &HistoryEntry) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let line = serde_json::to_string(entry)?;
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(file, "{}", line)
+}
+
+/// Entries in the order they were recorded; a missing file is an empty
+/// history and unreadable lines are skipped
+pub fn load(path: &Path) -> io::Result<Vec<HistoryEntry>> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let mut entries = Vec::new();
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(e) => warn!("Skipping line {} of {}: {}", index + 1, path.display(), e),
+        }
+    }
+    Ok(entries)
+}
+
+/// `nettest history` filters
+#[derive(Debug, Clone, Default)]
+pub struct HistoryFilter {
+    pub since: Option<DateTime<Utc>>,
+    /// Part of the server host or address
+    pub server: Option<String>,
+    pub status: Option<String>,
+}
+
+impl HistoryFilter {
+    pub fn matches(&self, entry: &HistoryEntry) -> bool {
+        self.since.is_none_or(|since| entry.started_at >= since)
+            && self.server.as_ref().is_none_or(|server| {
+                entry.server.contains(server.as_str()) || entry.address.contains(server.as_str())
+            })
+            && self.status.as_ref().is_none_or(|status| entry.status == *status)
+    }
+}
+
+/// Median, minimum and maximum of one value over the selected runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub median: f64,
+    pub min: f64,
+    pub max: f64,
+    /// Runs that have the value
+    pub count: usize,
+}
+
+impl Stats {
+    pub fn of(values: impl Iterator<Item = f64>) -> Option<Self> {
+        let mut values: Vec<f64> = values.collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(f64::total_cmp);
+        let middle = values.len() / 2;
+        let median = if values.len().is_multiple_of(2) {
+            (values[middle - 1] + values[middle]) / 2.0
+        } else {
+            values[middle]
+        };
+        Some(Self {
+            median,
+            min: values[0],
+            max: values[values.len() - 1],
+            count: values.len(),
+        })
+    }
+}
+
+/// Runs `nettest history [OPTIONS]`
+pub fn history_run(args: Vec<String>, config: FileConfig) -> anyhow::Result<()> {
+    let mut path = history_file(&config);
+    let mut filter = HistoryFilter::default();
+    let mut limit = DEFAULT_LIST_LIMIT;
+    let mut json_output = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match (args[i].as_str(), value) {
+            ("-since", Some(value)) => {
+                let period = parse_period(value).map_err(|e| anyhow::anyhow!(e))?;
+                let since = chrono::Duration::from_std(period).ok().and_then(|period| Utc::now().checked_sub_signed(period));
+                filter.since = Some(since.ok_or_else(|| anyhow::anyhow!("-since {} reaches too far back", value))?);
+                i += 1;
+            }
+            ("-server", Some(value)) => {
+                filter.server = Some(value.clone());
+                i += 1;
+            }
+            ("-status", Some(value)) => {
+                if !matches!(value.as_str(), "complete" | "degraded" | "failed") {
+                    return Err(anyhow::anyhow!(
+                        "Invalid status: {}, expected complete, degraded or failed",
+                        value
+                    ));
+                }
+                filter.status = Some(value.clone());
+                i += 1;
+            }
+            ("-limit", Some(value)) => {
+                limit = value.parse()?;
+                i += 1;
+            }
+            ("-file", Some(value)) => {
+                path = Some(PathBuf::from(value));
+                i += 1;
+            }
+            ("-json", _) => json_output = true,
+            ("-h" | "--help", _) => {
+                print_history_help();
+                return Ok(());
+            }
+            (flag, _) => {
+                eprintln!("Error: Unknown option or missing value '{}'\n", flag);
+                print_history_help();
+                std::process::exit(1);
+            }
+        }
+        i += 1;
+    }
+
+    let path = path.ok_or_else(|| anyhow::anyhow!("No data directory for the history, use -file PATH"))?;
+    let entries: Vec<HistoryEntry> = load(&path)
+        .map_err(|e| anyhow::anyhow!("Failed to read history {}: {}", path.display(), e))?
+        .into_iter()
+        .filter(|entry| filter.matches(entry))
+        .collect();
+    let ping = Stats::of(entries.iter().filter_map(|entry| entry.ping_ms));
+    let download = Stats::of(entries.iter().filter_map(|entry| entry.download_mbps));
+    let upload = Stats::of(entries.iter().filter_map(|entry| entry.upload_mbps));
+    let listed = &entries[entries.len().saturating_sub(limit)..];
+
+    if json_output {
+        let stats_json = |stats: Option<Stats>| {
+            stats.map(|stats| {
+                json!({
+                    "median": stats.median,
+                    "min": stats.min,
+                    "max": stats.max,
+                    "count": stats.count,
+                })
+            })
+        };
+        let document = json!({
+            "runs": listed,
+            "summary": {
+                "runs": entries.len(),
+                "degraded": entries.iter().filter(|entry| entry.status == "degraded").count(),
+                "failed": entries.iter().filter(|entry| entry.status == "failed").count(),
+                "ping_ms": stats_json(ping),
+                "download_mbps": stats_json(download),
+                "upload_mbps": stats_json(upload),
+            },
+        });
+        println!("{}", serde_json::to_string_pretty(&document)?);
+        return Ok(());
+    }
+
+    if entries.is_empty() {
+        println!("No measurements in {}", path.display());
+        return Ok(());
+    }
+    let value = |value: Option<f64>| value.map_or_else(|| "-".to_string(), |value| format!("{:.2}", value));
+    let mut table = Table::new();
+    table.set_format(*FORMAT_BOX_CHARS);
+    table.set_titles(row!["Started", "Server", "Status", "Ping ms", "Download Mbit/s", "Upload Mbit/s"]);
+    for entry in listed {
+        table.add_row(row![
+            entry.started_at.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+            entry.server,
+            entry.status,
+            r->value(entry.ping_ms),
+            r->value(entry.download_mbps),
+            r->value(entry.upload_mbps)
+        ]);
+    }
+    table.printstd();
+    if listed.len() < entries.len() {
+        println!("Showing the last {} of {} runs", listed.len(), entries.len());
+    }
+
+    let mut summary = Table::new();
+    summary.set_format(*FORMAT_BOX_CHARS);
+    summary.set_titles(row!["", "Median", "Min", "Max", "Runs"]);
+    for (name, stats) in [("Ping ms", ping), ("Download Mbit/s", download), ("Upload Mbit/s", upload)] {
+        if let Some(stats) = stats {
+            summary.add_row(row![
+                name,
+                r->format!("{:.2}", stats.median),
+                r->format!("{:.2}", stats.min),
+                r->format!("{:.2}", stats.max),
+                r->stats.count
+            ]);
+        }
+    }
+    println!();
+    summary.printstd();
+    let degraded = entries.iter().filter(|entry| entry.status != "complete").count();
+    println!("{} runs, {} degraded or failed", entries.len(), degraded);
+    Ok(())
+}
+
+pub fn print_history_help() {
+    println!("nettest history - Past client measurements\n");
+    println!("USAGE:");
+    println!("    nettest history [OPTIONS]\n");
+    println!("EXAMPLES:");
+    println!("    nettest history -since 7d          Runs of the last week with median speeds");
+    println!("    nettest history -status degraded   Runs where threads failed or were retried\n");
+    println!("OPTIONS:");
+    println!("    -since PERIOD   Only runs started within PERIOD, e.g. 12h or 7d");
+    println!("    -server TEXT    Only runs whose server host or address contains TEXT");
+    println!("    -status STATUS  Only complete, degraded or failed runs");
+    println!("    -limit N        Runs to list, the summary covers all matches (default: 20)");
+    println!("    -json           Print runs and summary as JSON");
+    println!("    -file PATH      History file (default: client_history_file or");
+    println!("                    ~/.local/share/nettest/history.jsonl)");
+    println!("    -h, --help      Show this help message");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(started_at: &str, server: &str, status: &str, download_mbps: Option<f64>) -> HistoryEntry {
+        HistoryEntry {
+            started_at: started_at.parse().unwrap(),
+            server: server.to_string(),
+            address: "192.0.2.1:5005".to_string(),
+            transport: "tcp".to_string(),
+            status: status.to_string(),
+            thread_count: 3,
+            ping_ms: Some(4.2),
+            download_mbps,
+            upload_mbps: None,
+            retries: 0,
+        }
+    }
+
+    fn runs() -> [HistoryEntry; 4] {
+        [
+            entry("2026-03-01T10:00:00Z", "a.example.com", "complete", Some(100.0)),
+            entry("2026-03-05T10:00:00Z", "a.example.com", "degraded", Some(40.0)),
+            entry("2026-03-06T10:00:00Z", "b.example.com", "complete", Some(300.0)),
+            entry("2026-03-07T10:00:00Z", "a.example.com", "failed", None),
+        ]
+    }
+
+    #[test]
+    fn test_append_and_load() {
+        let path = std::env::temp_dir().join(format!("nettest-history-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert!(load(&path).unwrap().is_empty());
+
+        let runs = runs();
+        for run in &runs {
+            append(&path, run).unwrap();
+        }
+        let loaded = load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, runs);
+    }
+
+    #[test]
+    fn test_filter_by_time_and_server() {
+        let filter = HistoryFilter {
+            since: Some("2026-03-02T00:00:00Z".parse().unwrap()),
+            server: Some("a.example".to_string()),
+            status: None,
+        };
+        let runs = runs();
+        let selected: Vec<&HistoryEntry> = runs.iter().filter(|entry| filter.matches(entry)).collect();
+        assert_eq!(selected.len(), 2);
+    }
+
+    #[test]
+    fn test_stats() {
+        let download = Stats::of(runs().iter().filter_map(|entry| entry.download_mbps)).unwrap();
+        assert_eq!((download.median, download.min, download.max, download.count), (100.0, 40.0, 300.0, 3));
+        assert_eq!(Stats::of([1.0, 4.0].into_iter()).unwrap().median, 2.5);
+        assert!(Stats::of(std::iter::empty()).is_none());
+    }
+}
@@ -0,0 +1,245 @@
+//! Servers on the local network, announced over mDNS by servers started with
+//! `-mdns` (see `mioserver::control_server::mdns`). Used by `-local`, when no
+//! server is given, and by `nettest discover`.
+
+use std::collections::HashSet;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use log::debug;
+use mdns_sd::{ResolvedService, ServiceDaemon, ServiceEvent};
+use prettytable::format::consts::FORMAT_BOX_CHARS;
+use prettytable::{row, Table};
+use serde::Serialize;
+
+use crate::client::client::ClientConfig;
+use crate::client::durations::parse_duration;
+use crate::client::source::AddressFamily;
+use crate::mioserver::control_server::mdns::SERVICE_TYPE;
+
+/// Long enough for servers to answer the first query and its repeat
+pub const DEFAULT_BROWSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LocalServer {
+    /// Instance name, e.g. `nettest._nettest._tcp.local.`
+    pub name: String,
+    pub host: String,
+    /// IPv4 addresses first
+    pub addresses: Vec<IpAddr>,
+    pub tcp_port: u16,
+    pub tls_port: Option<u16>,
+    pub version: Option<String>,
+}
+
+impl LocalServer {
+    fn from_resolved(service: &ResolvedService) -> Self {
+        let mut addresses: Vec<IpAddr> = service.get_addresses().iter().map(|ip| ip.to_ip_addr()).collect();
+        addresses.sort_by_key(|address| (address.is_ipv6(), *address));
+        let port = |key: &str| service.get_property_val_str(key).and_then(|port| port.parse().ok());
+        Self {
+            name: service.get_fullname().to_string(),
+            host: service.get_hostname().to_string(),
+            addresses,
+            // The SRV record carries the TCP port as well
+            tcp_port: port("tcp_port").unwrap_or(service.get_port()),
+            tls_port: port("tls_port"),
+            version: service.get_property_val_str("version").map(str::to_string),
+        }
+    }
+}
+
+/// Servers that answered within `timeout`, sorted by name and address.
+/// Browsing ends early once `enough` holds for the servers found so far.
+pub fn browse(timeout: Duration, enough: impl Fn(&[LocalServer]) -> bool) -> anyhow::Result<Vec<LocalServer>> {
+    let daemon = ServiceDaemon::new()?;
+    let receiver = daemon.browse(SERVICE_TYPE)?;
+    let now = Instant::now();
+    let deadline = now.checked_add(timeout).unwrap_or(now + Duration::from_secs(86_400));
+    let mut seen = HashSet::new();
+    let mut servers = Vec::new();
+    while let Ok(event) = receiver.recv_deadline(deadline) {
+        if let ServiceEvent::ServiceResolved(service) = event {
+            let server = LocalServer::from_resolved(&service);
+            debug!("mDNS: {:?}", server);
+            // Announcements are repeated, and servers share the instance name
+            if seen.insert((server.name.clone(), server.addresses.clone())) {
+                servers.push(server);
+                if enough(&servers) {
+                    break;
+                }
+            }
+        }
+    }
+    let _ = daemon.stop_browse(SERVICE_TYPE);
+    let _ = daemon.shutdown();
+    servers.sort_by(|a, b| (&a.name, &a.addresses).cmp(&(&b.name, &b.addresses)));
+    Ok(servers)
+}
+
+/// First server that can serve the measurement: one with an address of the
+/// family, and with a TLS port for `-tls`. Link-local IPv6 addresses are
+/// left out, they can't be connected to without their interface.
+fn pick(servers: &[LocalServer], use_tls: bool, family: AddressFamily) -> Option<(&LocalServer, IpAddr)> {
+    servers
+        .iter()
+        .filter(|server| !use_tls || server.tls_port.is_some())
+        .find_map(|server| {
+            let address = server.addresses.iter().find(|address| {
+                let link_local = matches!(address, IpAddr::V6(v6) if v6.is_unicast_link_local());
+                family.matches(address) && !link_local
+            })?;
+            Some((server, *address))
+        })
+}
+
+/// Sets `server` and the ports to a server on the local network. Returns
+/// false when none answered; with `-local` that is an error.
+pub fn discover_local_server(config: &mut ClientConfig) -> anyhow::Result<bool> {
+    let usable = |servers: &[LocalServer]| pick(servers, config.use_tls, config.source.family).is_some();
+    let servers = match browse(DEFAULT_BROWSE_TIMEOUT, usable) {
+        Ok(servers) => servers,
+        Err(e) if config.local => return Err(anyhow::anyhow!("mDNS browsing failed: {}", e)),
+        Err(e) => {
+            debug!("mDNS browsing failed: {}", e);
+            return Ok(false);
+        }
+    };
+    let Some((server, address)) = pick(&servers, config.use_tls, config.source.family) else {
+        if config.local {
+            let reason = match (servers.is_empty(), config.source.family) {
+                (true, _) => String::new(),
+                (false, AddressFamily::Any) if config.use_tls => " with TLS".to_string(),
+                (false, AddressFamily::Any) => " with an address".to_string(),
+                (false, family) => format!(" with an {} address", family.as_str()),
+            };
+            return Err(anyhow::anyhow!("No server{} found on the local network, see 'nettest discover'", reason));
+        }
+        return Ok(false);
+    };
+
+    if servers.len() > 1 && !config.json_output && !config.raw_output && !config.is_check() {
+        println!(
+            "Found {} servers on the local network, using {} ({}), see 'nettest discover'",
+            servers.len(),
+            server.host,
+            address
+        );
+    }
+    config.server = Some(address.to_string());
+    config.port = server.tcp_port;
+    if let Some(tls_port) = server.tls_port {
+        config.tls_port = tls_port;
+    }
+    Ok(true)
+}
+
+/// Runs `nettest discover [OPTIONS]`
+pub fn discover_run(args: Vec<String>) -> anyhow::Result<()> {
+    let mut timeout = DEFAULT_BROWSE_TIMEOUT;
+    let mut json_output = false;
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1);
+        match (args[i].as_str(), value) {
+            ("-timeout", Some(value)) => {
+                timeout = parse_duration(value).map_err(|e| anyhow::anyhow!(e))?;
+                i += 1;
+            }
+            ("-json", _) => json_output = true,
+            ("-h" | "--help", _) => {
+                print_discover_help();
+                return Ok(());
+            }
+            (flag, _) => {
+                eprintln!("Error: Unknown option or missing value '{}'\n", flag);
+                print_discover_help();
+                std::process::exit(1);
+            }
+        }
+        i += 1;
+    }
+
+    let servers = browse(timeout, |_| false)?;
+    if json_output {
+        println!("{}", serde_json::to_string_pretty(&servers)?);
+        return Ok(());
+    }
+    if servers.is_empty() {
+        println!(
+            "No servers found on the local network within {}s, servers announce themselves with -mdns",
+            timeout.as_secs_f64()
+        );
+        return Ok(());
+    }
+    let mut table = Table::new();
+    table.set_format(*FORMAT_BOX_CHARS);
+    table.set_titles(row!["Name", "Host", "Addresses", "TCP", "TLS", "Version"]);
+    for server in &servers {
+        let addresses: Vec<String> = server.addresses.iter().map(IpAddr::to_string).collect();
+        table.add_row(row![
+            server.name,
+            server.host,
+            addresses.join("\n"),
+            r->server.tcp_port,
+            r->server.tls_port.map_or_else(|| "-".to_string(), |port| port.to_string()),
+            server.version.as_deref().unwrap_or("-")
+        ]);
+    }
+    table.printstd();
+    Ok(())
+}
+
+pub fn print_discover_help() {
+    println!("nettest discover - Servers on the local network\n");
+    println!("USAGE:");
+    println!("    nettest discover [OPTIONS]\n");
+    println!("Lists the servers announcing _nettest._tcp over mDNS (servers started with -mdns).");
+    println!("'nettest -c -local' measures against the first of them.\n");
+    println!("OPTIONS:");
+    println!("    -timeout SECS   How long to wait for answers (default: 2)");
+    println!("    -json           Print the servers as JSON");
+    println!("    -h, --help      Show this help message");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(name: &str, addresses: &[&str], tls_port: Option<u16>) -> LocalServer {
+        LocalServer {
+            name: name.to_string(),
+            host: "nettest.local.".to_string(),
+            addresses: addresses.iter().map(|address| address.parse().unwrap()).collect(),
+            tcp_port: 5005,
+            tls_port,
+            version: Some("2.0.3".to_string()),
+        }
+    }
+
+    fn servers() -> [LocalServer; 4] {
+        [
+            server("a._nettest._tcp.local.", &[], Some(443)),
+            server("b._nettest._tcp.local.", &["192.168.1.20"], None),
+            server("c._nettest._tcp.local.", &["192.168.1.30", "fe80::1"], Some(8443)),
+            server("d._nettest._tcp.local.", &["fe80::2", "2001:db8::40"], None),
+        ]
+    }
+
+    #[test]
+    fn test_pick_for_transport() {
+        let servers = servers();
+        assert_eq!(pick(&servers, false, AddressFamily::Any).unwrap().0.name, "b._nettest._tcp.local.");
+        assert_eq!(pick(&servers, true, AddressFamily::Any).unwrap().0.name, "c._nettest._tcp.local.");
+        assert!(pick(&servers[..2], true, AddressFamily::Any).is_none());
+    }
+
+    #[test]
+    fn test_pick_skips_link_local() {
+        let servers = servers();
+        let (server, address) = pick(&servers, false, AddressFamily::V6).unwrap();
+        assert_eq!((server.name.as_str(), address.to_string()), ("d._nettest._tcp.local.", "2001:db8::40".to_string()));
+        assert!(pick(&servers[..3], false, AddressFamily::V6).is_none());
+    }
+}
@@ -0,0 +1,323 @@
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use log::debug;
+
+use crate::client::client::{ClientConfig, SharedStats, ThreadMeasurement};
+use crate::client::control_server::{
+    fetch_measurement_servers, get_best_measurement_server, probe_servers, MeasurementSaver, MeasurementServer,
+    ProbedServer,
+};
+use crate::client::observer::{MeasurementObserver, MeasurementPhase, Observers};
+use crate::client::setup::SetupSummary;
+use crate::client::runnner::run_threads;
+use crate::client::state::TestPhase;
+
+/// Speed of a download or upload phase
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Speed {
+    pub bps: f64,
+    pub gbps: f64,
+    pub mbps: f64,
+}
+
+impl From<(f64, f64, f64)> for Speed {
+    /// `(bps, gbps, mbps)` as returned by the calculator
+    fn from((bps, gbps, mbps): (f64, f64, f64)) -> Self {
+        Self { bps, gbps, mbps }
+    }
+}
+
+/// Connection that did not complete the measurement
+#[derive(Debug, Clone)]
+pub struct FailedThread {
+    pub thread_id: usize,
+    /// State the connection was in when it failed
+    pub phase: TestPhase,
+    pub error: String,
+}
+
+/// When a phase ran, relative to the start of the measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseTiming {
+    pub phase: MeasurementPhase,
+    pub start_ns: u64,
+    /// `None` when the phase did not finish
+    pub duration_ns: Option<u64>,
+}
+
+/// Outcome of a measurement, also decides the exit code of `nettest -c`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurementStatus {
+    /// Every connection completed every selected phase
+    Complete,
+    /// Connections failed or were retried, or a selected phase has no value
+    Degraded,
+    /// No selected phase produced a value
+    Failed,
+}
+
+impl MeasurementStatus {
+    /// `measured` tells for each selected phase whether it produced a value
+    pub(crate) fn evaluate(measured: &[bool], threads_failed_or_retried: bool) -> Self {
+        if !measured.iter().any(|measured| *measured) {
+            Self::Failed
+        } else if threads_failed_or_retried || measured.iter().any(|measured| !measured) {
+            Self::Degraded
+        } else {
+            Self::Complete
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Complete => "complete",
+            Self::Degraded => "degraded",
+            Self::Failed => "failed",
+        }
+    }
+
+    /// 0 when complete, 1 when failed, 2 for a partial result
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Self::Complete => 0,
+            Self::Failed => 1,
+            Self::Degraded => 2,
+        }
+    }
+}
+
+/// Result of a complete measurement
+#[derive(Debug, Clone)]
+pub struct MeasurementResult {
+    /// Server as configured, host name or IP address
+    pub server_host: String,
+    pub server: SocketAddr,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    /// Number of connections the measurement was started with
+    pub thread_count: usize,
+    /// Pre-download speed of the first connection with `-t auto`
+    pub pre_test: Option<Speed>,
+    /// Median round trip time in nanoseconds
+    pub ping_median_ns: Option<u64>,
+    /// Every ping round trip time in nanoseconds, in the order they were sent
+    pub ping_samples_ns: Vec<u64>,
+    pub download: Option<Speed>,
+    pub upload: Option<Speed>,
+    /// Connections that completed all phases, failed ones are left out
+    pub threads: Vec<ThreadMeasurement>,
+    pub failed: Vec<FailedThread>,
+    pub timings: Vec<PhaseTiming>,
+    /// DNS, TCP, TLS, WebSocket and first byte times of the connections
+    pub setup: SetupSummary,
+    /// Event loops (OS threads) the connections were spread over
+    pub event_loops: usize,
+    /// Interface the connections were bound to with `-I` or `-B`
+    pub interface: Option<String>,
+    /// Source address of `-B`
+    pub source_address: Option<IpAddr>,
+    pub status: MeasurementStatus,
+    /// Phases repeated on a new connection after a failure, see `-retries`
+    pub retries: usize,
+    /// Set when `save_results` is enabled and the control server rejected the result
+    pub save_error: Option<String>,
+}
+
+impl MeasurementResult {
+    pub fn failed_threads(&self) -> usize {
+        self.thread_count.saturating_sub(self.threads.len())
+    }
+
+    pub fn ping_median_ms(&self) -> Option<f64> {
+        self.ping_median_ns.map(|ns| ns as f64 / 1_000_000.0)
+    }
+
+    pub fn is_degraded(&self) -> bool {
+        self.status != MeasurementStatus::Complete
+    }
+}
+
+#[cfg(test)]
+impl MeasurementResult {
+    /// Complete result of one thread against `server` without any measured
+    /// value, for tests to fill in what they check
+    pub(crate) fn for_test(server_host: &str, server: &str) -> Self {
+        Self {
+            server_host: server_host.to_string(),
+            server: server.parse().unwrap(),
+            started_at: "2026-03-02T10:00:00Z".parse().unwrap(),
+            duration: Duration::from_secs(20),
+            thread_count: 1,
+            pre_test: None,
+            ping_median_ns: None,
+            ping_samples_ns: Vec::new(),
+            download: None,
+            upload: None,
+            threads: Vec::new(),
+            failed: Vec::new(),
+            timings: Vec::new(),
+            setup: SetupSummary::default(),
+            event_loops: 1,
+            interface: None,
+            source_address: None,
+            status: MeasurementStatus::Complete,
+            retries: 0,
+            save_error: None,
+        }
+    }
+}
+
+/// Runs a measurement against an RMBT server, as `nettest -c` does, and returns
+/// the result instead of printing it.
+///
+/// ```no_run
+/// use nettest::client::{client::ClientConfig, measurement::Measurement};
+///
+/// # async fn example() -> anyhow::Result<()> {
+/// let config = ClientConfig {
+///     server: Some("127.0.0.1".to_string()),
+///     ..ClientConfig::default()
+/// };
+/// let result = Measurement::new(config).run().await?;
+/// println!("{:?} Mbit/s", result.download.map(|speed| speed.mbps));
+/// # Ok(())
+/// # }
+/// ```
+pub struct Measurement {
+    config: ClientConfig,
+    observers: Observers,
+}
+
+impl Measurement {
+    pub fn new(config: ClientConfig) -> Self {
+        Self {
+            config,
+            observers: Observers::default(),
+        }
+    }
+
+    /// Adds an observer for progress events, e.g. to update a UI while the
+    /// measurement runs. Observers are called in the order they were added.
+    pub fn observer(mut self, observer: Arc<dyn MeasurementObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Connects `thread_count` connections and runs ping, download and upload.
+    /// Without `server` the nearest server of the control server is used.
+    /// The connections are driven on their own threads, the future only waits
+    /// for them, so it can run on any runtime including `current_thread`.
+    pub async fn run(mut self) -> anyhow::Result<MeasurementResult> {
+        if self.config.thread_count == 0 && !self.config.auto_threads {
+            return Err(anyhow::anyhow!("thread_count must be at least 1"));
+        }
+        self.config.durations.validate().map_err(|e| anyhow::anyhow!(e))?;
+        if self.config.server.is_none() {
+            discover_server(&mut self.config).await?;
+        }
+
+        let stats = Arc::new(Mutex::new(SharedStats::default()));
+        let observers: Arc<dyn MeasurementObserver> = Arc::new(self.observers.clone());
+        let config = self.config.clone();
+        let thread_observers = observers.clone();
+        let mut result = tokio::task::spawn_blocking(move || run_threads(config, stats, thread_observers))
+            .await
+            .map_err(|e| anyhow::anyhow!("Measurement aborted: {}", e))??;
+
+        if self.config.save_results {
+            let envelopes = result.threads.iter().map(|t| t.envelope.clone()).collect();
+            let mut measurement_saver = MeasurementSaver::new(&self.config);
+            if let Err(e) = measurement_saver
+                .save_measurement_with_speeds(
+                    result.ping_median_ns,
+                    result.download.map(|speed| speed.mbps),
+                    result.upload.map(|speed| speed.mbps),
+                    envelopes,
+                    &result.setup,
+                )
+                .await
+            {
+                result.save_error = Some(e.to_string());
+            }
+        }
+        observers.on_result(&result);
+        Ok(result)
+    }
+}
+
+/// Sets `server` and the ports to the measurement server known to the control
+/// server with the fastest connect, see `-probe`
+pub async fn discover_server(config: &mut ClientConfig) -> anyhow::Result<()> {
+    debug!("No server address provided, using default");
+    //TODO: verify tls
+    let server = get_best_measurement_server(
+        &config.x_nettest_client,
+        &config.control_server,
+        &config.server_filter,
+        config.probe_servers,
+        config.use_tls,
+        &config.source,
+    )
+    .await?
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "No server found. Probably no running servers of version {} or higher matching the filters",
+            config.server_filter.min_version
+        )
+    })?;
+    let address = server
+        .address()
+        .ok_or_else(|| anyhow::anyhow!("Server {} has no address", server.name))?;
+    config.server = Some(address);
+    if let Some((port, tls_port)) = server.rmbt_ports() {
+        config.port = port;
+        config.tls_port = tls_port;
+    }
+    Ok(())
+}
+
+/// `-list-servers`: the servers matching the filters, in the order
+/// auto-selection prefers them. The `probe_servers` nearest are probed.
+pub async fn list_servers(config: &ClientConfig) -> anyhow::Result<Vec<ProbedServer>> {
+    let servers = fetch_measurement_servers(&config.x_nettest_client, &config.control_server, &config.source).await?;
+    let servers: Vec<MeasurementServer> =
+        servers.into_iter().filter(|server| config.server_filter.matches(server)).collect();
+    Ok(probe_servers(servers, config.probe_servers, config.use_tls, &config.source).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::setup::ConnectionSetup;
+
+    #[test]
+    fn test_status_from_measured_phases() {
+        assert_eq!(MeasurementStatus::evaluate(&[true, true, true], false), MeasurementStatus::Complete);
+        assert_eq!(MeasurementStatus::evaluate(&[true, true], true), MeasurementStatus::Degraded);
+        assert_eq!(MeasurementStatus::evaluate(&[true, false, true], false), MeasurementStatus::Degraded);
+        assert_eq!(MeasurementStatus::evaluate(&[false, false], true), MeasurementStatus::Failed);
+        assert_eq!(MeasurementStatus::Degraded.exit_code(), 2);
+    }
+
+    #[test]
+    fn test_failed_threads() {
+        let mut result = MeasurementResult::for_test("localhost", "127.0.0.1:5005");
+        assert_eq!(result.failed_threads(), 1);
+        // More recorded connections than the count never underflows
+        result.thread_count = 0;
+        result.threads.push(ThreadMeasurement {
+            measurements: vec![],
+            failed: false,
+            thread_id: 0,
+            phase: TestPhase::PerfCompleted,
+            upload_measurements: vec![],
+            envelope: None,
+            chunk_size: 4096,
+            setup: ConnectionSetup::default(),
+        });
+        assert_eq!(result.failed_threads(), 0);
+    }
+}
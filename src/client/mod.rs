@@ -1,9 +1,23 @@
+pub mod auto_threads;
+pub mod check;
 pub mod client;
 pub mod constants;
+pub mod dns;
+pub mod durations;
+pub mod engine;
+pub mod export;
 pub mod globals;
 pub mod handlers;
+pub mod history;
+pub mod mdns;
+pub mod measurement;
+pub mod observer;
+pub mod phases;
 pub mod print;
 mod runnner;
+pub mod schedule;
+pub mod setup;
+pub mod source;
 pub mod state;
 pub mod calculator;
 pub mod args_parser;
@@ -0,0 +1,145 @@
+//! Progress events of a running measurement. The command line output is built
+//! on these as well, see `print::printer` and `print::graph_service`.
+
+use std::sync::Arc;
+
+use crate::client::measurement::{MeasurementResult, Speed};
+use crate::client::state::TestPhase;
+
+/// Phases as reported to observers, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MeasurementPhase {
+    /// Connection setup and greeting on every thread
+    Connect,
+    /// GETCHUNKS, finds the chunk size
+    PreDownload,
+    /// PING on the first thread
+    Ping,
+    /// GETTIME
+    Download,
+    /// PUTTIMERESULT, or PUT with `-legacy`
+    Upload,
+    /// Signed result, only requested when results are saved
+    SignedResult,
+}
+
+impl MeasurementPhase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MeasurementPhase::Connect => "connect",
+            MeasurementPhase::PreDownload => "pre_download",
+            MeasurementPhase::Ping => "ping",
+            MeasurementPhase::Download => "download",
+            MeasurementPhase::Upload => "upload",
+            MeasurementPhase::SignedResult => "signed_result",
+        }
+    }
+}
+
+impl From<&TestPhase> for MeasurementPhase {
+    fn from(phase: &TestPhase) -> Self {
+        use TestPhase::*;
+        match phase {
+            GreetingSendConnectionType | GreetingSendToken | GreetingReceiveGreeting
+            | GreetingReceiveResponse | GreetingCompleted => MeasurementPhase::Connect,
+            GetChunksSendChunksCommand | GetChunksReceiveChunk | GetChunksSendOk
+            | GetChunksReceiveTime | GetChunksCompleted => MeasurementPhase::PreDownload,
+            PingSendPing | PingReceivePong | PingSendOk | PingReceiveTime | PingCompleted => {
+                MeasurementPhase::Ping
+            }
+            GetTimeSendCommand | GetTimeReceiveChunk | GetTimeSendOk | GetTimeReceiveTime
+            | GetTimeCompleted => MeasurementPhase::Download,
+            PerfSendCommand | PerfReceiveOk | PerfSendChunks | PerfSendLastChunk
+            | PerfReceiveTime | PerfCompleted | PutSendCommand | PutReceiveOk | PutSendChunks
+            | PutReceiveTimeBytes | PutSendLastChunk | PutReceiveFinalTime | PutCompleted => {
+                MeasurementPhase::Upload
+            }
+            SignedResultSend | SignedResultReceive | SignedResultSendOk
+            | SignedResultCompleted => MeasurementPhase::SignedResult,
+        }
+    }
+}
+
+/// What a finished phase measured
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhaseResult {
+    Completed,
+    Ping { median_ns: u64 },
+    /// Download or upload speed over all threads
+    Throughput(Speed),
+}
+
+/// Bytes transferred by one thread so far in the download or upload phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThroughputSample {
+    pub thread_id: usize,
+    pub phase: MeasurementPhase,
+    /// Time since the start of the phase on this thread
+    pub elapsed_ns: u64,
+    pub bytes: u64,
+}
+
+/// Callbacks for the events of a measurement. All methods default to doing
+/// nothing. They are called from the measurement threads, so implementations
+/// should return quickly.
+pub trait MeasurementObserver: Send + Sync {
+    fn on_phase_started(&self, _phase: MeasurementPhase) {}
+
+    fn on_phase_finished(&self, _phase: MeasurementPhase, _result: &PhaseResult) {}
+
+    /// Sent about every `THROUGHPUT_SAMPLE_INTERVAL` per thread
+    fn on_throughput_sample(&self, _sample: &ThroughputSample) {}
+
+    fn on_ping_sample(&self, _thread_id: usize, _rtt_ns: u64) {}
+
+    /// `-t auto` chose the number of connections, from the pre-download speed
+    /// unless no throughput phase runs
+    fn on_thread_count_selected(&self, _thread_count: usize, _pre_test: Option<Speed>) {}
+
+    /// The thread is left out of the result
+    fn on_thread_failed(&self, _thread_id: usize, _phase: MeasurementPhase, _error: &str) {}
+
+    fn on_result(&self, _result: &MeasurementResult) {}
+}
+
+/// Forwards every event to all observers in the order they were added
+#[derive(Default, Clone)]
+pub struct Observers(Vec<Arc<dyn MeasurementObserver>>);
+
+impl Observers {
+    pub fn push(&mut self, observer: Arc<dyn MeasurementObserver>) {
+        self.0.push(observer);
+    }
+}
+
+impl MeasurementObserver for Observers {
+    fn on_phase_started(&self, phase: MeasurementPhase) {
+        self.0.iter().for_each(|o| o.on_phase_started(phase));
+    }
+
+    fn on_phase_finished(&self, phase: MeasurementPhase, result: &PhaseResult) {
+        self.0.iter().for_each(|o| o.on_phase_finished(phase, result));
+    }
+
+    fn on_throughput_sample(&self, sample: &ThroughputSample) {
+        self.0.iter().for_each(|o| o.on_throughput_sample(sample));
+    }
+
+    fn on_ping_sample(&self, thread_id: usize, rtt_ns: u64) {
+        self.0.iter().for_each(|o| o.on_ping_sample(thread_id, rtt_ns));
+    }
+
+    fn on_thread_count_selected(&self, thread_count: usize, pre_test: Option<Speed>) {
+        self.0
+            .iter()
+            .for_each(|o| o.on_thread_count_selected(thread_count, pre_test));
+    }
+
+    fn on_thread_failed(&self, thread_id: usize, phase: MeasurementPhase, error: &str) {
+        self.0.iter().for_each(|o| o.on_thread_failed(thread_id, phase, error));
+    }
+
+    fn on_result(&self, result: &MeasurementResult) {
+        self.0.iter().for_each(|o| o.on_result(result));
+    }
+}
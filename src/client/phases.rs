@@ -0,0 +1,115 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::client::observer::MeasurementPhase;
+
+/// Phases selected with `-phases` / `client_phases`. The greeting always runs,
+/// the pre-download only when download or upload need its chunk size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PhaseSelection {
+    pub ping: bool,
+    pub download: bool,
+    pub upload: bool,
+}
+
+impl Default for PhaseSelection {
+    fn default() -> Self {
+        Self {
+            ping: true,
+            download: true,
+            upload: true,
+        }
+    }
+}
+
+impl PhaseSelection {
+    pub fn pre_download(&self) -> bool {
+        self.download || self.upload
+    }
+
+    /// Whether `phase` runs with this selection; the signed result additionally
+    /// depends on `-save -signed`
+    pub fn contains(&self, phase: MeasurementPhase) -> bool {
+        match phase {
+            MeasurementPhase::Connect | MeasurementPhase::SignedResult => true,
+            MeasurementPhase::PreDownload => self.pre_download(),
+            MeasurementPhase::Ping => self.ping,
+            MeasurementPhase::Download => self.download,
+            MeasurementPhase::Upload => self.upload,
+        }
+    }
+
+    /// Selected measurement phases in the order they run
+    pub fn names(&self) -> Vec<&'static str> {
+        [
+            (self.ping, MeasurementPhase::Ping),
+            (self.download, MeasurementPhase::Download),
+            (self.upload, MeasurementPhase::Upload),
+        ]
+        .into_iter()
+        .filter(|(selected, _)| *selected)
+        .map(|(_, phase)| phase.as_str())
+        .collect()
+    }
+}
+
+impl FromStr for PhaseSelection {
+    type Err = String;
+
+    /// Comma separated list of `ping`, `download` and `upload`, or `all`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut selection = Self {
+            ping: false,
+            download: false,
+            upload: false,
+        };
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match name.to_ascii_lowercase().as_str() {
+                "ping" => selection.ping = true,
+                "download" => selection.download = true,
+                "upload" => selection.upload = true,
+                "all" => selection = Self::default(),
+                _ => {
+                    return Err(format!(
+                        "unknown phase '{}', expected ping, download, upload or all",
+                        name
+                    ))
+                }
+            }
+        }
+        if selection.names().is_empty() {
+            return Err("at least one of ping, download and upload must be selected".to_string());
+        }
+        Ok(selection)
+    }
+}
+
+impl fmt::Display for PhaseSelection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.names().join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phase_list() {
+        let upload_only: PhaseSelection = "upload".parse().unwrap();
+        assert!(!upload_only.ping && !upload_only.download && upload_only.upload);
+        assert!(upload_only.contains(MeasurementPhase::PreDownload));
+
+        let ping_only: PhaseSelection = " Ping ".parse().unwrap();
+        assert!(!ping_only.contains(MeasurementPhase::PreDownload));
+        assert_eq!(ping_only.to_string(), "ping");
+
+        assert_eq!("download,all".parse::<PhaseSelection>().unwrap(), PhaseSelection::default());
+    }
+
+    #[test]
+    fn test_parse_phase_list_invalid() {
+        assert!("".parse::<PhaseSelection>().is_err());
+        assert!("ping,jitter".parse::<PhaseSelection>().is_err());
+    }
+}
@@ -0,0 +1,171 @@
+//! `-dualstack`: the IPv4 and IPv6 measurements side by side, as a table or
+//! as one JSON document holding both results.
+
+use prettytable::format::consts::FORMAT_BOX_CHARS;
+use prettytable::{row, Table};
+use serde_json::{json, Value};
+
+use crate::client::client::ClientConfig;
+use crate::client::measurement::MeasurementResult;
+use crate::client::print::json_output::{result_to_json, JSON_SCHEMA_VERSION};
+use crate::client::source::AddressFamily;
+
+/// Outcome of the measurement over one family, the error if it did not run
+pub type FamilyRun = (AddressFamily, Result<MeasurementResult, String>);
+
+type Metric = fn(&MeasurementResult) -> Option<f64>;
+
+/// Ping, download and upload
+const METRICS: [Metric; 3] = [
+    MeasurementResult::ping_median_ms,
+    |result| result.download.map(|speed| speed.mbps),
+    |result| result.upload.map(|speed| speed.mbps),
+];
+
+/// IPv6 against IPv4: the ping difference in ms, the download and upload
+/// difference in percent of IPv4
+fn differences(ipv4: Result<&MeasurementResult, &str>, ipv6: Result<&MeasurementResult, &str>) -> [Option<f64>; 3] {
+    let mut differences = [None; 3];
+    for (index, metric) in METRICS.into_iter().enumerate() {
+        differences[index] = match (ipv4.ok().and_then(metric), ipv6.ok().and_then(metric)) {
+            (Some(v4), Some(v6)) if index == 0 => Some(v6 - v4),
+            (Some(v4), Some(v6)) if v4 > 0.0 => Some((v6 - v4) / v4 * 100.0),
+            _ => None,
+        };
+    }
+    differences
+}
+
+/// Rows of the comparison: metric, IPv4, IPv6 and the difference of IPv6 to
+/// IPv4
+fn comparison_rows(ipv4: Result<&MeasurementResult, &str>, ipv6: Result<&MeasurementResult, &str>) -> Vec<[String; 4]> {
+    let text = |value: Option<f64>| value.map_or_else(|| "-".to_string(), |value| format!("{:.2}", value));
+    let mut rows = vec![
+        [
+            "Address".to_string(),
+            ipv4.map_or_else(|e| format!("failed: {}", e), |result| result.server.to_string()),
+            ipv6.map_or_else(|e| format!("failed: {}", e), |result| result.server.to_string()),
+            String::new(),
+        ],
+        [
+            "Status".to_string(),
+            ipv4.map_or("-", |result| result.status.as_str()).to_string(),
+            ipv6.map_or("-", |result| result.status.as_str()).to_string(),
+            String::new(),
+        ],
+    ];
+
+    let differences = differences(ipv4, ipv6);
+    for (index, (name, metric)) in ["Ping (ms)", "Download (Mbit/s)", "Upload (Mbit/s)"]
+        .into_iter()
+        .zip(METRICS)
+        .enumerate()
+    {
+        let difference = match differences[index] {
+            Some(ms) if index == 0 => format!("{:+.2} ms", ms),
+            Some(percent) => format!("{:+.1} %", percent),
+            None => "-".to_string(),
+        };
+        rows.push([
+            name.to_string(),
+            text(ipv4.ok().and_then(metric)),
+            text(ipv6.ok().and_then(metric)),
+            difference,
+        ]);
+    }
+    rows
+}
+
+fn run_of(runs: &[FamilyRun], family: AddressFamily) -> Result<&MeasurementResult, &str> {
+    match runs.iter().find(|(run_family, _)| *run_family == family) {
+        Some((_, Ok(result))) => Ok(result),
+        Some((_, Err(e))) => Err(e),
+        None => Err("not run"),
+    }
+}
+
+pub fn print_comparison(runs: &[FamilyRun]) {
+    let mut table = Table::new();
+    table.set_format(*FORMAT_BOX_CHARS);
+    table.set_titles(row!["", "IPv4", "IPv6", "IPv6 vs IPv4"]);
+    for [metric, ipv4, ipv6, difference] in comparison_rows(run_of(runs, AddressFamily::V4), run_of(runs, AddressFamily::V6)) {
+        table.add_row(row![metric, r->ipv4, r->ipv6, r->difference]);
+    }
+    table.printstd();
+}
+
+/// `-dualstack -raw`: the differences of IPv6 to IPv4 as
+/// `ping_ms/download_percent/upload_percent`, below the line of each family
+pub fn raw_comparison(runs: &[FamilyRun]) -> String {
+    let differences = differences(run_of(runs, AddressFamily::V4), run_of(runs, AddressFamily::V6));
+    let text = |value: Option<f64>| value.map_or_else(|| "-".to_string(), |value| format!("{:+.2}", value));
+    format!("{}/{}/{}", text(differences[0]), text(differences[1]), text(differences[2]))
+}
+
+/// `-dualstack -json`: the `-json` document of each family, or its error
+pub fn comparison_to_json(config: &ClientConfig, runs: &[FamilyRun]) -> Value {
+    let document = |family: AddressFamily| match run_of(runs, family) {
+        Ok(result) => result_to_json(config, result),
+        Err(e) => json!({ "error": e }),
+    };
+    json!({
+        "schema_version": JSON_SCHEMA_VERSION,
+        "ipv4": document(AddressFamily::V4),
+        "ipv6": document(AddressFamily::V6),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::measurement::Speed;
+
+    fn result(server: &str, ping_ms: u64, download_mbps: f64) -> MeasurementResult {
+        MeasurementResult {
+            thread_count: 3,
+            ping_median_ns: Some(ping_ms * 1_000_000),
+            download: Some(Speed::from((download_mbps * 1e6, download_mbps / 1000.0, download_mbps))),
+            ..MeasurementResult::for_test("probe.example.com", server)
+        }
+    }
+
+    fn ipv4() -> MeasurementResult {
+        result("192.0.2.1:5005", 10, 400.0)
+    }
+
+    fn ipv6() -> MeasurementResult {
+        result("[2001:db8::1]:5005", 12, 500.0)
+    }
+
+    #[test]
+    fn test_comparison_rows() {
+        let (ipv4, ipv6) = (ipv4(), ipv6());
+        let rows = comparison_rows(Ok(&ipv4), Ok(&ipv6));
+        assert_eq!(rows[0][2], "[2001:db8::1]:5005");
+        assert_eq!(rows[2], ["Ping (ms)", "10.00", "12.00", "+2.00 ms"]);
+        assert_eq!(rows[3], ["Download (Mbit/s)", "400.00", "500.00", "+25.0 %"]);
+        assert_eq!(rows[4], ["Upload (Mbit/s)", "-", "-", "-"]);
+    }
+
+    #[test]
+    fn test_comparison_rows_with_failed_family() {
+        let ipv4 = ipv4();
+        let rows = comparison_rows(Ok(&ipv4), Err("probe.example.com has no IPv6 address"));
+        assert_eq!(rows[0][2], "failed: probe.example.com has no IPv6 address");
+        assert_eq!(rows[3], ["Download (Mbit/s)", "400.00", "-", "-"]);
+    }
+
+    #[test]
+    fn test_raw_comparison() {
+        let runs = vec![(AddressFamily::V4, Ok(ipv4())), (AddressFamily::V6, Ok(ipv6()))];
+        assert_eq!(raw_comparison(&runs), "+2.00/+25.00/-");
+    }
+
+    #[test]
+    fn test_comparison_to_json() {
+        let runs = vec![(AddressFamily::V4, Ok(ipv4())), (AddressFamily::V6, Err("timeout".to_string()))];
+        let document = comparison_to_json(&ClientConfig::default(), &runs);
+        assert_eq!(document["ipv4"]["server"]["address"], "192.0.2.1:5005");
+        assert_eq!(document["ipv6"]["error"], "timeout");
+    }
+}
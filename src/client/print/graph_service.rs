@@ -2,7 +2,9 @@ use log::debug;
 use textplots::Plot;
 use textplots::{Chart, Shape};
 
-use crate::client::client::Measurement;
+use crate::client::client::ThreadMeasurement;
+use crate::client::measurement::MeasurementResult as FinalResult;
+use crate::client::observer::MeasurementObserver;
 
 #[derive(Debug, Clone)]
 pub struct MeasurementResult {
@@ -10,10 +12,17 @@ pub struct MeasurementResult {
     pub measurements: Vec<(u64, u64)>, // (time_ns, bytes)
 }
 
+/// `-g`: download and upload graphs once the measurement is complete
 pub struct GraphService;
 
+impl MeasurementObserver for GraphService {
+    fn on_result(&self, result: &FinalResult) {
+        Self::print_graph(&result.threads);
+    }
+}
+
 impl GraphService {
-    pub fn print_graph(state_refs: &Vec<Measurement>) {
+    pub fn print_graph(state_refs: &Vec<ThreadMeasurement>) {
         let download_results: Vec<MeasurementResult> = state_refs
             .iter()
             .enumerate()
@@ -0,0 +1,183 @@
+//! `-json`: the complete result as one JSON document. The schema is described
+//! in JSON_OUTPUT.md; bump `JSON_SCHEMA_VERSION` on incompatible changes.
+
+use serde_json::{json, Value};
+
+use crate::client::client::ClientConfig;
+use crate::client::measurement::{MeasurementResult, Speed};
+use crate::client::observer::MeasurementObserver;
+
+pub const JSON_SCHEMA_VERSION: u32 = 1;
+
+fn speed_json(speed: Option<Speed>) -> Value {
+    match speed {
+        Some(speed) => json!({
+            "bps": speed.bps,
+            "mbps": speed.mbps,
+            "gbps": speed.gbps,
+        }),
+        None => Value::Null,
+    }
+}
+
+/// Builds the `-json` document for a finished measurement
+pub fn result_to_json(config: &ClientConfig, result: &MeasurementResult) -> Value {
+    let threads: Vec<Value> = result
+        .threads
+        .iter()
+        .map(|thread| {
+            json!({
+                "thread_id": thread.thread_id,
+                "chunk_size": thread.chunk_size,
+                "download_measurements": thread.measurements,
+                "upload_measurements": thread.upload_measurements,
+                "signed_envelope": thread.envelope,
+                "setup": thread.setup,
+            })
+        })
+        .collect();
+    let failed_threads: Vec<Value> = result
+        .failed
+        .iter()
+        .map(|thread| {
+            json!({
+                "thread_id": thread.thread_id,
+                "phase": format!("{:?}", thread.phase),
+                "error": thread.error,
+            })
+        })
+        .collect();
+    let timings: Vec<Value> = result
+        .timings
+        .iter()
+        .map(|timing| {
+            json!({
+                "phase": timing.phase.as_str(),
+                "start_ns": timing.start_ns,
+                "duration_ns": timing.duration_ns,
+            })
+        })
+        .collect();
+
+    json!({
+        "schema_version": JSON_SCHEMA_VERSION,
+        "client": {
+            "version": env!("CARGO_PKG_VERSION"),
+            "uuid": config.client_uuid,
+            "git_hash": config.git_hash,
+        },
+        "started_at": result
+            .started_at
+            .to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
+        "duration_ns": result.duration.as_nanos() as u64,
+        "status": result.status.as_str(),
+        "degraded": result.is_degraded(),
+        "retries": result.retries,
+        "server": {
+            "host": result.server_host,
+            "address": result.server.to_string(),
+        },
+        "source": {
+            "interface": result.interface,
+            "address": result.source_address,
+        },
+        "transport": config.transport(),
+        "legacy_upload": config.legacy,
+        "thread_count": result.thread_count,
+        "event_loops": result.event_loops,
+        "auto_threads": config.auto_threads,
+        "pre_test": speed_json(result.pre_test),
+        "phases": config.phases.names(),
+        "ping": {
+            "median_ns": result.ping_median_ns,
+            "samples_ns": result.ping_samples_ns,
+        },
+        "download": speed_json(result.download),
+        "upload": speed_json(result.upload),
+        "threads": threads,
+        "failed_threads": failed_threads,
+        "timings": timings,
+        "setup": result.setup,
+        "save_error": result.save_error,
+    })
+}
+
+/// Prints the JSON document once the measurement is complete
+pub struct JsonPrinter {
+    config: ClientConfig,
+}
+
+impl JsonPrinter {
+    pub fn new(config: ClientConfig) -> Self {
+        Self { config }
+    }
+}
+
+impl MeasurementObserver for JsonPrinter {
+    fn on_result(&self, result: &MeasurementResult) {
+        let document = result_to_json(&self.config, result);
+        match serde_json::to_string_pretty(&document) {
+            Ok(document) => println!("{}", document),
+            Err(e) => eprintln!("Failed to serialize result: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::client::ThreadMeasurement;
+    use crate::client::measurement::{FailedThread, PhaseTiming};
+    use crate::client::setup::ConnectionSetup;
+    use crate::client::observer::MeasurementPhase;
+    use crate::client::state::TestPhase;
+
+    #[test]
+    fn test_document_follows_schema() {
+        let config = ClientConfig {
+            use_tls: true,
+            ..ClientConfig::default()
+        };
+        let result = MeasurementResult {
+            thread_count: 2,
+            ping_median_ns: Some(1_500_000),
+            ping_samples_ns: vec![1_400_000, 1_500_000, 1_600_000],
+            download: Some(Speed::from((8e8, 0.8, 800.0))),
+            threads: vec![ThreadMeasurement {
+                measurements: vec![(1_000, 4096), (2_000, 8192)],
+                failed: false,
+                thread_id: 0,
+                phase: TestPhase::PerfCompleted,
+                upload_measurements: vec![],
+                envelope: None,
+                chunk_size: 4096,
+                setup: ConnectionSetup::default(),
+            }],
+            failed: vec![FailedThread {
+                thread_id: 1,
+                phase: TestPhase::GreetingReceiveGreeting,
+                error: "timeout".to_string(),
+            }],
+            timings: vec![PhaseTiming {
+                phase: MeasurementPhase::Download,
+                start_ns: 10,
+                duration_ns: None,
+            }],
+            ..MeasurementResult::for_test("example.com", "192.0.2.1:443")
+        };
+
+        let document = result_to_json(&config, &result);
+
+        assert_eq!(document["schema_version"], JSON_SCHEMA_VERSION);
+        assert_eq!(document["transport"], "tls");
+        assert_eq!(document["status"], "complete");
+        assert_eq!(document["server"]["address"], "192.0.2.1:443");
+        assert_eq!(document["ping"]["samples_ns"].as_array().unwrap().len(), 3);
+        assert_eq!(document["download"]["bps"], 8e8);
+        assert!(document["upload"].is_null());
+        assert_eq!(document["threads"][0]["download_measurements"][1][1], 8192);
+        assert_eq!(document["failed_threads"][0]["phase"], "GreetingReceiveGreeting");
+        assert_eq!(document["timings"][0]["phase"], "download");
+        assert!(document["timings"][0]["duration_ns"].is_null());
+    }
+}
@@ -1,2 +1,5 @@
+pub mod comparison;
 pub mod graph_service;
-pub mod printer;
\ No newline at end of file
+pub mod json_output;
+pub mod printer;
+pub mod server_list;
\ No newline at end of file
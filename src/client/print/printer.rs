@@ -1,6 +1,10 @@
 use prettytable::format::{FormatBuilder, LinePosition, LineSeparator, TableFormat};
 use prettytable::{row, Table};
 
+use crate::client::measurement::{MeasurementResult, Speed};
+use crate::client::observer::{MeasurementObserver, MeasurementPhase, PhaseResult};
+use crate::client::phases::PhaseSelection;
+
 const GREEN: &str = "\x1b[32m";
 const RESET: &str = "\x1b[0m";
 
@@ -44,13 +48,6 @@ pub fn print_test_result(phase: &str, status: &str, speed: Option<(f64, f64, f64
     print_row(phase, &result, is_last);
 }
 
-pub fn print_raw_results(ping_ms: f64, download_gbps: f64, upload_gbps: f64) {
-    println!(
-        "ping/{:.2}/download/{:.2}/upload/{:.2}",
-        ping_ms, download_gbps, upload_gbps
-    );
-}
-
 pub fn print_test_header() {
     let title = "Nettest Broadband Test";
     let padding = (TABLE_WIDTH - title.len()) / 2;
@@ -85,14 +82,6 @@ pub fn print_test_header() {
     print!("{}", table);
 }
 
-pub fn print_result(phase: &str, status: &str, speed: Option<usize>, is_last: bool) {
-    let result = match speed {
-        Some(mbps) => format!("{} - {:.2} ", status, mbps),
-        None => status.to_string(),
-    };
-    print_row(phase, &result, is_last);
-}
-
 pub fn print_float_result(phase: &str, status: &str, speed: Option<f64>, is_last: bool) {
     let result = match speed {
         Some(mbps) => format!("{:.2} {}", mbps, status),
@@ -100,3 +89,91 @@ pub fn print_float_result(phase: &str, status: &str, speed: Option<f64>, is_last
     };
     print_row(phase, &result, is_last);
 }
+
+fn print_thread_failure(thread_id: usize, phase: MeasurementPhase, error: &str) {
+    if phase == MeasurementPhase::Connect {
+        println!("Thread {} could not connect to the server. {}", thread_id, error);
+    } else {
+        println!("Thread {} failed during {}: {}", thread_id, phase.as_str(), error);
+    }
+}
+
+/// Result table of the command line client, with rows for the selected phases
+#[derive(Default)]
+pub struct TablePrinter {
+    phases: PhaseSelection,
+}
+
+impl TablePrinter {
+    pub fn new(phases: PhaseSelection) -> Self {
+        Self { phases }
+    }
+}
+
+impl MeasurementObserver for TablePrinter {
+    fn on_phase_started(&self, phase: MeasurementPhase) {
+        if phase == MeasurementPhase::Connect {
+            print_test_header();
+        }
+    }
+
+    fn on_thread_count_selected(&self, thread_count: usize, pre_test: Option<Speed>) {
+        let result = match pre_test {
+            Some(speed) => format!("{} (pre-test {:.2} Mbit/s)", thread_count, speed.mbps),
+            None => thread_count.to_string(),
+        };
+        print_row("Threads (auto)", &result, false);
+    }
+
+    fn on_phase_finished(&self, phase: MeasurementPhase, result: &PhaseResult) {
+        match (phase, result) {
+            (MeasurementPhase::Ping, PhaseResult::Ping { median_ns }) => {
+                let ping_ms = *median_ns as f64 / 1_000_000.0;
+                let is_last = !self.phases.download && !self.phases.upload;
+                print_float_result("Ping Median", "ms", Some(ping_ms), is_last);
+            }
+            (MeasurementPhase::Download, PhaseResult::Throughput(speed)) => {
+                let speed = (speed.bps, speed.gbps, speed.mbps);
+                print_test_result("Download Test", "Completed", Some(speed), !self.phases.upload);
+            }
+            (MeasurementPhase::Upload, PhaseResult::Throughput(speed)) => {
+                let speed = (speed.bps, speed.gbps, speed.mbps);
+                print_test_result("Upload Test", "Completed", Some(speed), true);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_thread_failed(&self, thread_id: usize, phase: MeasurementPhase, error: &str) {
+        print_thread_failure(thread_id, phase, error);
+    }
+
+    fn on_result(&self, result: &MeasurementResult) {
+        let setup = result.setup.describe();
+        if !setup.is_empty() {
+            println!("Connection setup: {}", setup);
+        }
+    }
+}
+
+/// `-raw`: `ping/download/upload` in ms and Gbit/s on one line, `-` for
+/// phases that were skipped
+pub struct RawPrinter;
+
+impl MeasurementObserver for RawPrinter {
+    fn on_result(&self, result: &MeasurementResult) {
+        let value = |value: Option<f64>| {
+            value.map_or_else(|| "-".to_string(), |value| format!("{:.2}", value))
+        };
+        println!(
+            "{}/{}/{}",
+            value(result.ping_median_ms()),
+            value(result.download.map(|speed| speed.gbps)),
+            value(result.upload.map(|speed| speed.gbps)),
+        );
+    }
+
+    fn on_thread_failed(&self, thread_id: usize, phase: MeasurementPhase, error: &str) {
+        print_thread_failure(thread_id, phase, error);
+    }
+}
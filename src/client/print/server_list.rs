@@ -0,0 +1,53 @@
+//! `-list-servers`: the servers auto-selection chooses from, fastest first.
+
+use prettytable::format::consts::FORMAT_BOX_CHARS;
+use prettytable::{row, Table};
+
+use crate::client::client::ClientConfig;
+use crate::client::control_server::{Probe, ProbedServer};
+
+pub fn print_server_list(config: &ClientConfig, servers: &[ProbedServer]) {
+    if servers.is_empty() {
+        println!("No server matches the filters");
+        return;
+    }
+    let mut table = Table::new();
+    table.set_format(*FORMAT_BOX_CHARS);
+    table.set_titles(row!["", "Name", "City", "Provider", "Address", "Port", "Distance", "Connect ms", "Version", "Flags"]);
+    for (index, ProbedServer { server, probe }) in servers.iter().enumerate() {
+        let provider = server
+            .provider
+            .as_ref()
+            .map_or_else(|| "-".to_string(), |provider| format!("{} ({})", provider.name, provider.country));
+        let port = server
+            .rmbt_ports()
+            .map_or_else(|| "-".to_string(), |(port, tls_port)| if config.use_tls { tls_port } else { port }.to_string());
+        let connect = match probe {
+            Probe::Rtt(rtt) => format!("{:.2}", rtt.as_secs_f64() * 1000.0),
+            Probe::NotProbed => "-".to_string(),
+            Probe::Unreachable => "unreachable".to_string(),
+        };
+        let flags: Vec<&str> = [(server.on_net, "on-net"), (server.dedicated, "dedicated"), (server.ip_v6_support, "IPv6")]
+            .into_iter()
+            .filter_map(|(set, flag)| set.then_some(flag))
+            .collect();
+        table.add_row(row![
+            if index == 0 { "*" } else { "" },
+            server.name,
+            server.city,
+            provider,
+            server.address().unwrap_or_else(|| "-".to_string()),
+            r->port,
+            r->format!("{:.0}", server.distance),
+            r->connect,
+            server.version.as_deref().unwrap_or("-"),
+            flags.join(" ")
+        ]);
+    }
+    table.printstd();
+    println!(
+        "{} servers match, the {} nearest were probed, * is used without -c",
+        servers.len(),
+        config.probe_servers.min(servers.len())
+    );
+}
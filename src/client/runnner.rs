@@ -1,246 +1,620 @@
 use std::{
-    sync::{Arc, Barrier},
+    any::Any,
+    collections::BTreeMap,
+    net::{IpAddr, SocketAddr},
+    panic::{catch_unwind, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Barrier,
+    },
     thread,
+    time::{Duration, Instant},
 };
 
-use crate::config::parser::parse_listen_address;
 use std::sync::Mutex;
 
-use log::{debug, info};
+use log::{debug, info, warn};
 
 use crate::client::{
     calculator::{
         calculate_download_speed_from_stats_silent, calculate_upload_speed_from_stats_silent,
     },
-    client::{ClientConfig, Measurement, SharedStats},
-    control_server::MeasurementSaver,
-    print::printer::{print_float_result, print_test_result},
-    state::TestState,
+    client::{ClientConfig, ThreadMeasurement, SharedStats},
+    measurement::{FailedThread, MeasurementResult, MeasurementStatus, PhaseTiming, Speed},
+    observer::{MeasurementObserver, MeasurementPhase, Observers, PhaseResult},
+    engine::{loop_count, loop_thread_ids, ConnectionFailure, EventLoop},
+    setup::{ConnectionSetup, SetupSummary},
+    state::TestPhase,
 };
 
-pub async fn run_threads(
+/// Records when each phase started and finished
+struct PhaseTimer {
+    start: Instant,
+    timings: Mutex<Vec<PhaseTiming>>,
+}
+
+impl MeasurementObserver for PhaseTimer {
+    fn on_phase_started(&self, phase: MeasurementPhase) {
+        self.timings.lock().unwrap().push(PhaseTiming {
+            phase,
+            start_ns: self.start.elapsed().as_nanos() as u64,
+            duration_ns: None,
+        });
+    }
+
+    fn on_phase_finished(&self, phase: MeasurementPhase, _result: &PhaseResult) {
+        let now_ns = self.start.elapsed().as_nanos() as u64;
+        if let Some(timing) = self
+            .timings
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .rev()
+            .find(|timing| timing.phase == phase)
+        {
+            timing.duration_ns = Some(now_ns - timing.start_ns);
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match (panic.downcast_ref::<&str>(), panic.downcast_ref::<String>()) {
+        (Some(message), _) => format!("thread panicked: {}", message),
+        (_, Some(message)) => format!("thread panicked: {}", message),
+        _ => "thread panicked".to_string(),
+    }
+}
+
+/// Pause before reconnecting, gives a restarting server or network time to recover
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// The barrier the event loops meet at between phases. A loop that panics
+/// outside of a phase, e.g. in an observer, still waits at the barriers it
+/// has left while unwinding, so the other loops are not blocked.
+struct LoopBarrier<'a> {
+    barrier: &'a Barrier,
+    remaining: usize,
+}
+
+impl<'a> LoopBarrier<'a> {
+    /// `remaining` is the number of times every loop waits at the barrier
+    fn new(barrier: &'a Barrier, config: &ClientConfig, pre_tested: bool) -> Self {
+        let phases = &config.phases;
+        let remaining = 1
+            + usize::from(phases.pre_download() && !pre_tested)
+            + usize::from(phases.ping)
+            + 2 * usize::from(phases.download)
+            + 2 * usize::from(phases.upload)
+            + usize::from(config.save_results && config.signed_result);
+        Self { barrier, remaining }
+    }
+
+    fn wait(&mut self) {
+        self.remaining -= 1;
+        self.barrier.wait();
+    }
+}
+
+impl Drop for LoopBarrier<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            for _ in 0..self.remaining {
+                self.barrier.wait();
+            }
+        }
+    }
+}
+
+/// Settings and results shared by the event loops
+struct Shared {
     config: ClientConfig,
+    addr: SocketAddr,
+    observer: Arc<dyn MeasurementObserver>,
     stats: Arc<Mutex<SharedStats>>,
-) -> Result<Vec<Measurement>, anyhow::Error> {
-    let config_clone = config.clone();
-    let barrier = Arc::new(Barrier::new(config.thread_count));
-    let mut thread_handles = vec![];
-    let ping_median = Arc::new(Mutex::new(None::<u64>));
-    let download_speed = Arc::new(Mutex::new(None::<f64>));
-    let upload_speed = Arc::new(Mutex::new(None::<f64>));
+    failed: Mutex<Vec<FailedThread>>,
+    retries: AtomicUsize,
+    ping_median: Mutex<Option<u64>>,
+    ping_samples: Mutex<Vec<u64>>,
+    download: Mutex<Option<Speed>>,
+    upload: Mutex<Option<Speed>>,
+}
 
-    // Get server address (IP or hostname)
-    let server_addr = config.server.unwrap();
-
-    // Resolve IP if it's a hostname
-    let ip = if crate::client::control_server::servers::is_ip_address(&server_addr) {
-        server_addr.clone()
-    } else {
-        match crate::client::control_server::servers::resolve_ip_from_web_address(&server_addr) {
-            Ok(ip) => ip,
-            Err(_) => server_addr.clone(), // Fallback to original if resolution fails
-        }
-    };
+impl Shared {
+    fn has_failed(&self, thread_id: usize) -> bool {
+        self.failed
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|thread| thread.thread_id == thread_id)
+    }
+}
 
-    debug!("Resolved IP: {}", ip);
+/// One event loop and its connections. Connections that failed for good are
+/// dropped, the loop itself keeps waiting at the barriers so the other loops
+/// are not blocked.
+struct Worker {
+    /// Loop 0 reports the phases to the observer and calculates the speeds
+    index: usize,
+    shared: Arc<Shared>,
+    event_loop: EventLoop,
+    thread_ids: Vec<usize>,
+    retries_left: BTreeMap<usize, u32>,
+    /// Chunk size of the pre-download, applied to new connections
+    chunk_size: Option<usize>,
+}
 
-    debug!(
-        "config.port: {}, config.tls_port: {}",
-        config.port, config.tls_port
-    );
+impl Worker {
+    fn new(index: usize, shared: Arc<Shared>, thread_ids: Vec<usize>) -> anyhow::Result<Self> {
+        let retries_left = thread_ids
+            .iter()
+            .map(|thread_id| (*thread_id, shared.config.retries))
+            .collect();
+        Ok(Self {
+            index,
+            shared,
+            event_loop: EventLoop::new()?,
+            thread_ids,
+            retries_left,
+            chunk_size: None,
+        })
+    }
 
-    let addr = if !config.use_tls {
-        parse_listen_address(&format!("{}:{}", ip, config.port)).unwrap()
-    } else {
-        parse_listen_address(&format!("{}:{}", ip, config.tls_port)).unwrap()
-    };
-
-    for i in 0..config.thread_count {
-        let barrier = Arc::clone(&barrier);
-        let stats = Arc::clone(&stats);
-        let ping_median_clone = Arc::clone(&ping_median);
-        let download_speed_clone = Arc::clone(&download_speed);
-        let upload_speed_clone = Arc::clone(&upload_speed);
-        thread_handles.push(thread::spawn(move || {
-            let mut state =
-                match TestState::new(addr, config.use_tls, config.use_websocket, i, None, None) {
-                    Ok(state) => state,
-                    Err(e) => {
-                        debug!("TestState error: {:?} token: {}", e, i);
-                        return Err(e);
-                    }
-                };
-
-            let greeting = state.process_greeting();
-            match greeting {
-                Ok(_) => {}
-                Err(e) => {
-                    println!("Thread {i} could not connect to the server. {:?}", e);
-                    return Err(anyhow::anyhow!("Greeting failed with error: {:?}", e));
+    /// Connects and greets, retrying while retries are left
+    fn open(&mut self, thread_ids: &[usize]) {
+        let mut thread_ids = thread_ids.to_vec();
+        while !thread_ids.is_empty() {
+            let mut failures = Vec::new();
+            for thread_id in &thread_ids {
+                if let Err(failure) = self.event_loop.connect(
+                    *thread_id,
+                    self.shared.addr,
+                    &self.shared.config,
+                    Some(self.shared.observer.clone()),
+                    self.chunk_size,
+                ) {
+                    failures.push(failure);
                 }
             }
-            barrier.wait();
-            state.run_get_chunks().unwrap();
+            failures.extend(self.run_caught(&thread_ids, MeasurementPhase::Connect));
+            thread_ids = self.retry_or_fail(failures, MeasurementPhase::Connect);
+        }
+    }
 
-            barrier.wait();
+    /// Runs one phase on the given connections. Failed connections are
+    /// reconnected and repeat the phase while retries are left, except for
+    /// the download and upload, see `retry_or_fail`.
+    fn run_phase(&mut self, thread_ids: &[usize], phase: MeasurementPhase) {
+        let mut thread_ids = thread_ids.to_vec();
+        while !thread_ids.is_empty() {
+            let failures = self.run_caught(&thread_ids, phase);
+            let retry = self.retry_or_fail(failures, phase);
+            self.open(&retry);
+            thread_ids = retry
+                .into_iter()
+                .filter(|thread_id| self.event_loop.contains(*thread_id))
+                .collect();
+        }
+    }
 
-            if i == 0 {
-                state.run_ping().unwrap();
-                let median = state.measurement_state().ping_median.unwrap();
-                let ping_ms = median as f64 / 1000000.0;
+    /// A panic in a handler fails the connections still in the phase instead
+    /// of taking the loop and its barrier down
+    fn run_caught(&mut self, thread_ids: &[usize], phase: MeasurementPhase) -> Vec<ConnectionFailure> {
+        let legacy = self.shared.config.legacy;
+        let event_loop = &mut self.event_loop;
+        match catch_unwind(AssertUnwindSafe(|| event_loop.run_phase(thread_ids, phase, legacy))) {
+            Ok(failures) => failures,
+            Err(panic) => self.event_loop.abort_phase(&panic_message(panic.as_ref())),
+        }
+    }
 
-                *ping_median_clone.lock().unwrap() = Some(median);
+    /// Returns the failed connections that get another try. A repeated
+    /// download or upload would run after the other connections finished,
+    /// with the link to itself, and overstate the speed; those fail at once.
+    fn retry_or_fail(&mut self, failures: Vec<ConnectionFailure>, phase: MeasurementPhase) -> Vec<usize> {
+        let throughput = matches!(phase, MeasurementPhase::Download | MeasurementPhase::Upload);
+        let mut retry = Vec::new();
+        for failure in failures {
+            let retries_left = self.retries_left.entry(failure.thread_id).or_insert(0);
+            if throughput || *retries_left == 0 {
+                self.fail(failure, phase);
+                continue;
+            }
+            *retries_left -= 1;
+            self.shared.retries.fetch_add(1, Ordering::Relaxed);
+            warn!(
+                "Thread {}: {} failed ({}), reconnecting, {} retries left",
+                failure.thread_id,
+                phase.as_str(),
+                failure.error,
+                retries_left
+            );
+            retry.push(failure.thread_id);
+        }
+        if !retry.is_empty() {
+            thread::sleep(RETRY_DELAY);
+        }
+        retry
+    }
+
+    fn fail(&self, failure: ConnectionFailure, phase: MeasurementPhase) {
+        info!(
+            "Failed thread {} on phase {:?}: {}",
+            failure.thread_id, failure.phase, failure.error
+        );
+        self.shared
+            .observer
+            .on_thread_failed(failure.thread_id, phase, &failure.error);
+        self.shared.failed.lock().unwrap().push(FailedThread {
+            thread_id: failure.thread_id,
+            phase: failure.phase,
+            error: failure.error,
+        });
+    }
 
-                if config.raw_output {
-                    print!("{:.2}", ping_ms);
-                } else {
-                    print_float_result("Ping Median", "ms", Some(ping_ms), false);
+    /// Runs the selected phases in step with the other loops
+    fn run(mut self, barrier: &Barrier, thread_count: usize) -> Vec<ThreadMeasurement> {
+        let shared = self.shared.clone();
+        let config = &shared.config;
+        let observer = &shared.observer;
+        let coordinator = self.index == 0;
+        let warmup_ns = config.durations.warmup.as_nanos() as u64;
+        // With `-t auto` connection 0 is connected and pre-tested already
+        let pre_tested = self.chunk_size.is_some();
+        let mut barrier = LoopBarrier::new(barrier, config, pre_tested);
+
+        let unopened: Vec<usize> = self
+            .thread_ids
+            .iter()
+            .copied()
+            .filter(|thread_id| !self.event_loop.contains(*thread_id))
+            .collect();
+        self.open(&unopened);
+        barrier.wait();
+        if coordinator && !pre_tested {
+            observer.on_phase_finished(MeasurementPhase::Connect, &PhaseResult::Completed);
+        }
+
+        if config.phases.pre_download() && !pre_tested {
+            if coordinator {
+                observer.on_phase_started(MeasurementPhase::PreDownload);
+            }
+            self.run_phase(&self.event_loop.thread_ids(), MeasurementPhase::PreDownload);
+            self.chunk_size = self
+                .event_loop
+                .thread_ids()
+                .first()
+                .and_then(|thread_id| self.event_loop.state(*thread_id))
+                .map(|state| state.chunk_size);
+            barrier.wait();
+            if coordinator {
+                observer.on_phase_finished(MeasurementPhase::PreDownload, &PhaseResult::Completed);
+            }
+        }
+
+        if config.phases.ping {
+            // The first connection still open pings
+            let pinger = (0..thread_count).find(|thread_id| !shared.has_failed(*thread_id));
+            if let Some(pinger) = pinger.filter(|thread_id| self.event_loop.contains(*thread_id)) {
+                observer.on_phase_started(MeasurementPhase::Ping);
+                self.run_phase(&[pinger], MeasurementPhase::Ping);
+                if let Some(state) = self.event_loop.state(pinger) {
+                    *shared.ping_samples.lock().unwrap() = state.ping_times.clone();
+                    if let Some(median) = state.ping_median {
+                        *shared.ping_median.lock().unwrap() = Some(median);
+                        observer.on_phase_finished(
+                            MeasurementPhase::Ping,
+                            &PhaseResult::Ping { median_ns: median },
+                        );
+                    }
                 }
             }
             barrier.wait();
+        }
 
-            state.run_get_time().unwrap();
+        if config.phases.download {
+            if coordinator {
+                observer.on_phase_started(MeasurementPhase::Download);
+            }
+            self.run_phase(&self.event_loop.thread_ids(), MeasurementPhase::Download);
             {
-                let mut stats = stats.lock().unwrap();
-                stats.download_measurements.push(
-                    state
-                        .measurement_state()
+                let mut stats = shared.stats.lock().unwrap();
+                for thread_id in self.event_loop.thread_ids() {
+                    let Some(state) = self.event_loop.state(thread_id) else {
+                        continue;
+                    };
+                    stats
                         .download_measurements
-                        .iter()
-                        .cloned()
-                        .collect(),
-                );
+                        .push(state.download_measurements.iter().cloned().collect());
+                }
             }
 
             barrier.wait();
 
-            if i == 0 {
-                let stats_guard = stats.lock().unwrap();
-                let speed =
-                    calculate_download_speed_from_stats_silent(&stats_guard.download_measurements);
-
-                // Save download speed for later use
-                *download_speed_clone.lock().unwrap() = Some(speed.2); // speed.1 is Gbps
-
-                if config.raw_output {
-                    print!("/{:.2}", speed.1); // speed.1 is Gbps
-                } else {
-                    print_test_result("Download Test", "Completed", Some(speed), false);
+            if coordinator {
+                let stats = shared.stats.lock().unwrap();
+                let speed = (!stats.download_measurements.is_empty()).then(|| {
+                    Speed::from(calculate_download_speed_from_stats_silent(
+                        &stats.download_measurements,
+                        warmup_ns,
+                    ))
+                });
+                drop(stats);
+                if let Some(speed) = speed {
+                    *shared.download.lock().unwrap() = Some(speed);
+                    observer.on_phase_finished(
+                        MeasurementPhase::Download,
+                        &PhaseResult::Throughput(speed),
+                    );
                 }
             }
 
             barrier.wait();
+        }
 
-            if config.legacy {
-                state.run_put().unwrap();
-            } else {
-                state.run_perf_test().unwrap();
+        if config.phases.upload {
+            if coordinator {
+                observer.on_phase_started(MeasurementPhase::Upload);
             }
+            self.run_phase(&self.event_loop.thread_ids(), MeasurementPhase::Upload);
             {
-                let mut stats = stats.lock().unwrap();
-                stats.upload_measurements.push(
-                    state
-                        .measurement_state()
+                let mut stats = shared.stats.lock().unwrap();
+                for thread_id in self.event_loop.thread_ids() {
+                    let Some(state) = self.event_loop.state(thread_id) else {
+                        continue;
+                    };
+                    stats
                         .upload_measurements
-                        .iter()
-                        .cloned()
-                        .collect(),
-                );
+                        .push(state.upload_measurements.iter().cloned().collect());
+                }
             }
 
             barrier.wait();
 
-            if i == 0 {
-                let stats_guard = stats.lock().unwrap();
-                let speed =
-                    calculate_upload_speed_from_stats_silent(&stats_guard.upload_measurements);
-
-                // Save upload speed for later use
-                *upload_speed_clone.lock().unwrap() = Some(speed.2); // speed.1 is Gbps
-
-                if config.raw_output {
-                    println!("/{:.2}", speed.1); // speed.1 is Gbps, println! for line break
-                } else {
-                    print_test_result("Upload Test", "Completed", Some(speed), true);
+            if coordinator {
+                let stats = shared.stats.lock().unwrap();
+                let speed = (!stats.upload_measurements.is_empty()).then(|| {
+                    Speed::from(calculate_upload_speed_from_stats_silent(
+                        &stats.upload_measurements,
+                        warmup_ns,
+                    ))
+                });
+                drop(stats);
+                if let Some(speed) = speed {
+                    *shared.upload.lock().unwrap() = Some(speed);
+                    observer.on_phase_finished(
+                        MeasurementPhase::Upload,
+                        &PhaseResult::Throughput(speed),
+                    );
                 }
             }
 
             barrier.wait();
+        }
 
-            if config.save_results && config.signed_result {
-                state.run_signed_result().unwrap();
-                barrier.wait();
-            }
-
-            let result: Measurement = Measurement {
-                thread_id: i,
-                failed: state.measurement_state().failed,
-                phase: state.measurement_state().phase.clone(),
-                measurements: state
-                    .measurement_state()
-                    .download_measurements
-                    .iter()
-                    .cloned()
-                    .collect(),
-                upload_measurements: state
-                    .measurement_state()
-                    .upload_measurements
-                    .iter()
-                    .cloned()
-                    .collect(),
-                envelope: state.measurement_state().envelope.clone(),
-            };
-            Ok(result)
-        }));
-    }
-
-    let states: Vec<Measurement> = thread_handles
-        .into_iter()
-        .map(|h| h.join().unwrap())
-        .filter(|s| s.is_ok())
-        .map(|s| s.unwrap())
-        .collect();
-
-    for s in states.iter() {
-        if s.failed {
-            info!("Failed thread {} on phase {:?}", s.thread_id, s.phase);
+        if config.save_results && config.signed_result {
+            if coordinator {
+                observer.on_phase_started(MeasurementPhase::SignedResult);
+            }
+            self.run_phase(&self.event_loop.thread_ids(), MeasurementPhase::SignedResult);
+            barrier.wait();
+            if coordinator {
+                observer.on_phase_finished(MeasurementPhase::SignedResult, &PhaseResult::Completed);
+            }
         }
+
+        debug_assert_eq!(barrier.remaining, 0);
+        self.event_loop
+            .thread_ids()
+            .into_iter()
+            .filter_map(|thread_id| {
+                let state = self.event_loop.state(thread_id)?;
+                Some(ThreadMeasurement {
+                    thread_id,
+                    failed: false,
+                    phase: state.phase.clone(),
+                    measurements: state.download_measurements.iter().cloned().collect(),
+                    upload_measurements: state.upload_measurements.iter().cloned().collect(),
+                    envelope: state.envelope.clone(),
+                    chunk_size: state.chunk_size,
+                    setup: ConnectionSetup::from_marks(state.setup_started, &state.setup),
+                })
+            })
+            .collect()
     }
+}
 
-    let state_refs: Vec<Measurement> = states
-        .iter()
-        //TODO whar to do on failed threads?
-        .filter(|s| !s.failed)
-        .cloned()
-        .collect();
+/// Runs all phases on `thread_count` connections and reports their progress
+/// to `observer`. The connections are spread over `event_loops` mio event
+/// loops, each on its own OS thread. Failed connections are left out of the
+/// speeds and listed in the result, which is then marked as degraded.
+pub(crate) fn run_threads(
+    config: ClientConfig,
+    stats: Arc<Mutex<SharedStats>>,
+    observer: Arc<dyn MeasurementObserver>,
+) -> Result<MeasurementResult, anyhow::Error> {
+    let started_at = chrono::Utc::now();
+    let timer = Arc::new(PhaseTimer {
+        start: Instant::now(),
+        timings: Mutex::new(Vec::new()),
+    });
+    let mut observers = Observers::default();
+    observers.push(timer.clone());
+    observers.push(observer);
+    let observer: Arc<dyn MeasurementObserver> = Arc::new(observers);
 
-    if state_refs.len() != config.thread_count {
-        println!("Failed threads: {} out of {}", config.thread_count - state_refs.len(), config.thread_count);
-    }
+    // Get server address (IP or hostname)
+    let server_addr = config
+        .server
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("No server address configured"))?;
 
-    let envelopes: Vec<Option<String>> = state_refs.iter().map(|s| s.envelope.clone()).collect();
+    debug!(
+        "config.port: {}, config.tls_port: {}",
+        config.port, config.tls_port
+    );
 
-    // Save results if -save option is enabled
-    if config.save_results {
-        let mut measurement_saver = MeasurementSaver::new(&config_clone);
+    // Resolve IP if it's a hostname, through the interface of `-I` / `-B`
+    let port = if config.use_tls { config.tls_port } else { config.port };
+    let lookup_started = Instant::now();
+    let addr = config
+        .source
+        .resolve(&server_addr, port)
+        .map_err(|e| anyhow::anyhow!("Invalid server address '{}': {}", server_addr, e))?
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("Invalid server address '{}': no address", server_addr))?;
+
+    let is_host_name = server_addr.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().is_err();
+    let dns_ns = is_host_name.then(|| lookup_started.elapsed().as_nanos() as u64);
+    debug!("Resolved address: {}", addr);
+
+    let shared = Arc::new(Shared {
+        config: config.clone(),
+        addr,
+        observer: observer.clone(),
+        stats,
+        failed: Mutex::new(Vec::new()),
+        retries: AtomicUsize::new(0),
+        ping_median: Mutex::new(None),
+        ping_samples: Mutex::new(Vec::new()),
+        download: Mutex::new(None),
+        upload: Mutex::new(None),
+    });
+
+    observer.on_phase_started(MeasurementPhase::Connect);
+
+    // `-t auto`: the first connection runs the pre-download alone, its speed
+    // decides how many connections join for the rest of the measurement
+    let mut thread_count = config.thread_count;
+    let mut pre_test = None;
+    let mut first_worker = Worker::new(0, shared.clone(), vec![0])?;
+    if config.auto_threads {
+        if config.phases.pre_download() {
+            first_worker.open(&[0]);
+            if !first_worker.event_loop.contains(0) {
+                return Err(anyhow::anyhow!("Pre-test connection failed: {}", first_error(&shared)));
+            }
+            observer.on_phase_finished(MeasurementPhase::Connect, &PhaseResult::Completed);
+            observer.on_phase_started(MeasurementPhase::PreDownload);
+            first_worker.run_phase(&[0], MeasurementPhase::PreDownload);
+            let Some(measurement_state) = first_worker.event_loop.state(0) else {
+                return Err(anyhow::anyhow!("Pre-test failed: {}", first_error(&shared)));
+            };
+            observer.on_phase_finished(MeasurementPhase::PreDownload, &PhaseResult::Completed);
+
+            let bps = measurement_state.pre_download_bytes as f64 * 8.0 * 1e9
+                / measurement_state.pre_download_time_ns.max(1) as f64;
+            let speed = Speed::from((bps, bps / 1e9, bps / 1e6));
+            first_worker.chunk_size = Some(measurement_state.chunk_size);
+            thread_count = config.thread_thresholds.threads_for(speed.mbps);
+            info!("Pre-test {:.2} Mbit/s, using {} threads", speed.mbps, thread_count);
+            pre_test = Some(speed);
+        } else {
+            // Only ping runs, on the first connection
+            thread_count = 1;
+        }
+        observer.on_thread_count_selected(thread_count, pre_test);
+    }
 
-        // Get all saved values
-        let ping_median_value = *ping_median.lock().unwrap();
-        let download_speed_value = *download_speed.lock().unwrap();
-        let upload_speed_value = *upload_speed.lock().unwrap();
+    let event_loops = loop_count(config.event_loops, thread_count);
+    debug!("{} connections on {} event loops", thread_count, event_loops);
+    let barrier = Arc::new(Barrier::new(event_loops));
+    let chunk_size = first_worker.chunk_size;
+    first_worker.thread_ids = loop_thread_ids(0, event_loops, thread_count);
+    let mut workers = vec![first_worker];
+    for index in 1..event_loops {
+        let mut worker = Worker::new(
+            index,
+            shared.clone(),
+            loop_thread_ids(index, event_loops, thread_count),
+        )?;
+        worker.chunk_size = chunk_size;
+        workers.push(worker);
+    }
+    let loop_handles: Vec<_> = workers
+        .into_iter()
+        .map(|worker| {
+            let barrier = Arc::clone(&barrier);
+            let thread_ids = worker.thread_ids.clone();
+            (thread_ids, thread::spawn(move || worker.run(&barrier, thread_count)))
+        })
+        .collect();
 
-        if let Err(e) = measurement_saver
-            .save_measurement_with_speeds(
-                ping_median_value,
-                download_speed_value,
-                upload_speed_value,
-                envelopes,
-            )
-            .await
-        {
-            eprintln!("Failed to save measurement: {}", e);
+    let mut threads = Vec::new();
+    for (thread_ids, handle) in loop_handles {
+        match handle.join() {
+            Ok(loop_threads) => threads.extend(loop_threads),
+            Err(panic) => {
+                // Panics inside a phase are caught; this is outside of them
+                let error = panic_message(panic.as_ref());
+                for thread_id in thread_ids {
+                    if shared.has_failed(thread_id) {
+                        continue;
+                    }
+                    observer.on_thread_failed(thread_id, MeasurementPhase::Connect, &error);
+                    shared.failed.lock().unwrap().push(FailedThread {
+                        thread_id,
+                        phase: TestPhase::GreetingSendConnectionType,
+                        error: error.clone(),
+                    });
+                }
+            }
         }
     }
+    threads.sort_by_key(|thread| thread.thread_id);
+
+    let ping_median_ns = *shared.ping_median.lock().unwrap();
+    let download = *shared.download.lock().unwrap();
+    let upload = *shared.upload.lock().unwrap();
+    let mut failed = shared.failed.lock().unwrap().clone();
+    failed.sort_by_key(|thread| thread.thread_id);
+    let retries = shared.retries.load(Ordering::Relaxed);
+
+    let measured: Vec<bool> = [
+        (config.phases.ping, ping_median_ns.is_some()),
+        (config.phases.download, download.is_some()),
+        (config.phases.upload, upload.is_some()),
+    ]
+    .into_iter()
+    .filter(|(selected, _)| *selected)
+    .map(|(_, measured)| measured)
+    .collect();
+    let status = MeasurementStatus::evaluate(&measured, !failed.is_empty() || retries > 0);
+
+    let setups: Vec<&ConnectionSetup> = threads.iter().map(|thread| &thread.setup).collect();
+    let setup = SetupSummary::new(dns_ns, &setups);
+    let ping_samples_ns = shared.ping_samples.lock().unwrap().clone();
+    let timings = timer.timings.lock().unwrap().clone();
+    Ok(MeasurementResult {
+        server_host: server_addr,
+        server: addr,
+        started_at,
+        duration: timer.start.elapsed(),
+        thread_count,
+        pre_test,
+        ping_median_ns,
+        ping_samples_ns,
+        download,
+        upload,
+        threads,
+        failed,
+        timings,
+        setup,
+        event_loops,
+        interface: config.source.interface_name(),
+        source_address: config.source.address,
+        status,
+        retries,
+        save_error: None,
+    })
+}
 
-    Ok(state_refs)
+fn first_error(shared: &Shared) -> String {
+    shared
+        .failed
+        .lock()
+        .unwrap()
+        .first()
+        .map(|thread| thread.error.clone())
+        .unwrap_or_default()
 }
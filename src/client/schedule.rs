@@ -0,0 +1,143 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Local};
+use croner::Cron;
+
+use crate::client::durations::parse_duration;
+
+/// `-schedule` / `client_schedule`: when a long-running client starts its
+/// measurements
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Time between the starts of two measurements, the first one starts at once
+    Interval(Duration),
+    /// Five-field crontab expression in local time, e.g. `*/15 * * * *`
+    Cron(Box<Cron>),
+}
+
+impl Schedule {
+    /// Start of the measurement following one started at `last`, or the first
+    /// one when `last` is `None`. An interval run that overran its slot is
+    /// followed immediately instead of catching up.
+    pub fn next_start(&self, last: Option<DateTime<Local>>, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Self::Interval(interval) => {
+                let Some(last) = last else {
+                    return Some(now);
+                };
+                let next = last + chrono::Duration::from_std(*interval).ok()?;
+                Some(next.max(now))
+            }
+            Self::Cron(cron) => cron.find_next_occurrence(&now, false).ok(),
+        }
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    /// An interval such as `900`, `15m` or `6h`, or a crontab expression such
+    /// as `0 * * * *` or `@hourly`
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        if value.contains(char::is_whitespace) || value.starts_with('@') {
+            // `Cron::from_str` leaves the pattern unparsed
+            let schedule = Cron::new(value)
+                .parse()
+                .map(|cron| Self::Cron(Box::new(cron)))
+                .map_err(|e| format!("invalid cron expression '{}': {}", value, e))?;
+            // Malformed patterns such as minute 61 or four fields parse, but
+            // never match, as do impossible dates like 31 February
+            if schedule.next_start(None, Local::now()).is_none() {
+                return Err(format!(
+                    "cron expression '{}' never matches, expected minute hour day month weekday",
+                    value
+                ));
+            }
+            return Ok(schedule);
+        }
+        match parse_period(value) {
+            Ok(interval) if !interval.is_zero() => Ok(Self::Interval(interval)),
+            Ok(_) => Err("schedule interval must be greater than 0".to_string()),
+            Err(_) => Err(format!(
+                "invalid schedule '{}', expected an interval like 15m or a cron expression like \"*/15 * * * *\"",
+                value
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Interval(interval) => write!(f, "every {}s", interval.as_secs_f64()),
+            Self::Cron(cron) => write!(f, "{}", cron.as_str()),
+        }
+    }
+}
+
+/// Spans longer than a phase duration: seconds as for the durations, or a
+/// whole number of minutes, hours or days such as `15m`, `6h` or `7d`
+pub fn parse_period(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let unit = match value.chars().last() {
+        Some('m') if !value.ends_with("ms") => 60,
+        Some('h') => 60 * 60,
+        Some('d') => 24 * 60 * 60,
+        _ => return parse_duration(value),
+    };
+    value[..value.len() - 1]
+        .parse::<u64>()
+        .ok()
+        .and_then(|count| count.checked_mul(unit))
+        .map(Duration::from_secs)
+        .ok_or_else(|| format!("invalid period '{}', expected e.g. 90, 15m, 6h or 7d", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Timelike};
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 3, 2, 10, 7, 30).unwrap() + chrono::Duration::milliseconds(250)
+    }
+
+    #[test]
+    fn test_parse_period() {
+        assert_eq!(parse_period("15m").unwrap(), Duration::from_secs(900));
+        assert_eq!(parse_period("7d").unwrap(), Duration::from_secs(7 * 86400));
+        assert_eq!(parse_period("500ms").unwrap(), Duration::from_millis(500));
+        assert!(parse_period("1.5h").is_err());
+        assert!(parse_period("999999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_interval_next_start() {
+        let now = now();
+        let interval: Schedule = "15m".parse().unwrap();
+        assert_eq!(interval.next_start(None, now), Some(now));
+        let last = now - chrono::Duration::minutes(5);
+        assert_eq!(interval.next_start(Some(last), now), Some(last + chrono::Duration::minutes(15)));
+        // Overran its slot
+        let long_ago = now - chrono::Duration::hours(1);
+        assert_eq!(interval.next_start(Some(long_ago), now), Some(now));
+    }
+
+    #[test]
+    fn test_cron_next_start() {
+        let cron: Schedule = "*/15 * * * *".parse().unwrap();
+        let next = cron.next_start(None, now()).unwrap();
+        assert_eq!((next.hour(), next.minute(), next.second()), (10, 15, 0));
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        assert!("0s".parse::<Schedule>().is_err());
+        assert!("61 * * * *".parse::<Schedule>().is_err());
+        assert!("* * * *".parse::<Schedule>().is_err());
+        assert!("often".parse::<Schedule>().is_err());
+    }
+}
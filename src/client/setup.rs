@@ -0,0 +1,243 @@
+//! Connection setup timings: how long the name lookup, the TCP connect, the
+//! TLS handshake, the WebSocket upgrade and the first server byte took, per
+//! connection and summarised over the measurement.
+
+use std::time::Instant;
+
+use rustls::ClientConnection;
+use serde::Serialize;
+
+/// Instants of the setup steps of one connection. WebSocket streams
+/// handshake while they are constructed and mark their steps up to the
+/// upgrade themselves, the event loop marks them for the others. The first
+/// byte is that of the RMBT greeting on every transport.
+#[derive(Debug, Clone, Default)]
+pub struct SetupMarks {
+    pub connected: Option<Instant>,
+    pub tls_established: Option<Instant>,
+    pub upgraded: Option<Instant>,
+    pub first_byte: Option<Instant>,
+    pub tls: Option<TlsInfo>,
+}
+
+impl SetupMarks {
+    /// Marks the end of the TLS handshake once `conn` finished it
+    pub fn mark_tls(&mut self, conn: &ClientConnection) {
+        if self.tls_established.is_none() && !conn.is_handshaking() {
+            self.tls_established = Some(Instant::now());
+            self.tls = Some(TlsInfo::from_connection(conn));
+        }
+    }
+
+    /// Marks the upgrade once `response`, the bytes of a WebSocket handshake
+    /// read so far, holds the whole HTTP response, and the first byte once
+    /// the greeting follows it
+    pub fn mark_upgrade_response(&mut self, response: &[u8]) {
+        let Some(end) = response.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return;
+        };
+        self.upgraded.get_or_insert_with(Instant::now);
+        if response.len() > end + 4 {
+            self.first_byte.get_or_insert_with(Instant::now);
+        }
+    }
+}
+
+/// What the TLS handshake negotiated
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TlsInfo {
+    /// e.g. `TLSv1.3`
+    pub version: Option<String>,
+    /// e.g. `TLS13_AES_256_GCM_SHA384`
+    pub cipher: Option<String>,
+    pub alpn: Option<String>,
+}
+
+impl TlsInfo {
+    pub fn from_connection(conn: &ClientConnection) -> Self {
+        Self {
+            version: conn
+                .protocol_version()
+                .map(|version| version.as_str().map_or_else(|| format!("{:?}", version), |name| name.replace('_', "."))),
+            cipher: conn
+                .negotiated_cipher_suite()
+                .map(|suite| suite.suite().as_str().map_or_else(|| format!("{:?}", suite.suite()), str::to_string)),
+            alpn: conn
+                .alpn_protocol()
+                .map(|protocol| String::from_utf8_lossy(protocol).into_owned()),
+        }
+    }
+}
+
+/// Setup of one connection. Each step is timed from the end of the one
+/// before it, the first byte from the start of the connect.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConnectionSetup {
+    pub tcp_connect_ns: Option<u64>,
+    pub tls_handshake_ns: Option<u64>,
+    pub websocket_upgrade_ns: Option<u64>,
+    /// Until the first byte of the server's answer to the greeting
+    pub first_byte_ns: Option<u64>,
+    pub tls: Option<TlsInfo>,
+}
+
+impl ConnectionSetup {
+    pub fn from_marks(started: Instant, marks: &SetupMarks) -> Self {
+        let between = |from: Option<Instant>, to: Option<Instant>| {
+            Some(to?.saturating_duration_since(from?).as_nanos() as u64)
+        };
+        let secured = marks.tls_established.or(marks.connected);
+        Self {
+            tcp_connect_ns: between(Some(started), marks.connected),
+            tls_handshake_ns: between(marks.connected, marks.tls_established),
+            websocket_upgrade_ns: between(secured, marks.upgraded),
+            first_byte_ns: between(Some(started), marks.first_byte),
+            tls: marks.tls.clone(),
+        }
+    }
+}
+
+/// Fastest, median and slowest connection of a setup step
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct StepSummary {
+    pub min_ns: u64,
+    pub median_ns: u64,
+    pub max_ns: u64,
+}
+
+impl StepSummary {
+    fn of(mut values: Vec<u64>) -> Option<Self> {
+        values.sort_unstable();
+        Some(Self {
+            min_ns: *values.first()?,
+            median_ns: values[values.len() / 2],
+            max_ns: *values.last()?,
+        })
+    }
+}
+
+/// Setup timings of a measurement. The server name is looked up once for
+/// all connections; the TLS parameters are those of the first connection.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SetupSummary {
+    /// `None` when the server was given as an IP address
+    pub dns_ns: Option<u64>,
+    pub tcp_connect: Option<StepSummary>,
+    pub tls_handshake: Option<StepSummary>,
+    pub websocket_upgrade: Option<StepSummary>,
+    pub first_byte: Option<StepSummary>,
+    pub tls: Option<TlsInfo>,
+}
+
+impl SetupSummary {
+    pub fn new(dns_ns: Option<u64>, setups: &[&ConnectionSetup]) -> Self {
+        let step = |value: fn(&ConnectionSetup) -> Option<u64>| {
+            StepSummary::of(setups.iter().filter_map(|setup| value(setup)).collect())
+        };
+        Self {
+            dns_ns,
+            tcp_connect: step(|setup| setup.tcp_connect_ns),
+            tls_handshake: step(|setup| setup.tls_handshake_ns),
+            websocket_upgrade: step(|setup| setup.websocket_upgrade_ns),
+            first_byte: step(|setup| setup.first_byte_ns),
+            tls: setups.iter().find_map(|setup| setup.tls.clone()),
+        }
+    }
+
+    /// `DNS 1.20 ms, TCP 0.31 ms, TLS 4.02 ms (TLSv1.3, ...), first byte
+    /// 5.10 ms`, medians over the connections
+    pub fn describe(&self) -> String {
+        let ms = |ns: u64| format!("{:.2} ms", ns as f64 / 1e6);
+        let mut parts = Vec::new();
+        if let Some(dns_ns) = self.dns_ns {
+            parts.push(format!("DNS {}", ms(dns_ns)));
+        }
+        for (name, step) in [
+            ("TCP", self.tcp_connect),
+            ("TLS", self.tls_handshake),
+            ("WebSocket", self.websocket_upgrade),
+            ("first byte", self.first_byte),
+        ] {
+            let Some(step) = step else {
+                continue;
+            };
+            let mut part = format!("{} {}", name, ms(step.median_ns));
+            if let (Some(tls), "TLS") = (&self.tls, name) {
+                let negotiated: Vec<&str> = [&tls.version, &tls.cipher, &tls.alpn]
+                    .into_iter()
+                    .filter_map(|value| value.as_deref())
+                    .collect();
+                if !negotiated.is_empty() {
+                    part.push_str(&format!(" ({})", negotiated.join(", ")));
+                }
+            }
+            parts.push(part);
+        }
+        parts.join(", ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn wss_marks(started: Instant) -> SetupMarks {
+        let at = |ms: u64| Some(started + Duration::from_millis(ms));
+        SetupMarks {
+            connected: at(2),
+            tls_established: at(7),
+            upgraded: at(10),
+            first_byte: at(12),
+            tls: Some(TlsInfo {
+                version: Some("TLSv1.3".to_string()),
+                cipher: Some("TLS13_AES_256_GCM_SHA384".to_string()),
+                alpn: None,
+            }),
+        }
+    }
+
+    fn tcp_marks(started: Instant) -> SetupMarks {
+        let at = |ms: u64| Some(started + Duration::from_millis(ms));
+        SetupMarks { connected: at(4), first_byte: at(6), ..SetupMarks::default() }
+    }
+
+    #[test]
+    fn test_setup_from_marks() {
+        let started = Instant::now();
+        let wss = ConnectionSetup::from_marks(started, &wss_marks(started));
+        assert_eq!(wss.tcp_connect_ns, Some(2_000_000));
+        assert_eq!(wss.tls_handshake_ns, Some(5_000_000));
+        assert_eq!(wss.websocket_upgrade_ns, Some(3_000_000));
+        assert_eq!(wss.first_byte_ns, Some(12_000_000));
+
+        let tcp = ConnectionSetup::from_marks(started, &tcp_marks(started));
+        assert_eq!((tcp.tls_handshake_ns, tcp.websocket_upgrade_ns), (None, None));
+    }
+
+    #[test]
+    fn test_setup_summary() {
+        let started = Instant::now();
+        let wss = ConnectionSetup::from_marks(started, &wss_marks(started));
+        let tcp = ConnectionSetup::from_marks(started, &tcp_marks(started));
+        let summary = SetupSummary::new(Some(1_500_000), &[&tcp, &wss, &tcp]);
+        assert_eq!(summary.tcp_connect, Some(StepSummary { min_ns: 2_000_000, median_ns: 4_000_000, max_ns: 4_000_000 }));
+        assert_eq!(summary.tls_handshake.unwrap().median_ns, 5_000_000);
+        assert_eq!(
+            summary.describe(),
+            "DNS 1.50 ms, TCP 4.00 ms, TLS 5.00 ms (TLSv1.3, TLS13_AES_256_GCM_SHA384), WebSocket 3.00 ms, first byte 6.00 ms"
+        );
+        assert_eq!(SetupSummary::new(None, &[]).describe(), "");
+    }
+
+    #[test]
+    fn test_marks_upgrade_response() {
+        let mut marks = SetupMarks::default();
+        marks.mark_upgrade_response(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n");
+        assert_eq!((marks.upgraded, marks.first_byte), (None, None));
+        marks.mark_upgrade_response(b"HTTP/1.1 101 Switching Protocols\r\n\r\n");
+        assert!(marks.upgraded.is_some() && marks.first_byte.is_none());
+        marks.mark_upgrade_response(b"HTTP/1.1 101 Switching Protocols\r\n\r\n\x81\x17RMBTv1.0");
+        assert!(marks.first_byte.unwrap() >= marks.upgraded.unwrap());
+    }
+}
@@ -0,0 +1,259 @@
+//! `-I IFACE` / `-B ADDRESS`: the local end of every client connection, for
+//! probes with several uplinks. Measurement connections, server probes, name
+//! lookups and control server requests all leave through the chosen
+//! interface or address, and over the address family of `-4` / `-6`.
+
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use crate::client::dns;
+
+/// `-4` / `-6`: the address family every connection uses
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressFamily {
+    #[default]
+    Any,
+    V4,
+    V6,
+}
+
+impl AddressFamily {
+    pub fn matches(&self, address: &IpAddr) -> bool {
+        match self {
+            Self::Any => true,
+            Self::V4 => address.is_ipv4(),
+            Self::V6 => address.is_ipv6(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Any => "any",
+            Self::V4 => "IPv4",
+            Self::V6 => "IPv6",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceBinding {
+    /// Interface name, bound with `SO_BINDTODEVICE`
+    pub interface: Option<String>,
+    /// Source address, bound before connecting
+    pub address: Option<IpAddr>,
+    pub family: AddressFamily,
+}
+
+impl SourceBinding {
+    /// Connections go wherever the OS routes them
+    pub fn is_default(&self) -> bool {
+        self.interface.is_none() && self.address.is_none()
+    }
+
+    /// Unconnected socket for talking to `peer`, bound to the interface and
+    /// source address
+    pub(crate) fn socket(&self, peer: &SocketAddr, ty: Type) -> io::Result<Socket> {
+        let protocol = if ty == Type::DGRAM { Protocol::UDP } else { Protocol::TCP };
+        let socket = Socket::new(Domain::for_address(*peer), ty, Some(protocol))?;
+        if let Some(interface) = &self.interface {
+            bind_device(&socket, interface)?;
+        }
+        if let Some(address) = self.address {
+            if address.is_ipv4() != peer.is_ipv4() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("source address {} can't reach {}", address, peer),
+                ));
+            }
+            socket
+                .bind(&SockAddr::from(SocketAddr::new(address, 0)))
+                .map_err(|e| io::Error::new(e.kind(), format!("can't bind source address {}: {}", address, e)))?;
+        }
+        Ok(socket)
+    }
+
+    /// Starts a non-blocking connect for the measurement event loops
+    pub fn connect(&self, addr: SocketAddr) -> io::Result<mio::net::TcpStream> {
+        if self.is_default() {
+            return mio::net::TcpStream::connect(addr);
+        }
+        let socket = self.socket(&addr, Type::STREAM)?;
+        socket.set_nonblocking(true)?;
+        match socket.connect(&addr.into()) {
+            Ok(()) => {}
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) || e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+        Ok(mio::net::TcpStream::from_std(socket.into()))
+    }
+
+    pub async fn connect_async(&self, addr: SocketAddr) -> io::Result<tokio::net::TcpStream> {
+        if self.is_default() {
+            return tokio::net::TcpStream::connect(addr).await;
+        }
+        let socket = self.socket(&addr, Type::STREAM)?;
+        socket.set_nonblocking(true)?;
+        tokio::net::TcpSocket::from_std_stream(socket.into()).connect(addr).await
+    }
+
+    /// Addresses of `host` in the chosen family. With a binding the lookup
+    /// goes through the stub resolver, so it leaves through the same interface.
+    pub fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let addresses: Vec<SocketAddr> =
+            if let Ok(ip) = host.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+                vec![SocketAddr::new(ip, port)]
+            } else if self.is_default() {
+                (host, port).to_socket_addrs()?.collect()
+            } else {
+                dns::lookup(host, self)?
+                    .into_iter()
+                    .map(|ip| SocketAddr::new(ip, port))
+                    .collect()
+            };
+        let addresses: Vec<SocketAddr> = addresses
+            .into_iter()
+            .filter(|address| self.family.matches(&address.ip()))
+            .collect();
+        if addresses.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no {} address", host, self.family.as_str()),
+            ));
+        }
+        Ok(addresses)
+    }
+
+    /// HTTP client for requests to `url`, from the source address or the
+    /// interface's address, with the host resolved by `resolve`. reqwest
+    /// can't bind to a device, so `-I` alone relies on the interface address
+    /// being routed through the interface.
+    pub fn http_client(&self, url: &str) -> anyhow::Result<reqwest::Client> {
+        let mut builder = reqwest::Client::builder();
+        if self.is_default() && self.family == AddressFamily::Any {
+            return Ok(builder.build()?);
+        }
+        let url = reqwest::Url::parse(url)?;
+        if let Some(host) = url.host_str() {
+            let addresses = self.resolve(host, url.port_or_known_default().unwrap_or(80))?;
+            builder = builder.resolve_to_addrs(host, &addresses);
+            let local_address = match self.address {
+                Some(address) => Some(address),
+                None => addresses.first().and_then(|peer| self.interface_address(peer.is_ipv4())),
+            };
+            builder = builder.local_address(local_address);
+        }
+        Ok(builder.build()?)
+    }
+
+    /// Interface the connections leave through: the one given with `-I`, or
+    /// the one holding the `-B` address
+    pub fn interface_name(&self) -> Option<String> {
+        if self.interface.is_some() {
+            return self.interface.clone();
+        }
+        let address = self.address?;
+        if_addrs::get_if_addrs()
+            .ok()?
+            .into_iter()
+            .find(|candidate| candidate.ip() == address)
+            .map(|candidate| candidate.name)
+    }
+
+    /// First address of the interface in the family of the peer
+    fn interface_address(&self, ipv4: bool) -> Option<IpAddr> {
+        let interface = self.interface.as_ref()?;
+        if_addrs::get_if_addrs()
+            .ok()?
+            .into_iter()
+            .filter(|candidate| &candidate.name == interface && !candidate.is_link_local())
+            .map(|candidate| candidate.ip())
+            .find(|ip| ip.is_ipv4() == ipv4)
+    }
+}
+
+impl fmt::Display for SourceBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.interface, self.address) {
+            (Some(interface), Some(address)) => write!(f, "{} ({})", interface, address),
+            (Some(interface), None) => write!(f, "{}", interface),
+            (None, Some(address)) => write!(f, "{}", address),
+            (None, None) => write!(f, "default route"),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_device(socket: &Socket, interface: &str) -> io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes())).map_err(|e| {
+        let hint = if e.kind() == io::ErrorKind::PermissionDenied {
+            ", binding to an interface needs CAP_NET_RAW"
+        } else {
+            ""
+        };
+        io::Error::new(e.kind(), format!("can't bind to interface {}: {}{}", interface, e, hint))
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_device(_socket: &Socket, interface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!("binding to interface {} is only supported on Linux, use -B with its address", interface),
+    ))
+}
+
+/// `-B` value
+pub fn parse_source_address(value: &str) -> Result<IpAddr, String> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| format!("invalid source address '{}', expected an IP address of this host", value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loopback() -> SourceBinding {
+        SourceBinding {
+            address: Some("127.0.0.1".parse().unwrap()),
+            ..SourceBinding::default()
+        }
+    }
+
+    #[test]
+    fn test_binds_source_address_before_connecting() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = listener.local_addr().unwrap();
+        let source = loopback();
+        let socket = source.socket(&server, Type::STREAM).unwrap();
+        socket.connect(&server.into()).unwrap();
+        let (_, peer) = listener.accept().unwrap();
+        assert_eq!(peer.ip(), source.address.unwrap());
+    }
+
+    #[test]
+    fn test_source_address_family_mismatch() {
+        let ipv6 = "[::1]:5005".parse().unwrap();
+        assert!(loopback().socket(&ipv6, Type::STREAM).is_err());
+        assert_eq!(loopback().resolve("192.0.2.1", 80).unwrap(), ["192.0.2.1:80".parse().unwrap()]);
+    }
+
+    #[test]
+    fn test_parse_source_address_invalid() {
+        assert!(parse_source_address("eth0").is_err());
+    }
+
+    #[test]
+    fn test_resolve_restricted_to_family() {
+        let ipv6_only = SourceBinding {
+            family: AddressFamily::V6,
+            ..SourceBinding::default()
+        };
+        assert!(ipv6_only.resolve("192.0.2.1", 80).is_err());
+        assert_eq!(ipv6_only.resolve("[2001:db8::1]", 80).unwrap(), ["[2001:db8::1]:80".parse().unwrap()]);
+    }
+}
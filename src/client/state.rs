@@ -1,19 +1,28 @@
 use anyhow::Result;
 use log::{debug, info, trace};
-use mio::{Events, Interest, Poll, Token};
+use mio::{event::Event, Interest, Poll, Token};
 use std::collections::VecDeque;
+use std::sync::Arc;
 use std::time::Instant;
-use std::{net::SocketAddr, path::Path, time::Duration};
+use std::{net::SocketAddr, path::Path};
 use std::io;
 
+use crate::client::setup::SetupMarks;
+use crate::client::source::SourceBinding;
+
 use crate::client::handlers::basic_handler::{
     handle_client_readable_data, handle_client_writable_data,
 };
-use crate::client::constants::{MIN_CHUNK_SIZE};
+use crate::client::constants::{MIN_CHUNK_SIZE, THROUGHPUT_SAMPLE_INTERVAL};
+use crate::client::durations::{PhaseDurations, GREETING_TIMEOUT, SIGNED_RESULT_TIMEOUT};
+use crate::client::observer::{MeasurementObserver, MeasurementPhase, ThroughputSample};
 use crate::stream::stream::Stream;
 
 pub const ONE_SECOND_NS: u128 = 1_000_000_000;
 
+/// Size of the read and write buffers of a connection
+const BUFFER_SIZE: usize = 1024 * 8 * 16;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum TestPhase {
     GreetingSendConnectionType,
@@ -61,10 +70,12 @@ pub enum TestPhase {
     SignedResultCompleted,
 }
 
-pub struct TestState {
-    poll: Poll,
-    events: Events,
-    measurement_state: MeasurementState,
+/// Passes the ping samples and throughput of one connection to an observer
+pub struct ProgressReporter {
+    observer: Option<Arc<dyn MeasurementObserver>>,
+    /// Ping samples already passed to the observer
+    reported_pings: usize,
+    last_sample: Instant,
 }
 
 #[derive(Debug)]
@@ -78,8 +89,10 @@ pub struct MeasurementState {
     pub chunk_size: usize,
     pub ping_median: Option<u64>,
     pub phase_start_time: Option<Instant>,
-    pub read_buffer: [u8; 1024 * 8 * 16],
-    pub write_buffer: [u8; 1024 * 8 * 16],
+    /// End of the timeout of the phase being processed
+    pub phase_deadline: Option<Instant>,
+    pub read_buffer: Box<[u8]>,
+    pub write_buffer: Box<[u8]>,
     pub read_pos: usize,
     pub write_pos: usize,
     pub download_measurements: VecDeque<(u64, u64)>, // Stores (t_k^(j), b_k^(j)) for each chunk
@@ -95,42 +108,52 @@ pub struct MeasurementState {
     pub bytes_sent: u64,
     pub time_result_buffer: Vec<u8>,
     pub envelope: Option<String>,
+    pub durations: PhaseDurations,
+    /// Bytes and server time of the last GETCHUNKS round
+    pub pre_download_bytes: u64,
+    pub pre_download_time_ns: u64,
+    /// Start of the connect, the setup steps are timed from here
+    pub setup_started: Instant,
+    pub setup: SetupMarks,
 }
 
-impl TestState {
-    pub fn new(
+impl MeasurementState {
+    /// Opens the connection and registers it with `poll` under `token`
+    #[allow(clippy::too_many_arguments)]
+    pub fn connect(
         addr: SocketAddr,
+        source: &SourceBinding,
         use_tls: bool,
         use_websocket: bool,
-        tok: usize,
+        token: Token,
+        poll: &Poll,
         cert_path: Option<&Path>,
         key_path: Option<&Path>,
     ) -> Result<Self> {
-        let mut poll = Poll::new()?;
-        let events = Events::with_capacity(2048);
-        let token = Token(tok);
+        let setup_started = Instant::now();
         let mut stream = if use_tls && use_websocket {
             debug!("Creating WebSocket TLS stream");
-            let stream = Stream::new_websocket_tls(addr)?;
+            let stream = Stream::new_websocket_tls(addr, source)?;
             debug!("WebSocket TLS stream created");
             stream
         } else if use_tls {
             debug!("Creating Rustls stream {:?}", addr);
-            Stream::new_rustls(addr, cert_path, key_path)?
+            Stream::new_rustls(addr, source, cert_path, key_path)?
         } else {
             if use_websocket {
                 debug!("Creating WebSocket stream");
-                Stream::new_websocket(addr)?
+                Stream::new_websocket(addr, source)?
             } else {
-                Stream::new_tcp(addr)?
+                Stream::new_tcp(addr, source)?
             }
         };
 
         debug!("Registering stream");
-        stream.register(&mut poll, token, Interest::READABLE | Interest::WRITABLE)?;
+        stream.register(poll, token, Interest::READABLE | Interest::WRITABLE)?;
         debug!("Stream registered");
+        let setup = stream.setup_marks();
 
-        let measurement_state = MeasurementState {
+        Ok(MeasurementState {
             phase: TestPhase::GreetingSendConnectionType,
             upload_bytes: None,
             upload_time: None,
@@ -138,13 +161,14 @@ impl TestState {
             download_time: None,
             chunk_size: MIN_CHUNK_SIZE as usize,
             ping_median: None,
-            read_buffer: [0u8; 1024 * 8 * 16],
+            read_buffer: vec![0u8; BUFFER_SIZE].into_boxed_slice(),
             download_measurements: VecDeque::new(),
             upload_measurements: VecDeque::new(),
             phase_start_time: None,
+            phase_deadline: None,
             failed: false,
             token,
-            write_buffer: [0u8; 1024 * 8 * 16],
+            write_buffer: vec![0u8; BUFFER_SIZE].into_boxed_slice(),
             read_pos: 0,
             write_pos: 0,
             stream,
@@ -157,168 +181,160 @@ impl TestState {
             bytes_sent: 0,
             time_result_buffer: Vec::new(),
             envelope: None,
-        };
-
-
-        Ok(Self {
-            poll,
-            events,
-            measurement_state,
+            durations: PhaseDurations::default(),
+            pre_download_bytes: 0,
+            pre_download_time_ns: 0,
+            setup_started,
+            setup,
         })
     }
 
-    pub fn process_greeting(&mut self) -> Result<&mut TestState> {
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE | Interest::READABLE,
-        )?;
-
-        debug!("Greeting process_greeting");
-        self.process_phase(TestPhase::GreetingCompleted, ONE_SECOND_NS * 50)?;
-
-        debug!("Greeting completed");
-
-        Ok(self)
+    /// Moves to the first protocol state of `phase` and starts its timeout.
+    /// Returns the state that completes the phase. `legacy` uploads with PUT
+    /// instead of PUTTIMERESULT.
+    pub fn begin(&mut self, poll: &Poll, phase: MeasurementPhase, legacy: bool) -> Result<TestPhase> {
+        let durations = self.durations;
+        let (first, completed, timeout) = match phase {
+            MeasurementPhase::Connect => (
+                TestPhase::GreetingSendConnectionType,
+                TestPhase::GreetingCompleted,
+                GREETING_TIMEOUT,
+            ),
+            MeasurementPhase::PreDownload => (
+                TestPhase::GetChunksSendChunksCommand,
+                TestPhase::GetChunksCompleted,
+                durations.pre_download_timeout(),
+            ),
+            MeasurementPhase::Ping => (
+                TestPhase::PingSendPing,
+                TestPhase::PingCompleted,
+                durations.ping_timeout(),
+            ),
+            MeasurementPhase::Download => (
+                TestPhase::GetTimeSendCommand,
+                TestPhase::GetTimeCompleted,
+                durations.download_timeout(),
+            ),
+            MeasurementPhase::Upload if legacy => (
+                TestPhase::PutSendCommand,
+                TestPhase::PutCompleted,
+                durations.upload_timeout(),
+            ),
+            MeasurementPhase::Upload => (
+                TestPhase::PerfSendCommand,
+                TestPhase::PerfCompleted,
+                durations.upload_timeout(),
+            ),
+            MeasurementPhase::SignedResult => (
+                TestPhase::SignedResultSend,
+                TestPhase::SignedResultCompleted,
+                SIGNED_RESULT_TIMEOUT,
+            ),
+        };
+        // The greeting starts by itself once the connection is writable
+        let interest = if phase == MeasurementPhase::Connect {
+            Interest::WRITABLE | Interest::READABLE
+        } else {
+            Interest::WRITABLE
+        };
+        self.phase = first;
+        self.stream.reregister(poll, self.token, interest)?;
+        self.phase_start_time = Some(Instant::now());
+        self.phase_deadline = Some(Instant::now() + timeout);
+        Ok(completed)
     }
 
-    pub fn run_signed_result(&mut self) -> Result<()> {
-        self.measurement_state.phase = TestPhase::SignedResultSend;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::SignedResultCompleted, ONE_SECOND_NS * 12)?;
-        Ok(())
-    }
+    /// Runs the handler for a readiness event of this connection. Returns an
+    /// error once the connection failed.
+    pub fn handle_event(&mut self, poll: &Poll, event: &Event) -> Result<()> {
+        let mut result: Result<usize, io::Error> = Ok(0);
+        // The first readiness of a non-blocking connect is its completion
+        if self.setup.connected.is_none() && !event.is_error() {
+            self.setup.connected = Some(Instant::now());
+        }
+        if event.is_readable() {
+            result = handle_client_readable_data(self, poll);
+        } else if event.is_writable() {
+            result = handle_client_writable_data(self, poll);
+        }
 
-    pub fn run_perf_test(&mut self) -> Result<()> {
-        self.measurement_state.phase = TestPhase::PerfSendCommand;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::PerfCompleted, ONE_SECOND_NS * 12)?;
-        Ok(())
-    }
+        if let Stream::Rustls(stream) = &self.stream {
+            self.setup.mark_tls(&stream.conn);
+        }
 
-    pub fn run_ping(&mut self) -> Result<()> {
-        self.measurement_state.phase = TestPhase::PingSendPing;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::PingCompleted, ONE_SECOND_NS * 3)?;
-        Ok(())
+        match result {
+            Ok(0) => {
+                info!("No data to read for token {:?} phase: {:?}", self.token, self.phase);
+                self.failed = true;
+                Err(anyhow::anyhow!("No data in {:?}", self.phase))
+            }
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                trace!("WouldBlock");
+                Ok(())
+            }
+            Err(e) => {
+                info!("Error: {:?} for token {:?} phase: {:?}", e, self.token, self.phase);
+                self.failed = true;
+                Err(anyhow::anyhow!("{} in {:?}", e, self.phase))
+            }
+        }
     }
 
-    pub fn run_get_chunks(&mut self) -> Result<()> {
-        debug!("Run get chunks");
-        self.measurement_state.phase = TestPhase::GetChunksSendChunksCommand;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::GetChunksCompleted, ONE_SECOND_NS * 3)?;
-        debug!("Run get chunks completed");
-        Ok(())
+    /// Whether the phase timeout started by `begin` has passed
+    pub fn timed_out(&self) -> bool {
+        self.phase_deadline
+            .is_some_and(|deadline| Instant::now() > deadline)
     }
+}
 
-    pub fn run_get_time(&mut self) -> Result<()> {
-        self.measurement_state.phase = TestPhase::GetTimeSendCommand;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::GetTimeCompleted, ONE_SECOND_NS * 12)?;
-        Ok(())
+impl ProgressReporter {
+    pub fn new(observer: Option<Arc<dyn MeasurementObserver>>) -> Self {
+        Self {
+            observer,
+            reported_pings: 0,
+            last_sample: Instant::now(),
+        }
     }
 
-    pub fn run_put(&mut self) -> Result<()> {
-        self.measurement_state.phase = TestPhase::PutSendCommand;
-        self.measurement_state.stream.reregister(
-            &mut self.poll,
-            self.measurement_state.token,
-            Interest::WRITABLE,
-        )?;
-        self.process_phase(TestPhase::PutCompleted, ONE_SECOND_NS * 10)?;
-        Ok(())
+    /// Starts the sample interval over, called when a phase begins
+    pub fn restart(&mut self) {
+        self.last_sample = Instant::now();
     }
 
-    fn process_phase(
-        &mut self,
-        phase: TestPhase,
-        test_duration_ns: u128,
-    ) -> Result<()> {
-        if self.measurement_state.failed {
-            return Ok(());
+    /// Passes new ping samples and, once per sample interval, the bytes
+    /// transferred so far to the observer
+    pub fn report(&mut self, state: &MeasurementState) {
+        let Some(observer) = &self.observer else {
+            return;
+        };
+        for rtt_ns in &state.ping_times[self.reported_pings..] {
+            observer.on_ping_sample(state.token.0, *rtt_ns);
         }
+        self.reported_pings = state.ping_times.len();
 
-        self.measurement_state.phase_start_time = Some(Instant::now());
-
-        while self.measurement_state.phase != phase {
-            self.poll
-                .poll(&mut self.events, Some(Duration::from_nanos(test_duration_ns as u64)))?;
-
-            if self.events.is_empty() {
-                let elapsed = self
-                    .measurement_state
-                    .phase_start_time
-                    .unwrap()
-                    .elapsed()
-                    .as_nanos();
-                if elapsed > test_duration_ns {
-                    info!(
-                        "Test duration exceeded {:?} for token {:?}",
-                        self.measurement_state.phase, self.measurement_state.token
-                    );
-                    self.measurement_state.failed = true;
-                    return Err(anyhow::anyhow!("Test duration exceeded"));
-                }
-            }
-
-            for event in self.events.iter() {
-
-            // Process events in the current poll iteration
-            let mut should_remove: Result<usize, io::Error> = Ok(0);
-
-            if event.is_readable() {
-                should_remove = handle_client_readable_data(&mut self.measurement_state, &self.poll);
-            } else if event.is_writable() {
-                should_remove = handle_client_writable_data(&mut self.measurement_state, &self.poll);
-            }
-
-                match should_remove {
-                    Ok(n) => {
-                        if n == 0 {
-                            info!("No data to read for token {:?} phase: {:?}", self.measurement_state.token, self.measurement_state.phase);
-                            self.measurement_state.failed = true;
-                        }
-                        // If n > 0, continue processing
-                    }
-                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                        trace!("WouldBlock");
-                        continue;
-                    }
-                    Err(e) => {
-                        info!("Error: {:?} for token {:?} phase: {:?}", e, self.measurement_state.token, self.measurement_state.phase);
-                        self.measurement_state.failed = true;
-                        break;
-                    }
-                }
-            }
+        let phase = MeasurementPhase::from(&state.phase);
+        let bytes = match phase {
+            MeasurementPhase::Download => state.bytes_received,
+            MeasurementPhase::Upload => state.bytes_sent,
+            _ => return,
+        };
+        let finished = matches!(
+            state.phase,
+            TestPhase::GetTimeCompleted | TestPhase::PerfCompleted | TestPhase::PutCompleted
+        );
+        if self.last_sample.elapsed() < THROUGHPUT_SAMPLE_INTERVAL && !finished {
+            return;
         }
-
-        Ok(())
-    }
-
-    pub fn measurement_state(&self) -> &MeasurementState {
-        &self.measurement_state
+        self.last_sample = Instant::now();
+        observer.on_throughput_sample(&ThroughputSample {
+            thread_id: state.token.0,
+            phase,
+            elapsed_ns: state
+                .phase_start_time
+                .map(|start| start.elapsed().as_nanos() as u64)
+                .unwrap_or(0),
+            bytes,
+        });
     }
-
 }
@@ -1,7 +1,19 @@
 use log::LevelFilter;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::client::auto_threads::ThreadThresholds;
+use crate::client::control_server::DEFAULT_PROBE_COUNT;
+use crate::client::durations::PhaseDurations;
+use crate::client::phases::PhaseSelection;
+use crate::client::schedule::Schedule;
+use crate::logger::LogOptions;
+use crate::mioserver::handlers::timeout_utils::MAX_TRANSFER_SECS;
 
 pub mod constants;
 pub mod parser;
+pub mod paths;
 
 #[derive(Debug, Clone)]
 pub enum App {
@@ -12,20 +24,49 @@ pub enum App {
 #[derive(Debug, Clone)]
 pub struct FileConfig {
     pub app: App,
-    pub server_tcp_port: String,
-    pub server_tls_port: Option<String>,
+    pub server_tcp_port: u16,
+    pub server_tls_port: Option<u16>,
     pub cert_path: Option<String>,
     pub key_path: Option<String>,
+    pub self_signed: bool,
+    pub proxy_protocol: bool,
+    pub trusted_proxies: Option<String>,
     pub server_workers: Option<usize>,
     pub user: Option<String>,
+    pub group: Option<String>,
+    pub pid_file: Option<String>,
     pub daemonize: bool,
     pub use_websocket: bool,
     pub use_tls: bool,
     pub client_use_tls: bool,
     pub client_use_websocket: bool,
     pub client_thread_count: usize,
+    /// `client_thread_count = auto`
+    pub client_auto_threads: bool,
+    pub client_thread_thresholds: ThreadThresholds,
+    /// `client_*_duration`, `client_ping_count` and `client_warmup`
+    pub client_durations: PhaseDurations,
+    pub client_phases: PhaseSelection,
+    pub client_retries: u32,
+    /// 0 for one event loop per CPU core
+    pub client_event_loops: usize,
+    /// Nearest servers probed before auto-selecting one, 0 for the nearest
+    pub client_probe_servers: usize,
+    pub client_schedule: Option<Schedule>,
+    /// Append every client run to the history file
+    pub client_history: bool,
+    /// Default `$XDG_DATA_HOME/nettest/history.jsonl`
+    pub client_history_file: Option<PathBuf>,
+    /// Address the client serves Prometheus metrics on
+    pub client_exporter: Option<SocketAddr>,
+    pub client_exporter_max_age: Duration,
+    /// Interface every client connection is bound to
+    pub client_interface: Option<String>,
+    /// Source address of every client connection
+    pub client_source_address: Option<IpAddr>,
     pub protocol_version: Option<u32>, //TODO None for latest, Some(3) for v0.3
     pub logger: LevelFilter,
+    pub log_options: LogOptions,
     pub x_nettest_client: String,
     pub control_server: String,
     pub server_registration: bool,
@@ -36,26 +77,55 @@ pub struct FileConfig {
     pub signed_result: bool,
     pub enable_mdns: bool,
     pub max_chunk_size: Option<u32>,
+    /// Longest download or upload in seconds the server grants a client
+    pub max_transfer_secs: u64,
+    /// Base64 key signed results are signed with, generated at start-up when unset
+    pub signing_key: Option<String>,
+    /// Config file the values were read from, if any
+    pub source: Option<PathBuf>,
+    /// Ignored keys and overrides and an unreadable config file, for the
+    /// caller to show
+    pub warnings: Vec<String>,
 }
 
 impl Default for FileConfig {
     fn default() -> Self {
         Self {
             app: App::Server,
-            server_tcp_port: "5005".to_string(),
+            server_tcp_port: 5005,
             server_tls_port: None,
             cert_path: None,
             key_path: None,
+            self_signed: false,
+            proxy_protocol: false,
+            trusted_proxies: None,
             server_workers: None,
             user: None,
+            group: None,
+            pid_file: None,
             daemonize: false,
             use_websocket: false,
             use_tls: false,
             protocol_version: None, 
             logger: LevelFilter::Off,
+            log_options: LogOptions::default(),
             client_use_tls: false,
             client_use_websocket: false,
             client_thread_count: 3,
+            client_auto_threads: false,
+            client_thread_thresholds: ThreadThresholds::default(),
+            client_durations: PhaseDurations::default(),
+            client_phases: PhaseSelection::default(),
+            client_retries: 0,
+            client_event_loops: 0,
+            client_probe_servers: DEFAULT_PROBE_COUNT,
+            client_schedule: None,
+            client_history: true,
+            client_history_file: None,
+            client_exporter: None,
+            client_exporter_max_age: Duration::from_secs(300),
+            client_interface: None,
+            client_source_address: None,
             x_nettest_client: "nt".to_string(),
             control_server: "https://api.nettest.org".to_string(),
             server_registration: false,
@@ -66,6 +136,10 @@ impl Default for FileConfig {
             signed_result: false,
             enable_mdns: false,
             max_chunk_size: None,
+            max_transfer_secs: MAX_TRANSFER_SECS,
+            signing_key: None,
+            source: None,
+            warnings: Vec::new(),
         }
     }
 }
@@ -1,181 +1,333 @@
 use log::LevelFilter;
 
-use crate::config::FileConfig;
+use crate::client::durations::parse_duration;
+use crate::client::export::prometheus::parse_exporter_address;
+use crate::client::schedule::parse_period;
+use crate::client::source::parse_source_address;
+use crate::config::{paths, FileConfig};
+use std::env;
+use std::ffi::OsString;
+use std::fs;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-pub fn read_config_file() -> Result<FileConfig, anyhow::Error> {
-    use std::env;
-    use std::fs;
-    use std::path::PathBuf;
+/// Environment variable naming the config file, same as `--config`
+const CONFIG_ENV: &str = "NETTEST_CONFIG";
+/// Defaults used when no config file exists
+const DEFAULT_CONFIG: &str = include_str!("../../nettest.conf");
+/// Prefix of the per-key overrides, e.g. `NETTEST_SERVER_TCP_PORT=5006`
+const ENV_PREFIX: &str = "NETTEST_";
 
-    // Determine config file path based on OS
-    let config_path = if cfg!(target_os = "macos") {
-        // On macOS use ~/.config/ as main directory
-        let home_dir = env::var("HOME").unwrap_or_else(|_| "/tmp".to_string());
-        PathBuf::from(format!("{}/.config/nettest.conf", home_dir))
-    } else {
-        PathBuf::from("/etc/nettest.conf")
-    };
+/// Removes `--config PATH` / `--config=PATH` from the arguments, so it can be
+/// given in front of or after the mode flag
+pub fn take_config_flag(args: &mut Vec<String>) -> Result<Option<PathBuf>, anyhow::Error> {
+    let mut config_path = None;
+    let mut i = 0;
+    while i < args.len() {
+        if args[i] == "--config" {
+            if i + 1 >= args.len() {
+                return Err(anyhow::anyhow!("--config requires a file path"));
+            }
+            config_path = Some(PathBuf::from(args.remove(i + 1)));
+            args.remove(i);
+        } else if let Some(path) = args[i].strip_prefix("--config=") {
+            config_path = Some(PathBuf::from(path));
+            args.remove(i);
+        } else {
+            i += 1;
+        }
+    }
+    Ok(config_path)
+}
+
+/// Builds the configuration from built-in defaults, the config file and
+/// `NETTEST_*` environment overrides, each layer overriding the previous one.
+/// Command line flags are applied on top by the client and server parsers.
+///
+/// The file is `--config PATH`, `$NETTEST_CONFIG`, or the first existing of
+/// the user config file and the system config file. Without any file the
+/// bundled `nettest.conf` is used. Nothing is written to disk or printed,
+/// settings that were ignored are returned in `FileConfig::warnings`.
+pub fn read_config_file(explicit_path: Option<&Path>) -> Result<FileConfig, anyhow::Error> {
+    let mut config = FileConfig::default();
+    let mut errors = Vec::new();
 
-    // Check permissions and read file
-    let config_content = if config_path.exists() {
+    if let Some(config_path) = resolve_config_path(explicit_path)? {
         match fs::read_to_string(&config_path) {
             Ok(content) => {
-                println!("Reading config from: {:?}", config_path);
-                content
+                let origin = config_path.display().to_string();
+                errors.extend(apply_config_content(&mut config, &content, &origin));
+                config.source = Some(config_path);
+            }
+            // A file that was asked for explicitly has to be readable
+            Err(e) if explicit_path.is_some() || env::var_os(CONFIG_ENV).is_some() => {
+                return Err(anyhow::anyhow!(
+                    "Could not read config file {:?}: {}",
+                    config_path,
+                    e
+                ));
             }
             Err(e) => {
-                println!(
-                    "Warning: Could not read config file {:?}: {}",
+                config.warnings.push(format!(
+                    "Could not read config file {:?}: {}",
                     config_path, e
-                );
-                String::new()
+                ));
             }
         }
     } else {
-        if let Some(parent) = config_path.parent() {
-            if !parent.exists() {
-                if let Err(e) = fs::create_dir_all(parent) {
-                    println!("Warning: Could not create config directory: {}", e);
-                }
-            }
-        }
-        let default_config = include_str!("../../nettest.conf");
+        // Same values a fresh install used to get written to disk
+        errors.extend(apply_config_content(
+            &mut config,
+            DEFAULT_CONFIG,
+            "built-in nettest.conf",
+        ));
+    }
 
-        if let Err(e) = fs::write(&config_path, default_config) {
-            println!(
-                "Warning: Could not create config file {:?}: {}",
-                config_path, e
-            );
-        } else {
-            println!("Created default config file at: {:?}", config_path);
-        }
+    errors.extend(apply_env_overrides(&mut config, env::vars_os()));
 
-        default_config.to_string()
-    };
+    if !errors.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Invalid configuration:\n  {}",
+            errors.join("\n  ")
+        ));
+    }
+    Ok(config)
+}
 
-    parse_config_content(&config_content)
+fn resolve_config_path(explicit_path: Option<&Path>) -> Result<Option<PathBuf>, anyhow::Error> {
+    let requested = explicit_path.map(Path::to_path_buf).or_else(|| {
+        env::var_os(CONFIG_ENV)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from)
+    });
+    if let Some(path) = requested {
+        if !path.is_file() {
+            return Err(anyhow::anyhow!("Config file {:?} not found", path));
+        }
+        return Ok(Some(path));
+    }
+
+    Ok(paths::user_config_file()
+        .into_iter()
+        .chain(std::iter::once(paths::system_config_file()))
+        .find(|path| path.is_file()))
 }
 
-fn parse_config_content(content: &str) -> Result<FileConfig, anyhow::Error> {
-    let mut config = FileConfig::default();
+/// Applies `key = value` lines, returning one message per invalid line.
+/// Unknown keys are reported but don't prevent start-up.
+fn apply_config_content(config: &mut FileConfig, content: &str, origin: &str) -> Vec<String> {
+    let mut errors = Vec::new();
 
-    for line in content.lines() {
+    for (index, line) in content.lines().enumerate() {
+        let line_number = index + 1;
         let line = line.trim();
 
         if line.is_empty() || line.starts_with('#') {
             continue;
         }
 
-        if let Some((key, value)) = line.split_once('=') {
-            let key = key.trim();
-            let value = value.trim().trim_matches('"');
+        let Some((key, value)) = line.split_once('=') else {
+            errors.push(format!("{}:{}: expected 'key = value'", origin, line_number));
+            continue;
+        };
+        let key = key.trim();
+        let value = value.trim().trim_matches('"');
 
-            match key {
-                "server_tcp_port" => {
-                    if let Ok(port) = value.parse::<u16>() {
-                        config.server_tcp_port = port.to_string();
-                    } else {
-                        config.server_tcp_port = "5005".to_string();
-                    }
-                }
-                "server_tls_port" => {
-                    if let Ok(port) = value.parse::<u16>() {
-                        config.server_tls_port = Some(port.to_string());
-                    } else {
-                        config.server_tls_port = Some("443".to_string());
-                    }
-                }
-                "cert_path" => config.cert_path = Some(value.to_string()),
-                "key_path" => config.key_path = Some(value.to_string()),
-                "server_workers" => {
-                    if let Ok(threads) = value.parse::<usize>() {
-                        config.server_workers = Some(threads);
-                    }
-                }
-                "user" => config.user = Some(value.to_string()),
-                "daemonize" => config.daemonize = value.parse().unwrap_or(false),
-                "use_websocket" => config.use_websocket = value.parse().unwrap_or(false),
-                "protocol_version" => {
-                    if let Ok(version) = value.parse::<u32>() {
-                        config.protocol_version = Some(version);
-                    }
-                }
-                // Client-specific settings
-                "client_use_tls" => {
-                    if value == "true" {
-                        config.client_use_tls = true;
-                    } else {
-                        config.client_use_tls = false;
-                    }
-                }
-                "client_use_websocket" => {
-                    if value == "true" {
-                        config.client_use_websocket = true;
-                    } else {
-                        config.client_use_websocket = false;
-                    }
-                }
-                // Logging settings
-                "logger" => {
-                    if value == "info" {
-                        config.logger = LevelFilter::Info;
-                    } else if value == "debug" {
-                        config.logger = LevelFilter::Debug;
-                    } else if value == "trace" {
-                        config.logger = LevelFilter::Trace;
-                    } else {
-                        println!("Unknown logger level: {}, using default", value);
-                        return Err(anyhow::anyhow!("Unknown logger level: {}", value));
-                    }
-                }
-                "server_registration" => {
-                    if value == "true" {
-                        config.server_registration = true;
-                    } else {
-                        config.server_registration = false;
-                    }
-                }
-                "server_name" => config.server_name = Some(value.to_string()),
-                "registration_token" => config.registration_token = Some(value.to_string()),
-                "hostname" => config.hostname = Some(value.to_string()),
-                "x_nettest_client" => config.x_nettest_client = value.to_string(),
-                "control_server" => config.control_server = value.to_string(),
-                "client_uuid" => {
-                    // Remove quotes if present
-                    let clean_value = value.trim_matches('"').trim();
-                    if !clean_value.is_empty() {
-                        config.client_uuid = Some(clean_value.to_string());
-                    }
-                }
-                "signed_result" => {
-                    config.signed_result = value.parse().unwrap_or(false);
-                }
-                "enable_mdns" => {
-                    if value == "true" {
-                        config.enable_mdns = true;
-                    } else {
-                        config.enable_mdns = false;
-                    }
-                }
-                "max_chunk_size" => {
-                    if let Ok(size) = value.parse::<u32>() {
-                        config.max_chunk_size = Some(size);
-                    } else {
-                        println!("Warning: Invalid max_chunk_size value: {}, using default", value);
-                    }
-                }
-                _ => {
-                    //return error
-                    println!("Warning: Unknown config key: {}", key);
-                    return Err(anyhow::anyhow!("Unknown config key: {}", key));
-                }
+        match apply_setting(config, key, value) {
+            Ok(true) => {}
+            Ok(false) => config.warnings.push(format!(
+                "{}:{}: unknown config key '{}', ignored",
+                origin, line_number, key
+            )),
+            Err(e) => errors.push(format!("{}:{}: {}", origin, line_number, e)),
+        }
+    }
+
+    errors
+}
+
+/// Applies `NETTEST_<KEY>` variables, e.g. `NETTEST_LOGGER=debug`. Other
+/// variables are skipped, as are names or values that are not UTF-8.
+fn apply_env_overrides<I>(config: &mut FileConfig, vars: I) -> Vec<String>
+where
+    I: IntoIterator<Item = (OsString, OsString)>,
+{
+    let mut errors = Vec::new();
+
+    for (name, value) in vars {
+        let Some(name) = name.to_str() else {
+            continue;
+        };
+        if name == CONFIG_ENV {
+            continue;
+        }
+        let Some(key) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let Some(value) = value.to_str() else {
+            config.warnings.push(format!("environment override {} is not valid UTF-8, ignored", name));
+            continue;
+        };
+        match apply_setting(config, &key.to_ascii_lowercase(), value.trim()) {
+            Ok(true) => {}
+            Ok(false) => config.warnings.push(format!("unknown environment override {}, ignored", name)),
+            Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+    }
+
+    errors
+}
+
+/// Sets a single config key. Returns `Ok(false)` for unknown keys.
+fn apply_setting(config: &mut FileConfig, key: &str, value: &str) -> Result<bool, String> {
+    match key {
+        "server_tcp_port" => config.server_tcp_port = parse_port(key, value)?,
+        "server_tls_port" => config.server_tls_port = Some(parse_port(key, value)?),
+        "cert_path" => config.cert_path = optional(value),
+        "key_path" => config.key_path = optional(value),
+        "self_signed" => config.self_signed = parse_bool(key, value)?,
+        "proxy_protocol" => config.proxy_protocol = parse_bool(key, value)?,
+        "trusted_proxies" => config.trusted_proxies = optional(value),
+        "server_workers" => config.server_workers = Some(parse_positive(key, value)?),
+        "user" => config.user = optional(value),
+        "group" => config.group = optional(value),
+        "pid_file" => config.pid_file = optional(value),
+        "daemonize" => config.daemonize = parse_bool(key, value)?,
+        "use_websocket" => config.use_websocket = parse_bool(key, value)?,
+        "protocol_version" => config.protocol_version = Some(parse_number(key, value)?),
+        // Client-specific settings
+        "client_use_tls" => config.client_use_tls = parse_bool(key, value)?,
+        "client_use_websocket" => config.client_use_websocket = parse_bool(key, value)?,
+        "client_thread_count" => {
+            config.client_auto_threads = value.eq_ignore_ascii_case("auto");
+            if !config.client_auto_threads {
+                config.client_thread_count = parse_positive(key, value)?;
             }
         }
+        "client_thread_thresholds" => {
+            config.client_thread_thresholds = value.parse().map_err(|e| format!("{} for {}", e, key))?
+        }
+        "client_pre_download_duration" => {
+            config.client_durations.pre_download = parse_seconds(key, value)?
+        }
+        "client_ping_duration" => config.client_durations.ping = parse_seconds(key, value)?,
+        "client_ping_count" => config.client_durations.ping_count = parse_positive(key, value)?,
+        "client_download_duration" => config.client_durations.download = parse_seconds(key, value)?,
+        "client_upload_duration" => config.client_durations.upload = parse_seconds(key, value)?,
+        "client_warmup" => config.client_durations.warmup = parse_seconds(key, value)?,
+        "client_phases" => {
+            config.client_phases = value.parse().map_err(|e| format!("{} for {}", e, key))?
+        }
+        "client_retries" => config.client_retries = parse_number(key, value)?,
+        "client_event_loops" => config.client_event_loops = parse_number(key, value)?,
+        "client_probe_servers" => config.client_probe_servers = parse_number(key, value)?,
+        "client_schedule" => {
+            config.client_schedule = optional(value)
+                .map(|value| value.parse())
+                .transpose()
+                .map_err(|e| format!("{} for {}", e, key))?
+        }
+        "client_history" => config.client_history = parse_bool(key, value)?,
+        "client_history_file" => config.client_history_file = optional(value).map(PathBuf::from),
+        "client_exporter" => {
+            config.client_exporter = optional(value)
+                .map(|value| parse_exporter_address(&value))
+                .transpose()
+                .map_err(|e| format!("{} for {}", e, key))?
+        }
+        "client_exporter_max_age" => {
+            config.client_exporter_max_age = parse_period(value).map_err(|e| format!("{} for {}", e, key))?
+        }
+        "client_interface" => config.client_interface = optional(value),
+        "client_source_address" => {
+            config.client_source_address = optional(value)
+                .map(|value| parse_source_address(&value))
+                .transpose()
+                .map_err(|e| format!("{} for {}", e, key))?
+        }
+        // Logging settings
+        "logger" => {
+            config.logger = LevelFilter::from_str(value).map_err(|_| {
+                format!(
+                    "invalid value '{}' for logger, expected off, error, warn, info, debug or trace",
+                    value
+                )
+            })?
+        }
+        "log_target" => config.log_options.target = value.parse()?,
+        "log_file" => config.log_options.path = optional(value).map(PathBuf::from),
+        "log_format" => config.log_options.format = value.parse()?,
+        "log_rotate" => config.log_options.rotation = value.parse()?,
+        "log_keep" => config.log_options.keep = parse_positive(key, value)?,
+        "server_registration" => config.server_registration = parse_bool(key, value)?,
+        "server_name" => config.server_name = optional(value),
+        "registration_token" => config.registration_token = optional(value),
+        "hostname" => config.hostname = optional(value),
+        "x_nettest_client" => config.x_nettest_client = value.to_string(),
+        "control_server" => config.control_server = value.to_string(),
+        "client_uuid" => config.client_uuid = optional(value),
+        "signed_result" => config.signed_result = parse_bool(key, value)?,
+        "enable_mdns" => config.enable_mdns = parse_bool(key, value)?,
+        "max_chunk_size" => config.max_chunk_size = Some(parse_positive(key, value)?),
+        "max_transfer_secs" => config.max_transfer_secs = parse_positive(key, value)?,
+        "signing_key" => config.signing_key = parse_signing_key(key, value)?,
+        _ => return Ok(false),
     }
+    Ok(true)
+}
 
-    Ok(config)
+/// Empty values leave the setting unset, e.g. `user = ""`
+fn optional(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Base64 as written by `generate_secret_key`, empty for a generated key
+fn parse_signing_key(key: &str, value: &str) -> Result<Option<String>, String> {
+    let Some(value) = optional(value) else {
+        return Ok(None);
+    };
+    match base64::Engine::decode(&base64::engine::general_purpose::STANDARD, &value) {
+        Ok(decoded) if !decoded.is_empty() => Ok(Some(value)),
+        _ => Err(format!("invalid value for {}, expected a base64 encoded key", key)),
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!(
+            "invalid value '{}' for {}, expected true or false",
+            value, key
+        )),
+    }
+}
+
+fn parse_port(key: &str, value: &str) -> Result<u16, String> {
+    value
+        .parse::<u16>()
+        .map_err(|_| format!("invalid value '{}' for {}, expected a port (0-65535)", value, key))
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse::<T>()
+        .map_err(|_| format!("invalid value '{}' for {}, expected a number", value, key))
+}
+
+fn parse_seconds(key: &str, value: &str) -> Result<Duration, String> {
+    parse_duration(value).map_err(|e| format!("{} for {}", e, key))
+}
+
+fn parse_positive<T: FromStr + Default + PartialEq>(key: &str, value: &str) -> Result<T, String> {
+    let number = parse_number::<T>(key, value)?;
+    if number == T::default() {
+        return Err(format!("{} must be greater than 0", key));
+    }
+    Ok(number)
 }
 
 pub fn parse_listen_address(addr: &str) -> Result<SocketAddr, Box<dyn Error + Send + Sync>> {
@@ -232,3 +384,124 @@ pub fn parse_listen_address(addr: &str) -> Result<SocketAddr, Box<dyn Error + Se
 
     Err(format!("Invalid listen address format: {}", addr).into())
 }
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(OsString, OsString)> {
+        vars.iter()
+            .map(|(k, v)| (OsString::from(k), OsString::from(v)))
+            .collect()
+    }
+
+    #[test]
+    fn test_reports_every_invalid_value_with_line_number() {
+        let mut config = FileConfig::default();
+        let content = "server_tcp_port = 5006\nserver_workers = many\n# comment\nlogger = loud\nenable_mdns = maybe\n";
+        let errors = apply_config_content(&mut config, content, "test.conf");
+
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].starts_with("test.conf:2: "));
+        assert!(errors[1].starts_with("test.conf:4: "));
+        assert!(errors[2].starts_with("test.conf:5: "));
+        assert_eq!(config.server_tcp_port, 5006);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_not_fatal() {
+        let mut config = FileConfig::default();
+        let errors = apply_config_content(&mut config, "encryption_key = x\nuser = \"\"\n", "test.conf");
+
+        assert!(errors.is_empty());
+        assert_eq!(config.user, None);
+        assert_eq!(config.warnings, vec!["test.conf:1: unknown config key 'encryption_key', ignored"]);
+    }
+
+    #[test]
+    fn test_signing_key() {
+        let mut config = FileConfig::default();
+        let errors = apply_config_content(
+            &mut config,
+            "signing_key = \"c2lnbmluZyBrZXk=\"\nmax_transfer_secs = 600\n",
+            "test.conf",
+        );
+        assert!(errors.is_empty());
+        assert_eq!(config.signing_key.as_deref(), Some("c2lnbmluZyBrZXk="));
+        assert_eq!(config.max_transfer_secs, 600);
+
+        let errors = apply_config_content(&mut config, "signing_key = \"\"\n", "test.conf");
+        assert!(errors.is_empty());
+        assert_eq!(config.signing_key, None);
+
+        let errors = apply_config_content(&mut config, "signing_key = not-base64\n", "test.conf");
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_environment_overrides_file() {
+        let mut config = FileConfig::default();
+        apply_config_content(&mut config, "server_tcp_port = 5006\nlogger = info\n", "test.conf");
+        let errors = apply_env_overrides(
+            &mut config,
+            env(&[
+                ("NETTEST_SERVER_TCP_PORT", "5007"),
+                ("NETTEST_CONFIG", "/ignored"),
+                ("HOME", "/root"),
+            ]),
+        );
+
+        assert!(errors.is_empty());
+        assert_eq!(config.server_tcp_port, 5007);
+        assert_eq!(config.logger, LevelFilter::Info);
+    }
+
+    #[test]
+    fn test_invalid_environment_override() {
+        let mut config = FileConfig::default();
+        let errors = apply_env_overrides(&mut config, env(&[("NETTEST_SERVER_TLS_PORT", "x")]));
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].starts_with("NETTEST_SERVER_TLS_PORT: "));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_environment_skips_non_utf8_variables() {
+        use std::os::unix::ffi::OsStringExt;
+
+        let mut config = FileConfig::default();
+        let invalid = || OsString::from_vec(vec![0xff, 0xfe]);
+        let mut vars = env(&[("NETTEST_SERVER_TCP_PORT", "5007")]);
+        vars.push((invalid(), OsString::from("x")));
+        vars.push((OsString::from("NETTEST_LOGGER"), invalid()));
+        vars.push((OsString::from("LS_COLORS"), invalid()));
+
+        assert!(apply_env_overrides(&mut config, vars).is_empty());
+        assert_eq!(config.server_tcp_port, 5007);
+        assert_eq!(config.warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_config_flag_is_removed_from_args() {
+        let mut args: Vec<String> = ["nettest", "-s", "--config", "x.conf", "-l"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(take_config_flag(&mut args).unwrap(), Some(PathBuf::from("x.conf")));
+        assert_eq!(args, vec!["nettest", "-s", "-l"]);
+    }
+
+    #[test]
+    fn test_config_flag_with_equals_sign() {
+        let mut args = vec!["nettest".to_string(), "--config=/a.conf".to_string()];
+        assert_eq!(take_config_flag(&mut args).unwrap(), Some(PathBuf::from("/a.conf")));
+        assert_eq!(args, vec!["nettest"]);
+    }
+
+    #[test]
+    fn test_config_flag_without_path() {
+        let mut args = vec!["nettest".to_string(), "--config".to_string()];
+        assert!(take_config_flag(&mut args).is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use std::env;
+use std::path::PathBuf;
+
+/// System wide configuration file
+pub fn system_config_file() -> PathBuf {
+    if cfg!(target_os = "macos") {
+        // On macOS ~/.config/ has always been the main directory
+        home_dir()
+            .unwrap_or_else(|| PathBuf::from("/tmp"))
+            .join(".config/nettest.conf")
+    } else if cfg!(target_os = "windows") {
+        env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("C:\\ProgramData"))
+            .join("nettest\\nettest.conf")
+    } else {
+        PathBuf::from("/etc/nettest.conf")
+    }
+}
+
+/// Per-user configuration file, `$XDG_CONFIG_HOME/nettest/nettest.conf`
+pub fn user_config_file() -> Option<PathBuf> {
+    user_dir("XDG_CONFIG_HOME", ".config", "APPDATA").map(|dir| dir.join("nettest.conf"))
+}
+
+/// Directory for state the client keeps between runs (client UUID),
+/// `$XDG_STATE_HOME/nettest`
+pub fn user_state_dir() -> Option<PathBuf> {
+    user_dir("XDG_STATE_HOME", ".local/state", "LOCALAPPDATA")
+}
+
+/// Directory for data the client accumulates (measurement history),
+/// `$XDG_DATA_HOME/nettest`
+pub fn user_data_dir() -> Option<PathBuf> {
+    user_dir("XDG_DATA_HOME", ".local/share", "LOCALAPPDATA")
+}
+
+fn user_dir(xdg_var: &str, home_fallback: &str, windows_var: &str) -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        return env::var_os(windows_var).map(|dir| PathBuf::from(dir).join("nettest"));
+    }
+    // The XDG spec requires absolute paths, relative ones are ignored
+    if let Some(dir) = env::var_os(xdg_var).map(PathBuf::from) {
+        if dir.is_absolute() {
+            return Some(dir.join("nettest"));
+        }
+    }
+    home_dir().map(|home| home.join(home_fallback).join("nettest"))
+}
+
+fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .filter(|home| !home.is_empty())
+        .map(PathBuf::from)
+}
@@ -0,0 +1,19 @@
+//! RMBT measurement server and client.
+//!
+//! The server is embedded with [`mioserver::MioServer::builder`], a measurement
+//! is run from code with [`client::measurement::Measurement`]. Neither prints
+//! to stdout nor exits the process, and sockets passed by systemd are only
+//! taken over with [`mioserver::ServerBuilder::socket_activation`]. Help
+//! output, config file warnings and the logger set-up are left to the
+//! `nettest` binary.
+
+pub mod client;
+pub mod config;
+pub mod logger;
+pub mod mioserver;
+pub mod stream;
+pub mod tokio_server;
+
+pub use client::client::ClientConfig;
+pub use client::measurement::{Measurement, MeasurementResult};
+pub use mioserver::{MioServer, ServerBuilder, ServerConfig, ShutdownHandle};
@@ -0,0 +1,71 @@
+//! Writes log records to the systemd journal using its native datagram
+//! protocol, so connection fields end up as structured journal fields.
+
+use log::Level;
+use std::io;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+#[cfg(unix)]
+pub struct Journal {
+    socket: std::os::unix::net::UnixDatagram,
+}
+
+#[cfg(unix)]
+impl Journal {
+    pub fn connect() -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(JOURNAL_SOCKET)?;
+        Ok(Self { socket })
+    }
+
+    pub fn send(&self, level: Level, message: &str, fields: &[(&str, String)]) {
+        let mut datagram = Vec::with_capacity(message.len() + 128);
+        append_field(&mut datagram, "PRIORITY", &priority(level).to_string());
+        append_field(&mut datagram, "SYSLOG_IDENTIFIER", "nettest");
+        append_field(&mut datagram, "MESSAGE", message);
+        for (name, value) in fields {
+            append_field(&mut datagram, name, value);
+        }
+        // Records that don't fit into a datagram are dropped
+        let _ = self.socket.send(&datagram);
+    }
+}
+
+#[cfg(not(unix))]
+pub struct Journal;
+
+#[cfg(not(unix))]
+impl Journal {
+    pub fn connect() -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "journald is only available on Linux",
+        ))
+    }
+
+    pub fn send(&self, _level: Level, _message: &str, _fields: &[(&str, String)]) {}
+}
+
+/// syslog priorities used by the journal
+fn priority(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// `NAME=value\n`, or the length-prefixed form for values containing newlines
+fn append_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+    datagram.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        datagram.push(b'\n');
+        datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        datagram.push(b'=');
+    }
+    datagram.extend_from_slice(value.as_bytes());
+    datagram.push(b'\n');
+}
@@ -1,107 +1,311 @@
 use chrono::Local;
-#[cfg(unix)]
-use libc::{c_int, signal, SIGHUP};
 use log::{LevelFilter, Log, Metadata, Record};
-use std::fs::{self, File, OpenOptions};
+use std::cell::Cell;
 use std::io::{self, Write};
-use std::path::Path;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use lazy_static::lazy_static;
 
-pub struct FileLogger {
-    level: LevelFilter,
-    log_file: Arc<Mutex<File>>,
+use crate::mioserver::ServerTestPhase;
+
+pub mod journald;
+pub mod rotation;
+
+use journald::Journal;
+pub use rotation::LogRotation;
+use rotation::RotatingFile;
+
+/// Where log records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTarget {
+    /// Log file, echoed to stdout
+    File,
+    Stderr,
+    Journald,
 }
 
-impl FileLogger {
-    pub fn new(level: LevelFilter, log_path: &Path) -> Result<Self, std::io::Error> {
-        // Create directory if it doesn't exist
-        if let Some(parent) = log_path.parent() {
-            fs::create_dir_all(parent)?;
+impl std::str::FromStr for LogTarget {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "file" => Ok(LogTarget::File),
+            "stderr" => Ok(LogTarget::Stderr),
+            "journald" | "journal" => Ok(LogTarget::Journald),
+            _ => Err(format!(
+                "invalid log target '{}', expected file, stderr or journald",
+                value
+            )),
         }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("invalid log format '{}', expected text or json", value)),
+        }
+    }
+}
+
+/// Log destination settings from the config file (`log_*` keys) and CLI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogOptions {
+    pub target: LogTarget,
+    /// Log file, the platform default when unset
+    pub path: Option<PathBuf>,
+    pub format: LogFormat,
+    pub rotation: LogRotation,
+    /// Number of rotated files kept next to the current one
+    pub keep: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            target: LogTarget::File,
+            path: None,
+            format: LogFormat::Text,
+            rotation: LogRotation::None,
+            keep: 7,
+        }
+    }
+}
+
+/// Connection a worker thread is currently handling, added to every log record
+/// written from that thread
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionContext {
+    pub id: u64,
+    pub client_ip: Option<IpAddr>,
+    pub phase: ServerTestPhase,
+}
 
-        let log_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+thread_local! {
+    static CONNECTION: Cell<Option<ConnectionContext>> = const { Cell::new(None) };
+}
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Process-wide unique ID for a new connection
+pub fn next_connection_id() -> u64 {
+    NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Sets (or clears) the connection log records of the current thread belong to
+pub fn set_connection_context(context: Option<ConnectionContext>) {
+    CONNECTION.with(|connection| connection.set(context));
+}
+
+fn connection_context() -> Option<ConnectionContext> {
+    CONNECTION.with(|connection| connection.get())
+}
+
+enum Output {
+    File(Mutex<RotatingFile>),
+    Stderr,
+    Journald(Journal),
+}
+
+pub struct FileLogger {
+    // LevelFilter as usize, so the level can change while logging
+    level: AtomicUsize,
+    format: LogFormat,
+    output: Output,
+}
+
+impl FileLogger {
+    pub fn new(level: LevelFilter, options: &LogOptions) -> Result<Self, std::io::Error> {
+        let output = match options.target {
+            LogTarget::File => {
+                let path = log_file_path(options).unwrap();
+                let file = RotatingFile::open(&path, options.rotation, options.keep)?;
+                Output::File(Mutex::new(file))
+            }
+            LogTarget::Stderr => Output::Stderr,
+            LogTarget::Journald => Output::Journald(Journal::connect()?),
+        };
 
         Ok(Self {
-            level,
-            log_file: Arc::new(Mutex::new(log_file)),
+            level: AtomicUsize::new(level as usize),
+            format: options.format,
+            output,
         })
     }
 
-    pub fn reopen_log_file(&self, log_path: &Path) -> Result<(), std::io::Error> {
-        let mut log_file = self.log_file.lock().unwrap();
-        *log_file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(log_path)?;
+    pub fn level(&self) -> LevelFilter {
+        let level = self.level.load(Ordering::Relaxed);
+        LevelFilter::iter()
+            .find(|filter| *filter as usize == level)
+            .unwrap_or(LevelFilter::Off)
+    }
+
+    pub fn set_level(&self, level: LevelFilter) {
+        self.level.store(level as usize, Ordering::Relaxed);
+    }
+
+    pub fn reopen_log_file(&self) -> Result<(), std::io::Error> {
+        if let Output::File(file) = &self.output {
+            file.lock().unwrap().reopen()?;
+        }
         Ok(())
     }
 
-    fn format_log(&self, record: &Record) -> String {
-        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S");
-        format!("{} [{}] - {}\n", timestamp, record.level(), record.args())
+    fn format_log(&self, record: &Record, context: Option<ConnectionContext>) -> String {
+        let timestamp = Local::now();
+        match self.format {
+            LogFormat::Text => {
+                let timestamp = timestamp.format("%Y-%m-%d %H:%M:%S");
+                match context {
+                    Some(context) => format!(
+                        "{} [{}] [conn {} {} {:?}] - {}\n",
+                        timestamp,
+                        record.level(),
+                        context.id,
+                        context
+                            .client_ip
+                            .map(|ip| ip.to_string())
+                            .unwrap_or_else(|| "-".to_string()),
+                        context.phase,
+                        record.args()
+                    ),
+                    None => format!("{} [{}] - {}\n", timestamp, record.level(), record.args()),
+                }
+            }
+            LogFormat::Json => {
+                let mut line = serde_json::json!({
+                    "timestamp": timestamp.to_rfc3339_opts(chrono::SecondsFormat::Millis, false),
+                    "level": record.level().to_string(),
+                    "target": record.target(),
+                    "message": record.args().to_string(),
+                });
+                if let Some(context) = context {
+                    line["connection_id"] = context.id.into();
+                    line["client_ip"] = context.client_ip.map(|ip| ip.to_string()).into();
+                    line["phase"] = format!("{:?}", context.phase).into();
+                }
+                format!("{}\n", line)
+            }
+        }
     }
 }
 
 impl Log for FileLogger {
     fn enabled(&self, metadata: &Metadata) -> bool {
-        metadata.level() <= self.level
+        metadata.level() <= self.level()
     }
 
     fn log(&self, record: &Record) {
         if !self.enabled(record.metadata()) {
             return;
         }
+        let context = connection_context();
 
-        let message = self.format_log(record);
-
-        // Write to file
-        if let Ok(mut file) = self.log_file.lock() {
-            let _ = file.write_all(message.as_bytes());
-            let _ = file.flush();
+        match &self.output {
+            Output::File(file) => {
+                let message = self.format_log(record, context);
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.write_line(message.as_bytes());
+                    let _ = file.flush();
+                }
+                let _ = io::stdout().write_all(message.as_bytes());
+                let _ = io::stdout().flush();
+            }
+            Output::Stderr => {
+                let message = self.format_log(record, context);
+                let _ = io::stderr().write_all(message.as_bytes());
+            }
+            Output::Journald(journal) => {
+                // The journal keeps its own timestamps, connection data goes into fields
+                let message = match self.format {
+                    LogFormat::Text => record.args().to_string(),
+                    LogFormat::Json => self.format_log(record, context).trim_end().to_string(),
+                };
+                let mut fields = vec![("TARGET", record.target().to_string())];
+                if let Some(context) = context {
+                    fields.push(("CONNECTION_ID", context.id.to_string()));
+                    if let Some(ip) = context.client_ip {
+                        fields.push(("CLIENT_IP", ip.to_string()));
+                    }
+                    fields.push(("TEST_PHASE", format!("{:?}", context.phase)));
+                }
+                journal.send(record.level(), &message, &fields);
+            }
         }
-
-        // Write to stdout
-        let _ = io::stdout().write_all(message.as_bytes());
-        let _ = io::stdout().flush();
     }
 
     fn flush(&self) {
-        if let Ok(mut file) = self.log_file.lock() {
-            let _ = file.flush();
+        match &self.output {
+            Output::File(file) => {
+                if let Ok(mut file) = file.lock() {
+                    let _ = file.flush();
+                }
+                let _ = io::stdout().flush();
+            }
+            Output::Stderr => {
+                let _ = io::stderr().flush();
+            }
+            Output::Journald(_) => {}
         }
-        let _ = io::stdout().flush();
     }
 }
 
-#[cfg(unix)]
-extern "C" fn handle_sighup(_: c_int) {
-    // This will be called when logrotate sends SIGHUP
-    if let Some(logger) = LOGGER.lock().ok().and_then(|l| l.as_ref().cloned()) {
-        let log_path = if cfg!(target_os = "macos") {
-            let home = std::env::var("HOME").unwrap_or_else(|_| "/Users/root".to_string());
-            Path::new(&home).join("Library/Logs/rmbt/nettest.log")
-        } else {
-            Path::new("/var/log/rmbt/nettest.log").to_path_buf()
-        };
-        let _ = logger.reopen_log_file(&log_path);
+lazy_static! {
+    static ref LOGGER: Mutex<Option<Arc<FileLogger>>> = Mutex::new(None);
+    static ref LOG_OPTIONS: Mutex<LogOptions> = Mutex::new(LogOptions::default());
+}
+
+/// Sets the destination used by the next `init_logger` call
+pub fn set_log_options(options: LogOptions) {
+    *LOG_OPTIONS.lock().unwrap() = options;
+}
+
+/// Changes the level of the running logger, or starts logging if the
+/// logger was not initialized yet
+pub fn set_log_level(level: LevelFilter) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let logger = LOGGER.lock().unwrap().clone();
+    match logger {
+        Some(logger) => {
+            logger.set_level(level);
+            log::set_max_level(level);
+            Ok(())
+        }
+        None if level == LevelFilter::Off => Ok(()),
+        None => init_logger(level),
     }
 }
 
-#[cfg(windows)]
-extern "C" fn handle_sighup(_: i32) {
-    // Windows doesn't support SIGHUP, this is a no-op
+/// Reopens the log file after it was moved away by logrotate
+pub fn reopen_log_file() -> Result<(), std::io::Error> {
+    let logger = LOGGER.lock().unwrap().clone();
+    match logger {
+        Some(logger) => logger.reopen_log_file(),
+        None => Ok(()),
+    }
 }
 
-lazy_static! {
-    static ref LOGGER: Mutex<Option<Arc<FileLogger>>> = Mutex::new(None);
+/// Log file written with these options, `None` for stderr and journald
+pub fn log_file_path(options: &LogOptions) -> Option<PathBuf> {
+    match options.target {
+        LogTarget::File => Some(options.path.clone().unwrap_or_else(default_log_path)),
+        LogTarget::Stderr | LogTarget::Journald => None,
+    }
 }
 
-pub fn init_logger(level: LevelFilter) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Create log directory if it doesn't exist
+fn default_log_path() -> PathBuf {
     let log_dir = if cfg!(target_os = "macos") {
         // On macOS, use ~/Library/Logs/rmbt
         let home = std::env::var("HOME").unwrap_or_else(|_| "/Users/root".to_string());
@@ -111,54 +315,82 @@ pub fn init_logger(level: LevelFilter) -> Result<(), Box<dyn std::error::Error +
         let appdata = std::env::var("APPDATA").unwrap_or_else(|_| "C:\\Users\\Public\\AppData\\Roaming".to_string());
         Path::new(&appdata).join("nettest\\logs")
     } else {
-        // On Linux, create PID file and use /var/log
-        #[cfg(unix)]
-        {
-            let pid = std::process::id();
-            let pid_dir = Path::new("/run");
-            if !pid_dir.exists() {
-                std::fs::create_dir_all(pid_dir)?;
-            }
-            let pid_path = pid_dir.join("nettest.pid");
-            std::fs::write(&pid_path, pid.to_string())?;
-            log::info!("PID file location: {}", pid_path.display());
-        }
+        // On Linux use /var/log, the PID file is written by the server after daemonizing
         Path::new("/var/log/nettest").to_path_buf()
     };
+    log_dir.join("nettest.log")
+}
 
-    // Create directory and all parent directories if they don't exist
-    if !log_dir.exists() {
-        fs::create_dir_all(&log_dir)?;
+pub fn init_logger(level: LevelFilter) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    if LOGGER.lock().unwrap().is_some() {
+        return set_log_level(level);
     }
 
-    // Ensure we have write permissions
-    let log_path = log_dir.join("nettest.log");
-
-    // Try to create the log file with proper permissions
-    let log_file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&log_path)?;
-
-    // Create logger
-    let logger = Arc::new(FileLogger {
-        level,
-        log_file: Arc::new(Mutex::new(log_file)),
-    });
+    let options = LOG_OPTIONS.lock().unwrap().clone();
+    let logger = Arc::new(FileLogger::new(level, &options)?);
 
     // Store logger in global state
     *LOGGER.lock().unwrap() = Some(logger.clone());
 
-    // Set up SIGHUP handler for log rotation
-    unsafe {
-        #[cfg(unix)]
-        signal(SIGHUP, handle_sighup as usize);
-        #[cfg(windows)]
-        handle_sighup(0); // No-op for Windows
-    }
-
+    // The server reopens the log file on SIGHUP (logrotate), see reopen_log_file
     log::set_boxed_logger(Box::new(logger))?;
     log::set_max_level(level);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Level;
+
+    fn logger(format: LogFormat) -> FileLogger {
+        let options = LogOptions {
+            target: LogTarget::Stderr,
+            format,
+            ..LogOptions::default()
+        };
+        FileLogger::new(LevelFilter::Debug, &options).unwrap()
+    }
+
+    fn context() -> ConnectionContext {
+        ConnectionContext {
+            id: 42,
+            client_ip: Some("192.0.2.7".parse().unwrap()),
+            phase: ServerTestPhase::GreetingReceiveConnectionType,
+        }
+    }
+
+    #[test]
+    fn test_json_line() {
+        let logger = logger(LogFormat::Json);
+        let record = Record::builder().args(format_args!("listening")).level(Level::Info).target("nettest").build();
+        let line = logger.format_log(&record, None);
+        assert!(line.ends_with('\n') && !line.trim_end().contains('\n'));
+
+        let json: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(json["level"], "INFO");
+        assert_eq!(json["target"], "nettest");
+        assert_eq!(json["message"], "listening");
+        assert!(chrono::DateTime::parse_from_rfc3339(json["timestamp"].as_str().unwrap()).is_ok());
+        assert!(json.get("connection_id").is_none());
+    }
+
+    #[test]
+    fn test_json_line_with_connection_context() {
+        let logger = logger(LogFormat::Json);
+        let record = Record::builder().args(format_args!("handshake done")).level(Level::Debug).build();
+        let json: serde_json::Value = serde_json::from_str(&logger.format_log(&record, Some(context()))).unwrap();
+        assert_eq!(json["connection_id"], 42);
+        assert_eq!(json["client_ip"], "192.0.2.7");
+        assert_eq!(json["phase"], "GreetingReceiveConnectionType");
+    }
+
+    #[test]
+    fn test_text_line_with_connection_context() {
+        let logger = logger(LogFormat::Text);
+        let record = Record::builder().args(format_args!("handshake done")).level(Level::Debug).build();
+        let line = logger.format_log(&record, Some(context()));
+        assert!(line.ends_with(" [DEBUG] [conn 42 192.0.2.7 GreetingReceiveConnectionType] - handshake done\n"));
+    }
+}
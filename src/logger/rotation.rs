@@ -0,0 +1,210 @@
+use chrono::{Local, NaiveDate};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Wait before trying again after a rotation failed, e.g. because the
+/// directory is not writable after dropping privileges
+const ROTATION_RETRY: Duration = Duration::from_secs(60);
+
+/// When the log file is rotated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogRotation {
+    /// Never, left to an external tool like logrotate
+    None,
+    /// At the first write after midnight (local time)
+    Daily,
+    /// Before the file would grow beyond this many bytes
+    Size(u64),
+}
+
+impl std::str::FromStr for LogRotation {
+    type Err = String;
+
+    /// `none`, `daily` or a size such as `10M`, `500K`, `1G` or plain bytes
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_ascii_lowercase().as_str() {
+            "none" | "off" | "" => Ok(LogRotation::None),
+            "daily" => Ok(LogRotation::Daily),
+            size => {
+                let (number, unit) = match size.find(|c: char| !c.is_ascii_digit()) {
+                    Some(index) => size.split_at(index),
+                    None => (size, ""),
+                };
+                let multiplier = match unit {
+                    "" | "b" => 1,
+                    "k" | "kb" => 1024,
+                    "m" | "mb" => 1024 * 1024,
+                    "g" | "gb" => 1024 * 1024 * 1024,
+                    _ => return Err(format!("invalid log rotation '{}'", value)),
+                };
+                match number.parse::<u64>().ok().and_then(|number| number.checked_mul(multiplier)) {
+                    Some(size) if size > 0 => Ok(LogRotation::Size(size)),
+                    _ => Err(format!("invalid log rotation '{}'", value)),
+                }
+            }
+        }
+    }
+}
+
+/// Log file that rotates itself: `nettest.log` is moved to `nettest.log.1`,
+/// older files shift up and everything beyond `keep` files is deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    day: NaiveDate,
+    rotation: LogRotation,
+    keep: usize,
+    /// Set while rotating fails, no new attempt is made before then
+    retry_at: Option<Instant>,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: LogRotation, keep: usize) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        let file = Self::open_append(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            size,
+            day: Local::now().date_naive(),
+            rotation,
+            keep: keep.max(1),
+            retry_at: None,
+        })
+    }
+
+    fn open_append(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Reopens the file by path, e.g. after an external tool moved it away
+    pub fn reopen(&mut self) -> io::Result<()> {
+        self.file = Self::open_append(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let due = match self.rotation {
+            LogRotation::None => false,
+            LogRotation::Daily => Local::now().date_naive() != self.day,
+            LogRotation::Size(max) => self.size > 0 && self.size + line.len() as u64 > max,
+        };
+        if due && self.retry_at.is_none_or(|retry_at| Instant::now() >= retry_at) {
+            match self.rotate() {
+                Ok(()) => self.retry_at = None,
+                Err(e) => {
+                    // The logger can't log its own failures, reported once until rotating works again
+                    if self.retry_at.is_none() {
+                        eprintln!(
+                            "Failed to rotate log file {}: {}, retrying every {}s",
+                            self.path.display(),
+                            e,
+                            ROTATION_RETRY.as_secs()
+                        );
+                    }
+                    self.retry_at = Some(Instant::now() + ROTATION_RETRY);
+                }
+            }
+        }
+        // The line is written in any case, to the unrotated file if rotating failed
+        self.file.write_all(line)?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        let _ = fs::remove_file(self.rotated_path(self.keep));
+        for index in (1..self.keep).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        self.file = Self::open_append(&self.path)?;
+        self.size = 0;
+        self.day = Local::now().date_naive();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rotation() {
+        assert_eq!("daily".parse(), Ok(LogRotation::Daily));
+        assert_eq!("none".parse(), Ok(LogRotation::None));
+        assert_eq!("10M".parse(), Ok(LogRotation::Size(10 * 1024 * 1024)));
+        assert_eq!("4096".parse(), Ok(LogRotation::Size(4096)));
+        assert!("10X".parse::<LogRotation>().is_err());
+        assert!("0".parse::<LogRotation>().is_err());
+        assert!("99999999999999G".parse::<LogRotation>().is_err());
+    }
+
+    #[test]
+    fn test_rotates_by_size_and_keeps_limited_files() {
+        let dir = std::env::temp_dir().join(format!("nettest-rotation-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("test.log");
+
+        let mut file = RotatingFile::open(&path, LogRotation::Size(10), 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_line(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(dir.join("test.log.1")).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(dir.join("test.log.2")).unwrap(), "bbbbbbbb\n");
+        assert!(!dir.join("test.log.3").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_failed_rotation_keeps_writing_and_backs_off() {
+        let dir = std::env::temp_dir().join(format!("nettest-rotation-failed-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let path = dir.join("test.log");
+        // A non-empty directory in the way of test.log.1 makes the rename fail
+        fs::create_dir_all(dir.join("test.log.1").join("blocker")).unwrap();
+
+        let mut file = RotatingFile::open(&path, LogRotation::Size(10), 1).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n"] {
+            file.write_line(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "aaaaaaaa\nbbbbbbbb\ncccccccc\n");
+        assert!(file.retry_at.is_some());
+
+        // Not retried before the back-off ends, then rotated
+        fs::remove_dir_all(dir.join("test.log.1")).unwrap();
+        file.write_line(b"dddddddd\n").unwrap();
+        assert!(!dir.join("test.log.1").exists());
+        file.retry_at = Some(Instant::now());
+        file.write_line(b"eeeeeeee\n").unwrap();
+        file.flush().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "eeeeeeee\n");
+        assert!(file.retry_at.is_none());
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
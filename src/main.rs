@@ -1,49 +1,97 @@
 use log::{debug, info};
 use tokio::signal;
 
-use crate::config::parser::read_config_file;
-use crate::mioserver::MioServer;
+use nettest::config::parser::{read_config_file, take_config_flag};
+use nettest::mioserver::{self, MioServer};
+use nettest::client;
 use std::error::Error as StdError;
 
-pub mod config;
-pub mod logger;
-pub mod mioserver;
-pub mod stream;
-pub mod tokio_server;
-
-pub mod client;
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn StdError + Send + Sync>> {
+// The runtime is built by hand instead of using #[tokio::main]: the server may
+// fork into the background first, and only the forking thread survives fork().
+fn main() -> Result<(), Box<dyn StdError + Send + Sync>> {
     let mut args: Vec<String> = std::env::args().collect();
 
-    let config_result = read_config_file();
+    let config_path = take_config_flag(&mut args)?;
+    let config_result = read_config_file(config_path.as_deref());
+    if args.len() > 1 && args[1] == "-s" && args.iter().any(|arg| arg == "--check-config") {
+        // Configuration errors are part of the report, not a start-up failure
+        let args = args.into_iter().skip(1).collect();
+        let valid = mioserver::check_config::run(args, config_result);
+        std::process::exit(if valid { 0 } else { 1 });
+    }
     if config_result.is_err() {
         return Err(config_result.err().unwrap().into());
     }
     let config = config_result.unwrap();
+    if let Some(path) = &config.source {
+        println!("Reading config from: {:?}", path);
+    }
+    for warning in &config.warnings {
+        println!("Warning: {}", warning);
+    }
     if args.len() == 1 || args[1] == "-c" {
         args = args.iter().skip(1).map(|s| s.clone()).collect();
-        client::client::client_run(args, config).await?;
+        let code = tokio::runtime::Runtime::new()?.block_on(client::client::client_run(args, config))?;
+        if code != 0 {
+            std::process::exit(code);
+        }
         return Ok(());
     } else if args[1] == "-s" {
         debug!("args: {:?}", args);
         args = args.iter().skip(1).map(|s| s.clone()).collect();
 
-        let mut mio_server = MioServer::new(args, config)?;
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            mioserver::parser::print_help();
+            return Ok(());
+        }
+        if args.iter().any(|arg| arg == "-v" || arg == "--version") {
+            println!("nettest {}", env!("CARGO_PKG_VERSION"));
+            return Ok(());
+        }
+        let mut mio_server = match MioServer::new(args, config) {
+            Ok(mio_server) => mio_server,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        };
+        // Listeners and certificates are in place, now detach and give up root
+        mio_server.daemonize_and_drop_privileges()?;
 
-        // Create separate thread for signal handling
-        let shutdown_signal = mio_server.get_shutdown_signal();
-        tokio::spawn(async move {
-            signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
-            info!("Ctrl+C received, shutting down server...");
-            shutdown_signal.store(true, std::sync::atomic::Ordering::Relaxed);
-        });
+        let runtime = tokio::runtime::Runtime::new()?;
+        runtime.block_on(async {
+            // Create separate thread for signal handling
+            let shutdown = mio_server.shutdown_handle();
+            tokio::spawn(async move {
+                signal::ctrl_c().await.expect("Failed to listen for Ctrl+C");
+                info!("Ctrl+C received, shutting down server...");
+                shutdown.shutdown();
+            });
+            #[cfg(unix)]
+            {
+                let reload_signal = mio_server.get_reload_signal();
+                tokio::spawn(async move {
+                    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+                        .expect("Failed to listen for SIGHUP");
+                    while hangup.recv().await.is_some() {
+                        info!("SIGHUP received, reloading configuration...");
+                        reload_signal.store(true, std::sync::atomic::Ordering::Relaxed);
+                    }
+                });
+            }
 
-        mio_server.run()?;
-        info!("Server stopping...");
-        mio_server.shutdown().await?;
-        info!("Server stopped");
+            mio_server.run()?;
+            info!("Server stopping...");
+            mio_server.shutdown().await?;
+            info!("Server stopped");
+            Ok::<(), std::io::Error>(())
+        })?;
+    } else if args[1] == "discover" {
+        let args = args.into_iter().skip(2).collect();
+        client::mdns::discover_run(args)?;
+    } else if args[1] == "history" {
+        let args = args.into_iter().skip(2).collect();
+        client::history::history_run(args, config)?;
     } else if args[1] == "-v" || args[1] == "--version" {
         println!("nettest {}", env!("CARGO_PKG_VERSION"));
         return Ok(());
@@ -56,11 +104,17 @@ async fn main() -> Result<(), Box<dyn StdError + Send + Sync>> {
         println!("USAGE:");
         println!("    nettest              Run client with auto-discovered server");
         println!("    nettest -c [OPTIONS] Run as client");
-        println!("    nettest -s [OPTIONS] Run as server\n");
+        println!("    nettest -s [OPTIONS] Run as server");
+        println!("    nettest history      List and summarise past client runs");
+        println!("    nettest discover     List servers on the local network\n");
         println!("For detailed help:");
         println!("    nettest -c -h        Show client options");
         println!("    nettest -s -h        Show server options");
-        println!("    nettest -v           Print version and exit");
+        println!("    nettest history -h   Show history options");
+        println!("    nettest discover -h  Show discover options");
+        println!("    nettest -v           Print version and exit\n");
+        println!("GLOBAL OPTIONS:");
+        println!("    --config PATH        Read settings from PATH (also NETTEST_CONFIG)");
         if !is_help {
             std::process::exit(1);
         }
@@ -0,0 +1,143 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::mioserver::proxy_protocol::Cidr;
+use crate::mioserver::server::{MioServer, ServerConfig};
+
+/// Builds a [`MioServer`] from settings given in code instead of command line
+/// arguments and a config file.
+///
+/// ```no_run
+/// use nettest::mioserver::MioServer;
+///
+/// let mut server = MioServer::builder()
+///     .tcp_addresses(vec!["127.0.0.1:5005".parse().unwrap()])
+///     .workers(4)
+///     .build()?;
+/// let shutdown = server.shutdown_handle();
+/// std::thread::spawn(move || server.run());
+/// // ...
+/// shutdown.shutdown();
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub struct ServerBuilder {
+    config: ServerConfig,
+}
+
+impl Default for ServerBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ServerBuilder {
+    pub fn new() -> Self {
+        Self::from_config(ServerConfig::default())
+    }
+
+    /// Starts from a complete configuration, e.g. one adjusted from `ServerConfig::default()`
+    pub fn from_config(config: ServerConfig) -> Self {
+        Self { config }
+    }
+
+    /// Plain TCP listen addresses, replacing the default port 5005
+    pub fn tcp_addresses(mut self, addresses: Vec<SocketAddr>) -> Self {
+        self.config.tcp_addresses = addresses;
+        self
+    }
+
+    /// TLS listen addresses, only bound when a certificate is configured
+    pub fn tls_addresses(mut self, addresses: Vec<SocketAddr>) -> Self {
+        self.config.tls_addresses = addresses;
+        self
+    }
+
+    /// Certificate chain and private key in PEM format
+    pub fn certificate(mut self, cert_path: &str, key_path: &str) -> Self {
+        self.config.cert_path = Some(cert_path.to_string());
+        self.config.key_path = Some(key_path.to_string());
+        self
+    }
+
+    /// Prepared rustls configuration, takes precedence over certificate files
+    pub fn tls_config(mut self, tls_config: Arc<rustls::ServerConfig>) -> Self {
+        self.config.tls_config = Some(tls_config);
+        self
+    }
+
+    /// Generates a certificate, stored in the `certificate` paths if given
+    pub fn self_signed(mut self, self_signed: bool) -> Self {
+        self.config.self_signed = self_signed;
+        self
+    }
+
+    pub fn workers(mut self, workers: usize) -> Self {
+        self.config.num_workers = Some(workers);
+        self
+    }
+
+    pub fn proxy_protocol(mut self, proxy_protocol: bool) -> Self {
+        self.config.proxy_protocol = proxy_protocol;
+        self
+    }
+
+    pub fn trusted_proxies(mut self, trusted_proxies: Vec<Cidr>) -> Self {
+        self.config.trusted_proxies = trusted_proxies;
+        self
+    }
+
+    pub fn max_chunk_size(mut self, max_chunk_size: u32) -> Self {
+        self.config.max_chunk_size = Some(max_chunk_size);
+        self
+    }
+
+    /// Takes over the sockets systemd passes in `LISTEN_FDS` instead of
+    /// binding the listen addresses. Off by default, the variables belong to
+    /// the process that was started by systemd.
+    pub fn socket_activation(mut self, socket_activation: bool) -> Self {
+        self.config.socket_activation = socket_activation;
+        self
+    }
+
+    /// Binds the listeners. The server starts accepting connections in `run()`.
+    pub fn build(self) -> io::Result<MioServer> {
+        MioServer::from_config(self.config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::time::Duration;
+
+    #[test]
+    fn test_embedded_server_accepts_connections_and_shuts_down() {
+        let mut server = ServerBuilder::new()
+            .tcp_addresses(vec!["127.0.0.1:0".parse().unwrap()])
+            .workers(2)
+            .build()
+            .unwrap();
+        let addr = server.tcp_local_addrs()[0];
+        let shutdown = server.shutdown_handle();
+        let running = std::thread::spawn(move || server.run());
+
+        let mut client = std::net::TcpStream::connect(addr).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client
+            .write_all(b"GET /rmbt HTTP/1.1\r\nConnection: Upgrade\r\nUpgrade: RMBT\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let mut buffer = [0u8; 256];
+        while !String::from_utf8_lossy(&response).contains("RMBTv") {
+            let read = client.read(&mut buffer).unwrap();
+            assert!(read > 0, "connection closed before the greeting");
+            response.extend_from_slice(&buffer[..read]);
+        }
+
+        shutdown.shutdown();
+        running.join().unwrap().unwrap();
+        assert!(shutdown.is_shutdown());
+    }
+}
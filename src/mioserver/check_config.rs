@@ -0,0 +1,548 @@
+//! `nettest -s --check-config`: validates the configuration the server would
+//! start with and prints a report instead of starting it.
+
+use crate::config::FileConfig;
+use crate::mioserver::server::{MioServer, ServerConfig, DEFAULT_PID_FILE};
+use crate::mioserver::parser::parse_args;
+use crate::stream::rustls_server::load_server_config;
+use crate::tokio_server::utils::user::UserPrivileges;
+use mio::net::TcpListener;
+use openssl::asn1::Asn1Time;
+use openssl::pkey::PKey;
+use openssl::x509::X509;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Certificates expiring sooner than this are reported as a warning
+const CERT_EXPIRY_WARNING_DAYS: i32 = 30;
+
+#[derive(Default)]
+struct Report {
+    warnings: usize,
+    failures: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: impl AsRef<str>) {
+        println!("OK    {}", message.as_ref());
+    }
+
+    fn warn(&mut self, message: impl AsRef<str>) {
+        self.warnings += 1;
+        println!("WARN  {}", message.as_ref());
+    }
+
+    fn fail(&mut self, message: impl AsRef<str>) {
+        self.failures += 1;
+        println!("FAIL  {}", message.as_ref());
+    }
+
+    fn finish(&self) -> bool {
+        println!();
+        if self.failures == 0 {
+            println!("Configuration is valid ({} warnings)", self.warnings);
+        } else {
+            println!(
+                "Configuration has {} errors and {} warnings",
+                self.failures, self.warnings
+            );
+        }
+        self.failures == 0
+    }
+}
+
+/// Runs every check and prints the report. Returns `true` when the server
+/// could start with this configuration.
+pub fn run(args: Vec<String>, file_config: anyhow::Result<FileConfig>) -> bool {
+    let mut report = Report::default();
+
+    let file_config = match file_config {
+        Ok(file_config) => {
+            match &file_config.source {
+                Some(path) => report.ok(format!("config file {}", path.display())),
+                None => report.ok("no config file found, using built-in defaults"),
+            }
+            for warning in &file_config.warnings {
+                report.warn(warning);
+            }
+            file_config
+        }
+        Err(e) => {
+            for line in e.to_string().lines().filter(|l| !l.trim().is_empty()) {
+                report.fail(line.trim());
+            }
+            return report.finish();
+        }
+    };
+
+    let config = match parse_args(args, file_config) {
+        Ok(config) => config,
+        Err(e) => {
+            report.fail(e.to_string());
+            return report.finish();
+        }
+    };
+
+    report.ok(format!(
+        "{} worker threads, log level {}",
+        config.num_workers.unwrap_or(30),
+        config.log_level.unwrap_or(log::LevelFilter::Off)
+    ));
+
+    let tls_enabled = check_tls(&config, &mut report);
+    check_listeners(&config, tls_enabled, &mut report);
+    check_privileges(&config, &mut report);
+    check_registration(&config, tls_enabled, &mut report);
+
+    if config.proxy_protocol && config.trusted_proxies.is_empty() {
+        report.warn("proxy_protocol is enabled without trusted_proxies, any peer can set the client address");
+    }
+
+    report.finish()
+}
+
+/// Returns whether the TLS listeners would be started
+fn check_tls(config: &ServerConfig, report: &mut Report) -> bool {
+    match (&config.cert_path, &config.key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let (cert_exists, key_exists) =
+                (Path::new(cert_path).exists(), Path::new(key_path).exists());
+            if config.self_signed && !cert_exists && !key_exists {
+                let parent = Path::new(cert_path).parent().filter(|p| !p.as_os_str().is_empty());
+                if parent.map(|p| p.is_dir()).unwrap_or(true) {
+                    report.ok(format!(
+                        "self-signed certificate will be generated and stored in {}",
+                        cert_path
+                    ));
+                    return true;
+                }
+                report.fail(format!(
+                    "directory for the self-signed certificate {} does not exist",
+                    cert_path
+                ));
+                return false;
+            }
+            check_certificate(Path::new(cert_path), Path::new(key_path), report)
+        }
+        (Some(path), None) | (None, Some(path)) if !config.self_signed => {
+            report.fail(format!(
+                "only one of certificate and key is configured ({}), TLS needs both",
+                path
+            ));
+            false
+        }
+        _ if config.self_signed => {
+            report.ok("self-signed certificate will be generated at start-up");
+            true
+        }
+        _ => {
+            report.ok("TLS disabled, no certificate configured");
+            false
+        }
+    }
+}
+
+fn check_certificate(cert_path: &Path, key_path: &Path, report: &mut Report) -> bool {
+    let mut valid = true;
+
+    let cert = match fs::read(cert_path)
+        .map_err(|e| e.to_string())
+        .and_then(|pem| X509::from_pem(&pem).map_err(|e| e.to_string()))
+    {
+        Ok(cert) => Some(cert),
+        Err(e) => {
+            report.fail(format!("certificate {}: {}", cert_path.display(), e));
+            valid = false;
+            None
+        }
+    };
+    let key = match fs::read(key_path)
+        .map_err(|e| e.to_string())
+        .and_then(|pem| PKey::private_key_from_pem(&pem).map_err(|e| e.to_string()))
+    {
+        Ok(key) => Some(key),
+        Err(e) => {
+            report.fail(format!("private key {}: {}", key_path.display(), e));
+            valid = false;
+            None
+        }
+    };
+
+    #[cfg(unix)]
+    if let Ok(metadata) = fs::metadata(key_path) {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o077 != 0 {
+            report.warn(format!(
+                "private key {} is accessible by group or others (mode {:o})",
+                key_path.display(),
+                metadata.permissions().mode() & 0o777
+            ));
+        }
+    }
+
+    if let Some(cert) = &cert {
+        let now = Asn1Time::days_from_now(0).expect("current time");
+        match now.diff(cert.not_after()) {
+            Ok(remaining) if remaining.days < 0 || (remaining.days == 0 && remaining.secs < 0) => {
+                report.fail(format!(
+                    "certificate {} expired on {}",
+                    cert_path.display(),
+                    cert.not_after()
+                ));
+                valid = false;
+            }
+            Ok(remaining) if remaining.days < CERT_EXPIRY_WARNING_DAYS => report.warn(format!(
+                "certificate {} expires in {} days ({})",
+                cert_path.display(),
+                remaining.days,
+                cert.not_after()
+            )),
+            Ok(remaining) => report.ok(format!(
+                "certificate {} valid until {} ({} days)",
+                cert_path.display(),
+                cert.not_after(),
+                remaining.days
+            )),
+            Err(e) => report.warn(format!("could not check certificate expiry: {}", e)),
+        }
+        if let Ok(diff) = now.diff(cert.not_before()) {
+            if diff.days > 0 || diff.secs > 0 {
+                report.fail(format!(
+                    "certificate {} is not valid before {}",
+                    cert_path.display(),
+                    cert.not_before()
+                ));
+                valid = false;
+            }
+        }
+    }
+
+    if let (Some(cert), Some(key)) = (&cert, &key) {
+        let matches = cert
+            .public_key()
+            .map(|public| public.public_eq(key))
+            .unwrap_or(false);
+        if !matches {
+            report.fail(format!(
+                "private key {} does not belong to certificate {}",
+                key_path.display(),
+                cert_path.display()
+            ));
+            return false;
+        }
+    }
+
+    if valid {
+        // Same code path the server uses, catches chains rustls can't use
+        match load_server_config(cert_path, key_path) {
+            Ok(_) => report.ok(format!("TLS key {} matches the certificate", key_path.display())),
+            Err(e) => {
+                report.fail(format!("TLS configuration: {}", e));
+                valid = false;
+            }
+        }
+    }
+    valid
+}
+
+fn check_listeners(config: &ServerConfig, tls_enabled: bool, report: &mut Report) {
+    let mut listeners: Vec<(&str, SocketAddr)> =
+        config.tcp_addresses.iter().map(|addr| ("TCP", *addr)).collect();
+    if tls_enabled {
+        listeners.extend(config.tls_addresses.iter().map(|addr| ("TLS", *addr)));
+    }
+    if config.enable_mdns {
+        listeners.push(("static files", SocketAddr::from(([0, 0, 0, 0], 5006))));
+    }
+
+    for (i, (kind, addr)) in listeners.iter().enumerate() {
+        for (other_kind, other) in &listeners[..i] {
+            if overlaps(addr, other) {
+                report.fail(format!(
+                    "{} listener {} conflicts with {} listener {}",
+                    kind, addr, other_kind, other
+                ));
+            }
+        }
+    }
+
+    if std::env::var_os("LISTEN_FDS").is_some() {
+        report.ok("listening sockets are passed by systemd, skipping bind test");
+        return;
+    }
+
+    // Keep the sockets open until all are bound, like the server does
+    let mut bound = Vec::new();
+    let mut tcp_bound = 0;
+    for (kind, addr) in &listeners {
+        let result = if addr.is_ipv6() {
+            MioServer::bind_ipv6_with_v6only(*addr)
+        } else {
+            TcpListener::bind(*addr)
+        };
+        match result {
+            Ok(listener) => {
+                report.ok(format!("{} listener {} can be bound", kind, addr));
+                if *kind == "TCP" {
+                    tcp_bound += 1;
+                }
+                bound.push(listener);
+            }
+            Err(e) => report.warn(format!("{} listener {} cannot be bound: {}", kind, addr, e)),
+        }
+    }
+    if tcp_bound == 0 {
+        report.fail("none of the TCP listen addresses can be bound");
+    }
+}
+
+/// Listeners on the same port collide when the addresses are equal or one of
+/// them is the wildcard of the same family (IPv6 sockets are bound V6ONLY)
+fn overlaps(a: &SocketAddr, b: &SocketAddr) -> bool {
+    a.port() != 0
+        && a.port() == b.port()
+        && a.is_ipv4() == b.is_ipv4()
+        && (a.ip() == b.ip() || a.ip().is_unspecified() || b.ip().is_unspecified())
+}
+
+fn check_privileges(config: &ServerConfig, report: &mut Report) {
+    if let Some(user) = &config.user {
+        let privileges = UserPrivileges::new(user).and_then(|privileges| match &config.group {
+            Some(group) => privileges.with_group(group),
+            None => Ok(privileges),
+        });
+        match privileges {
+            Ok(_) => report.ok(format!(
+                "will switch to user {}{}",
+                user,
+                config.group.as_ref().map(|g| format!(":{}", g)).unwrap_or_default()
+            )),
+            Err(e) => report.fail(format!("user/group: {}", e)),
+        }
+        if UserPrivileges::check_root().is_err() {
+            report.fail(format!("must be started as root to switch to user {}", user));
+        }
+    }
+
+    let pid_file = config
+        .pid_file
+        .clone()
+        .or_else(|| config.daemon.then(|| DEFAULT_PID_FILE.to_string()));
+    if let Some(pid_file) = pid_file {
+        let parent = Path::new(&pid_file).parent().filter(|p| !p.as_os_str().is_empty());
+        // The default directory is created on start-up
+        if pid_file == DEFAULT_PID_FILE || parent.map(|p| p.is_dir()).unwrap_or(true) {
+            report.ok(format!("PID file {}", pid_file));
+        } else {
+            report.fail(format!("directory for PID file {} does not exist", pid_file));
+        }
+    }
+}
+
+fn check_registration(config: &ServerConfig, tls_enabled: bool, report: &mut Report) {
+    if !config.server_registration {
+        return;
+    }
+    match reqwest::Url::parse(&config.control_server) {
+        Ok(url) if url.scheme() == "https" || url.scheme() == "http" => {
+            report.ok(format!("registration with control server {}", config.control_server))
+        }
+        _ => report.fail(format!(
+            "control_server '{}' is not an http(s) URL",
+            config.control_server
+        )),
+    }
+    if config.registration_token.as_deref().unwrap_or("").is_empty() {
+        report.fail("server_registration is enabled but registration_token is not set");
+    }
+    if tls_enabled && config.hostname.is_none() {
+        report.warn("hostname is not set, the TLS port is not announced to the control server");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mioserver::self_signed;
+    use openssl::ec::{EcGroup, EcKey};
+    use openssl::hash::MessageDigest;
+    use openssl::nid::Nid;
+    use openssl::x509::X509NameBuilder;
+    use std::path::PathBuf;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nettest-check-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_pair(dir: &Path, cert_pem: &[u8], key_pem: &[u8]) -> (PathBuf, PathBuf) {
+        let (cert_path, key_path) = (dir.join("cert.pem"), dir.join("key.pem"));
+        fs::write(&cert_path, cert_pem).unwrap();
+        fs::write(&key_path, key_pem).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::set_permissions(&key_path, fs::Permissions::from_mode(0o600)).unwrap();
+        }
+        (cert_path, key_path)
+    }
+
+    /// Self-signed certificate and key that expired yesterday
+    fn expired_pair() -> (Vec<u8>, Vec<u8>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "localhost").unwrap();
+        let name = name.build();
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::from_unix(now - 30 * 86400).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::from_unix(now - 86400).unwrap()).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build().to_pem().unwrap(), key.private_key_to_pem_pkcs8().unwrap())
+    }
+
+    #[test]
+    fn test_check_certificate() {
+        let dir = temp_dir("cert");
+        let generated = self_signed::generate(None).unwrap();
+        let (cert_path, key_path) = write_pair(&dir, &generated.cert_pem, &generated.key_pem);
+
+        let mut report = Report::default();
+        assert!(check_certificate(&cert_path, &key_path, &mut report));
+        assert_eq!((report.failures, report.warnings), (0, 0));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_certificate_rejects_mismatched_key() {
+        let dir = temp_dir("mismatch");
+        let (cert, other) = (self_signed::generate(None).unwrap(), self_signed::generate(None).unwrap());
+        let (cert_path, key_path) = write_pair(&dir, &cert.cert_pem, &other.key_pem);
+
+        let mut report = Report::default();
+        assert!(!check_certificate(&cert_path, &key_path, &mut report));
+        assert_eq!(report.failures, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_certificate_rejects_expired_certificate() {
+        let dir = temp_dir("expired");
+        let (cert_pem, key_pem) = expired_pair();
+        let (cert_path, key_path) = write_pair(&dir, &cert_pem, &key_pem);
+
+        let mut report = Report::default();
+        assert!(!check_certificate(&cert_path, &key_path, &mut report));
+        assert_eq!(report.failures, 1);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_tls() {
+        let dir = temp_dir("tls");
+        let cert_path = dir.join("cert.pem").to_string_lossy().into_owned();
+        let key_path = dir.join("key.pem").to_string_lossy().into_owned();
+
+        let mut report = Report::default();
+        let config = ServerConfig { cert_path: Some(cert_path.clone()), ..ServerConfig::default() };
+        assert!(!check_tls(&config, &mut report));
+        assert_eq!(report.failures, 1);
+
+        // Missing files are generated with -self-signed
+        let mut report = Report::default();
+        let config = ServerConfig {
+            cert_path: Some(cert_path.clone()),
+            key_path: Some(key_path.clone()),
+            self_signed: true,
+            ..ServerConfig::default()
+        };
+        assert!(check_tls(&config, &mut report));
+        assert_eq!(report.failures, 0);
+
+        let mut report = Report::default();
+        let config = ServerConfig { self_signed: false, ..config };
+        assert!(!check_tls(&config, &mut report));
+        assert_eq!(report.failures, 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_check_privileges() {
+        let mut report = Report::default();
+        let config = ServerConfig {
+            user: Some("nettest-no-such-user".to_string()),
+            ..ServerConfig::default()
+        };
+        check_privileges(&config, &mut report);
+        assert!(report.failures >= 1);
+
+        let mut report = Report::default();
+        let config = ServerConfig {
+            pid_file: Some("/nonexistent-nettest-dir/nettest.pid".to_string()),
+            ..ServerConfig::default()
+        };
+        check_privileges(&config, &mut report);
+        assert_eq!(report.failures, 1);
+
+        // The default PID directory is created on start-up
+        let mut report = Report::default();
+        let config = ServerConfig { daemon: true, ..ServerConfig::default() };
+        check_privileges(&config, &mut report);
+        assert_eq!(report.failures, 0);
+    }
+
+    #[test]
+    fn test_check_registration() {
+        let config = ServerConfig {
+            server_registration: true,
+            control_server: "https://control.example".to_string(),
+            registration_token: None,
+            ..ServerConfig::default()
+        };
+        let mut report = Report::default();
+        check_registration(&config, false, &mut report);
+        assert_eq!(report.failures, 1);
+
+        let mut report = Report::default();
+        let config = ServerConfig {
+            registration_token: Some("token".to_string()),
+            ..config
+        };
+        check_registration(&config, true, &mut report);
+        assert_eq!((report.failures, report.warnings), (0, 1));
+
+        let mut report = Report::default();
+        let config = ServerConfig {
+            control_server: "control.example".to_string(),
+            hostname: Some("nettest.example".to_string()),
+            ..config
+        };
+        check_registration(&config, true, &mut report);
+        assert_eq!((report.failures, report.warnings), (1, 0));
+    }
+
+    #[test]
+    fn test_overlapping_listeners() {
+        let any4: SocketAddr = "0.0.0.0:443".parse().unwrap();
+        let local4: SocketAddr = "127.0.0.1:443".parse().unwrap();
+        let any6: SocketAddr = "[::]:443".parse().unwrap();
+        let other_port: SocketAddr = "127.0.0.1:5005".parse().unwrap();
+
+        assert!(overlaps(&any4, &local4));
+        assert!(overlaps(&local4, &local4));
+        assert!(!overlaps(&any4, &any6));
+        assert!(!overlaps(&local4, &other_port));
+    }
+}
@@ -31,7 +31,7 @@ pub async fn register_server(config: &ServerConfig) -> Result<()> {
 
     //if hostname isSome and keyPath is some and certPath is some then tlsPort is Some(tls_address.port()) else tlsPort is None
     let tls_port =
-        if config.hostname.is_some() && config.key_path.is_some() && config.cert_path.is_some() {
+        if config.hostname.is_some() && config.tls_enabled() {
             match config.tls_addresses.first() {
                 Some(address) => Some(address.port() as i32),
                 None => None,
@@ -8,6 +8,8 @@ use std::time::Duration;
 use tokio::time::interval;
 use std::net::{IpAddr, UdpSocket};
 
+/// Service type the server announces and `nettest discover` browses for
+pub const SERVICE_TYPE: &str = "_nettest._tcp.local.";
 
 /// Starts mDNS service that announces the server in the local network
 /// and responds to queries with server configuration via TXT records
@@ -23,7 +25,7 @@ pub async fn start_mdns_service(
     // Single mDNS service for both TCP and TLS (if available)
     // Both protocols share the same IP address, so we announce one host with both ports in TXT
     let tcp_port = config.tcp_addresses.first().unwrap().port();
-    let service_type = "_nettest._tcp.local.";
+    let service_type = SERVICE_TYPE;
     let instance_name = "nettest";
     let hostname = format!("{}.local.", instance_name);
     
@@ -34,10 +36,14 @@ pub async fn start_mdns_service(
     txt_properties.insert("tcp_port".to_string(), tcp_port.to_string());
     
     // Add TLS port if available (optional, for external access or additional security)
-    if config.cert_path.is_some() && config.key_path.is_some() {
+    if config.tls_enabled() {
         let tls_port = config.tls_addresses.first().unwrap().port();
         txt_properties.insert("tls_port".to_string(), tls_port.to_string());
         info!("TLS available on port {}, will be included in mDNS TXT records", tls_port);
+        // Self-signed certificates can't be verified, clients pin this fingerprint instead
+        if let Some(ref fingerprint) = config.tls_fingerprint {
+            txt_properties.insert("tls_fingerprint".to_string(), fingerprint.clone());
+        }
     }
     
     if let Some(ref version) = config.version {
@@ -94,7 +100,7 @@ pub async fn start_mdns_service(
 
 /// Gets the local non-loopback IP address
 /// Returns the first non-loopback, non-link-local IPv4 address found
-fn get_local_network_ip() -> Option<String> {
+pub(crate) fn get_local_network_ip() -> Option<String> {
     // Method 1: Try UDP socket connection to external address
     if let Ok(socket) = UdpSocket::bind("0.0.0.0:0") {
         if socket.connect("8.8.8.8:80").is_ok() {
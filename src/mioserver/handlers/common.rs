@@ -1,4 +1,4 @@
-use log::{info, trace};
+use log::{debug, info, trace};
 use mio::{Interest, Poll};
 use std::io;
 
@@ -6,7 +6,9 @@ use crate::{
     config::constants::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE},
     mioserver::{server::TestState, ServerTestPhase},
 };
-use crate::mioserver::handlers::timeout_utils::check_timeout_periodic;
+use crate::mioserver::handlers::timeout_utils::{
+    check_timeout_periodic, transfer_time_limit,
+};
 
 pub fn handle_main_command_send(poll: &Poll, state: &mut TestState) -> io::Result<usize> {
     info!("handle_get_put_ping_quit_send");
@@ -109,6 +111,12 @@ pub fn handle_main_command_receive(poll: &Poll, state: &mut TestState) -> io::Re
                 };
 
                 state.duration = duration;
+                state.time_limit = transfer_time_limit(
+                    state.connection_start,
+                    state.time_limit,
+                    duration,
+                    state.max_transfer_secs,
+                );
 
                 let chunk_size = if parts.len() == 1 {
                     MIN_CHUNK_SIZE
@@ -161,6 +169,13 @@ pub fn handle_main_command_receive(poll: &Poll, state: &mut TestState) -> io::Re
                 }
                 state.read_pos = 0;
                 state.measurement_state = ServerTestPhase::PutNoResultSendOk;
+                // The client decides how long it uploads
+                state.time_limit = transfer_time_limit(
+                    state.connection_start,
+                    state.time_limit,
+                    state.max_transfer_secs,
+                    state.max_transfer_secs,
+                );
                 state
                     .stream
                     .reregister(poll, state.token, Interest::WRITABLE)?;
@@ -170,6 +185,13 @@ pub fn handle_main_command_receive(poll: &Poll, state: &mut TestState) -> io::Re
             if command_str.starts_with("PUTTIMERESULT") {
                 state.read_pos = 0;
                 state.measurement_state = ServerTestPhase::PutTimeResultSendOk;
+                // The client decides how long it uploads
+                state.time_limit = transfer_time_limit(
+                    state.connection_start,
+                    state.time_limit,
+                    state.max_transfer_secs,
+                    state.max_transfer_secs,
+                );
                 let parts: Vec<&str> = command_str.split_whitespace().collect();
 
 
@@ -216,6 +238,13 @@ pub fn handle_main_command_receive(poll: &Poll, state: &mut TestState) -> io::Re
                 state.sent_time_ns = None;
                 state.bytes_received.clear();
                 state.measurement_state = ServerTestPhase::PutSendOk;
+                // The client decides how long it uploads
+                state.time_limit = transfer_time_limit(
+                    state.connection_start,
+                    state.time_limit,
+                    state.max_transfer_secs,
+                    state.max_transfer_secs,
+                );
                 state
                     .stream
                     .reregister(poll, state.token, Interest::WRITABLE)?;
@@ -223,7 +252,7 @@ pub fn handle_main_command_receive(poll: &Poll, state: &mut TestState) -> io::Re
             }
 
             if command_str.starts_with("SIGNEDRESULT") {
-                println!("SIGNEDRESULT");
+                debug!("SIGNEDRESULT");
                 state.read_pos = 0;
                 state.write_pos = 0;
                 state.measurement_state = ServerTestPhase::SignedResultSend;
@@ -85,9 +85,11 @@ pub fn handle_put_time_result_receive_chunk(
 
 pub fn handle_put_time_result_send_time(poll: &Poll, state: &mut TestState) -> io::Result<usize> {
     info!("handle_put_time_result_send_time");
-    let result = state.bytes_received.iter().map(|(t, b)| format!("({} {})", t, b)).collect::<Vec<String>>().join("; ");
-    let command = format!("TIMERESULT {}\n", result);
+    // Formatted once, the reply holds a pair per chunk and is resumed here on
+    // every writable event
     if state.write_pos == 0 {
+        let result = state.bytes_received.iter().map(|(t, b)| format!("({} {})", t, b)).collect::<Vec<String>>().join("; ");
+        let command = format!("TIMERESULT {}\n", result);
         if state.chunk_buffer.len() < command.len() {
             state.chunk_buffer.resize(command.len(), 0);
         }
@@ -119,3 +121,103 @@ pub fn handle_put_time_result_send_time(poll: &Poll, state: &mut TestState) -> i
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::io::Read;
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use mio::{net::TcpStream, Token};
+
+    use crate::stream::stream::Stream;
+
+    fn test_state(stream: TcpStream) -> TestState {
+        TestState {
+            token: Token(1),
+            connection_id: 1,
+            connection_start: Instant::now(),
+            time_limit: Duration::from_secs(60),
+            stream: Stream::Tcp(stream),
+            measurement_state: ServerTestPhase::PutTimeResultSendTimeResult,
+            read_buffer: [0; 1024 * 8],
+            write_buffer: [0; 1024 * 8],
+            read_bytes: BytesMut::new(),
+            read_pos: 0,
+            total_bytes_received: 0,
+            total_bytes_sent: 0,
+            write_pos: 0,
+            num_chunks: 0,
+            chunk_size: 0,
+            processed_chunks: 0,
+            clock: Some(Instant::now()),
+            sent_time_ns: None,
+            received_time_ns: None,
+            duration: 0,
+            put_duration: None,
+            chunk_buffer: Vec::new(),
+            loop_iteration_count: 0,
+            chunk: None,
+            terminal_chunk: None,
+            bytes_received: VecDeque::new(),
+            client_addr: None,
+            sig_key: None,
+            max_chunk_size: 4096,
+            max_transfer_secs: 3600,
+        }
+    }
+
+    #[test]
+    fn test_time_result_is_resumed_across_writable_events() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        server.set_nonblocking(true).unwrap();
+
+        let poll = Poll::new().unwrap();
+        let mut state = test_state(TcpStream::from_std(server));
+        state.stream.register(&poll, state.token, Interest::WRITABLE).unwrap();
+        // Large enough to fill the socket buffers several times over
+        state.bytes_received = (1..=200_000u64).map(|i| (i * 1_000, i * 4_096)).collect();
+        let expected = format!(
+            "TIMERESULT {}\n",
+            state
+                .bytes_received
+                .iter()
+                .map(|(t, b)| format!("({} {})", t, b))
+                .collect::<Vec<String>>()
+                .join("; ")
+        );
+
+        let mut received = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut partial_writes = 0;
+        loop {
+            match handle_put_time_result_send_time(&poll, &mut state) {
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    partial_writes += 1;
+                    // The reply is the one formatted on the first event, later
+                    // samples do not change it
+                    state.bytes_received.push_back((0, 0));
+                    let n = client.read(&mut buf).unwrap();
+                    received.extend_from_slice(&buf[..n]);
+                }
+                Err(e) => panic!("{}", e),
+            }
+        }
+        assert!(partial_writes > 0);
+        assert_eq!(state.measurement_state, ServerTestPhase::AcceptCommandSend);
+        assert_eq!(state.write_pos, 0);
+
+        client.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        while received.len() < expected.len() {
+            let n = client.read(&mut buf).unwrap();
+            assert!(n > 0);
+            received.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(String::from_utf8(received).unwrap(), expected);
+    }
+}
@@ -10,7 +10,7 @@ use base64;
 use crate::mioserver::{server::TestState, ServerTestPhase};
 
 pub fn handle_signed_result(poll: &Poll, state: &mut TestState) -> Result<usize, std::io::Error> {
-    println!("handle_signed_result");
+    debug!("handle_signed_result");
 
     let message = format!(
         "GETTIME:({} {}); PUTTIMERESULT:({} {}); CLIENT_IP:{}; TIMESTAMP:{};",
@@ -54,7 +54,7 @@ pub fn handle_signed_result(poll: &Poll, state: &mut TestState) -> Result<usize,
 }
 
 pub fn handle_signed_result_receive_ok(poll: &Poll, state: &mut TestState) -> Result<usize, std::io::Error> {
-    println!("handle_signed_result_receive_ok");
+    debug!("handle_signed_result_receive_ok");
     let ok = b"OK\n";
     loop {
         let n = state.stream.read(&mut state.read_buffer)?;
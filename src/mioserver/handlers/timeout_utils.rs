@@ -1,10 +1,33 @@
 use crate::mioserver::server::TestState;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 // Connection timeout constant
 pub const CONNECTION_TIMEOUT_SECS: u64 = 60;
 // Check timeout every 1000 iterations to avoid frequent time calls
 pub const TIMEOUT_CHECK_INTERVAL: u32 = 10000;
+// Default of `max_transfer_secs`, the longest download or upload a connection gets extra time for
+pub const MAX_TRANSFER_SECS: u64 = 3600;
+// Time added to a transfer for the commands around it
+const TRANSFER_MARGIN_SECS: u64 = 15;
+
+// A connection's time limit never grows beyond this, however many transfers it requests
+pub fn max_connection_secs(max_transfer_secs: u64) -> u64 {
+    CONNECTION_TIMEOUT_SECS.saturating_add(max_transfer_secs.saturating_mul(2))
+}
+
+// Time limit of a connection started at `connection_start` that fits a transfer of `secs`,
+// at most `max_transfer_secs`, starting now
+pub fn transfer_time_limit(
+    connection_start: Instant,
+    time_limit: Duration,
+    secs: u64,
+    max_transfer_secs: u64,
+) -> Duration {
+    let needed = connection_start.elapsed().saturating_add(Duration::from_secs(secs.min(max_transfer_secs).saturating_add(TRANSFER_MARGIN_SECS)));
+    time_limit
+        .max(needed)
+        .min(Duration::from_secs(max_connection_secs(max_transfer_secs)))
+}
 
 // Check connection timeout helper function
 pub fn check_timeout_periodic(state: &mut TestState, function_name: &str) -> Result<usize, std::io::Error> {
@@ -12,7 +35,7 @@ pub fn check_timeout_periodic(state: &mut TestState, function_name: &str) -> Res
     
     // Only check timeout every 1000 iterations
     if state.loop_iteration_count % TIMEOUT_CHECK_INTERVAL == 0 {
-        if state.connection_start.elapsed() > Duration::from_secs(CONNECTION_TIMEOUT_SECS) {
+        if state.connection_start.elapsed() > state.time_limit {
             log::debug!("Connection timeout in {}, age: {:?}, iterations: {}", 
                        function_name, state.connection_start.elapsed(), state.loop_iteration_count);
             return Err(std::io::Error::new(std::io::ErrorKind::Other, "Connection timeout"));
@@ -1,10 +1,17 @@
 pub mod server;
+pub mod builder;
 pub mod handlers;
 pub mod server_test_phase;
 pub mod worker;
 pub mod parser;
 pub mod control_server;
+pub mod proxy_protocol;
+pub mod self_signed;
+pub mod systemd;
+pub mod check_config;
+pub mod reload;
 
-pub use server::MioServer; 
+pub use builder::ServerBuilder;
+pub use server::{MioServer, ServerConfig, ShutdownHandle};
 pub use server_test_phase::ServerTestPhase;
 
@@ -1,16 +1,34 @@
 use crate::{
     config::FileConfig,
-    logger,
-    mioserver::{handlers::signed_result::generate_secret_key, server::ServerConfig},
+    mioserver::{
+        handlers::signed_result::generate_secret_key, proxy_protocol::parse_cidr_list,
+        server::ServerConfig,
+    },
     tokio_server::{ utils::user},
     config::parser::parse_listen_address,
 };
-use log::LevelFilter;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 
 pub fn parse_args(
     args: Vec<String>,
     default_config: FileConfig,
+) -> Result<ServerConfig, anyhow::Error> {
+    parse(args, default_config, true)
+}
+
+/// Same as [`parse_args`] for a reload on SIGHUP, when `-u` has already
+/// dropped the privileges
+pub fn parse_reload_args(
+    args: Vec<String>,
+    default_config: FileConfig,
+) -> Result<ServerConfig, anyhow::Error> {
+    parse(args, default_config, false)
+}
+
+fn parse(
+    args: Vec<String>,
+    default_config: FileConfig,
+    startup: bool,
 ) -> Result<ServerConfig, anyhow::Error> {
     let mut config = ServerConfig {
         tcp_addresses: vec![],
@@ -19,9 +37,12 @@ pub fn parse_args(
         key_path: default_config.key_path,
         num_workers: default_config.server_workers,
         user: default_config.user,
+        group: default_config.group,
         daemon: default_config.daemonize,
+        pid_file: default_config.pid_file,
         version: Some("2.0.0".to_string()),
-        secret_key: generate_secret_key(),
+        secret_key_configured: default_config.signing_key.is_some(),
+        secret_key: default_config.signing_key.unwrap_or_else(generate_secret_key),
         log_level: Some(default_config.logger),
         server_registration: default_config.server_registration,
         control_server: default_config.control_server,
@@ -30,7 +51,23 @@ pub fn parse_args(
         registration_token: default_config.registration_token,
         server_name: default_config.server_name,
         enable_mdns: false,
+        self_signed: default_config.self_signed,
+        tls_config: None,
+        tls_fingerprint: None,
+        proxy_protocol: default_config.proxy_protocol,
+        trusted_proxies: match default_config.trusted_proxies {
+            Some(ref list) => parse_cidr_list(list)
+                .map_err(|e| anyhow::anyhow!("Invalid trusted_proxies in config: {}", e))?,
+            None => vec![],
+        },
+        check_config: false,
+        max_chunk_size: default_config.max_chunk_size,
+        max_transfer_secs: default_config.max_transfer_secs,
+        log_options: default_config.log_options,
+        // `nettest -s` runs as a socket activated systemd service
+        socket_activation: true,
     };
+    let mut user_from_args = false;
 
     let mut i = 1;
     while i < args.len() {
@@ -38,7 +75,9 @@ pub fn parse_args(
             "-l" | "-L" => {
                 i += 1;
                 if i < args.len() {
-                    let addr = parse_listen_address(&args[i]).unwrap();
+                    let addr = parse_listen_address(&args[i]).map_err(|e| {
+                        anyhow::anyhow!("Invalid {} address '{}': {}", args[i - 1], args[i], e)
+                    })?;
                     if args[i - 1] == "-L" {
                         config.tls_addresses.push(addr);
                     } else {
@@ -58,19 +97,51 @@ pub fn parse_args(
                     config.key_path = Some(args[i].clone());
                 }
             }
+            "-selfsigned" => {
+                config.self_signed = true;
+            }
+            "-proxy-protocol" => {
+                config.proxy_protocol = true;
+            }
+            "-trusted-proxy" => {
+                i += 1;
+                if i < args.len() {
+                    let networks = parse_cidr_list(&args[i]).map_err(|e| anyhow::anyhow!(e))?;
+                    config.trusted_proxies.extend(networks);
+                }
+            }
             "-t" => {
                 i += 1;
                 if i < args.len() {
-                    config.num_workers = Some(args[i].parse().unwrap());
+                    match args[i].parse::<usize>() {
+                        Ok(workers) if workers > 0 => config.num_workers = Some(workers),
+                        _ => {
+                            return Err(anyhow::anyhow!(
+                                "Invalid -t value '{}', expected a number of worker threads",
+                                args[i]
+                            ))
+                        }
+                    }
                 }
             }
             "-u" => {
+                i += 1;
                 if i < args.len() {
-                    user::UserPrivileges::check_root()?;
-                    i += 1;
-                    config.user = Some(args[i].clone());
+                    user_from_args = true;
+                    match args[i].split_once(':') {
+                        Some((user, group)) => {
+                            config.user = Some(user.to_string());
+                            config.group = Some(group.to_string());
+                        }
+                        None => config.user = Some(args[i].clone()),
+                    }
                 }
+            }
+            "-pid" => {
                 i += 1;
+                if i < args.len() {
+                    config.pid_file = Some(args[i].clone());
+                }
             }
             "-d" => {
                 config.daemon = true;
@@ -78,7 +149,30 @@ pub fn parse_args(
             "-log" => {
                 i += 1;
                 if i < args.len() {
-                    config.log_level = Some(args[i].parse().unwrap());
+                    config.log_level = Some(args[i].parse().map_err(|_| {
+                        anyhow::anyhow!(
+                            "Invalid -log level '{}', expected off, error, warn, info, debug or trace",
+                            args[i]
+                        )
+                    })?);
+                }
+            }
+            "-log-file" => {
+                i += 1;
+                if i < args.len() {
+                    config.log_options.path = Some(args[i].clone().into());
+                }
+            }
+            "-log-target" => {
+                i += 1;
+                if i < args.len() {
+                    config.log_options.target = args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?;
+                }
+            }
+            "-log-format" => {
+                i += 1;
+                if i < args.len() {
+                    config.log_options.format = args[i].parse().map_err(|e: String| anyhow::anyhow!(e))?;
                 }
             }
             "-register" => {
@@ -87,65 +181,49 @@ pub fn parse_args(
             "-mdns" => {
                 config.enable_mdns = true;
             }
-            "--help" | "-h" => {
-                print_help();
-                std::process::exit(0);
-            }
-            "-v" | "--version" => {
-                println!("nettest {}", env!("CARGO_PKG_VERSION"));
-                std::process::exit(0);
+            "--check-config" => {
+                config.check_config = true;
             }
             _ => {
-                eprintln!("Error: Unknown option '{}'\n", args[i]);
-                print_help();
-                std::process::exit(1);
+                return Err(anyhow::anyhow!(
+                    "Unknown option '{}', see 'nettest -s -h'",
+                    args[i]
+                ))
             }
         }
         i += 1;
     }
-    if config.log_level.is_some() && config.log_level.unwrap() != LevelFilter::Off {
-        println!(
-            "Initializing logger with level: {:?}",
-            config.log_level.unwrap()
-        );
-        logger::init_logger(config.log_level.unwrap()).unwrap();
+    if user_from_args && startup && !config.check_config {
+        user::UserPrivileges::check_root()?;
     }
 
     //add default addresses if args were not provided
     if config.tcp_addresses.is_empty() {
         config.tcp_addresses.push(SocketAddr::new(
             IpAddr::V6(Ipv6Addr::UNSPECIFIED),
-            default_config.server_tcp_port.parse().unwrap(),
+            default_config.server_tcp_port,
         ));
         config.tcp_addresses.push(SocketAddr::new(
             IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            default_config.server_tcp_port.parse().unwrap(),
+            default_config.server_tcp_port,
         ));
     }
     if config.tls_addresses.is_empty() {
         //keep this order to avoid conflicts with IPv4 addresses on unix
         config.tls_addresses.push(SocketAddr::new(
             IpAddr::V6(Ipv6Addr::UNSPECIFIED),
-            default_config
-                .server_tls_port
-                .clone()
-                .unwrap_or("443".to_string())
-                .parse()
-                .unwrap(),
+            default_config.server_tls_port.unwrap_or(443),
         ));
         config.tls_addresses.push(SocketAddr::new(
             IpAddr::V4(Ipv4Addr::UNSPECIFIED),
-            default_config
-                .server_tls_port
-                .unwrap_or("443".to_string())
-                .parse()
-                .unwrap(),
+            default_config.server_tls_port.unwrap_or(443),
         ));
     }
     Ok(config)
 }
 
-fn print_help() {
+/// Options of `nettest -s`, printed by the binary for `-h`
+pub fn print_help() {
     println!("nettest - Network speed measurement server\n");
     println!("USAGE:");
     println!("    nettest -s [OPTIONS]\n");
@@ -153,7 +231,9 @@ fn print_help() {
     println!("    nettest -s                                   Start TCP server on port 5005");
     println!("    nettest -s -k key.pem -c cert.pem            Start TCP + TLS server");
     println!("    nettest -s -l 8080 -L 8443                   Custom ports for TCP and TLS");
-    println!("    nettest -s -d -u nobody                      Run as daemon with user 'nobody'\n");
+    println!("    nettest -s -selfsigned                       TCP + TLS with a generated certificate");
+    println!("    nettest -s -d -u nobody                      Run as daemon with user 'nobody'");
+    println!("    nettest -s -L 443 --check-config             Validate the setup without starting\n");
     println!("OPTIONS:");
     println!("    -l ADDRESS      TCP listen address (default: 0.0.0.0:5005)");
     println!("                    Examples: \"5005\", \"192.168.1.1:5005\", \"[::]:5005\"");
@@ -162,12 +242,48 @@ fn print_help() {
     println!("    -c PATH         Path to SSL certificate in PEM format (required for TLS)");
     println!("                    Include intermediate certs in same file if needed");
     println!("    -k PATH         Path to SSL private key in PEM format (required for TLS)");
+    println!("    -selfsigned     Generate a self-signed certificate for TLS at startup");
+    println!("                    Stored in the -c/-k paths if given, reused when they exist");
     println!("    -t THREADS      Number of worker threads");
-    println!("    -u USER         Drop privileges and run as specified user (requires root)");
+    println!("    -proxy-protocol Require a PROXY protocol v1/v2 header on TCP and TLS connections");
+    println!("    -trusted-proxy CIDR");
+    println!("                    Proxy allowed to send PROXY headers and X-Forwarded-For/Forwarded");
+    println!("                    for WebSocket upgrades, e.g. \"10.0.0.0/8,::1\" (repeatable)");
+    println!("    -u USER[:GROUP] Drop privileges to USER (and GROUP) after binding (requires root)");
     println!("    -d              Run as daemon in background");
+    println!("    -pid PATH       Write PID file (default with -d: /run/nettest/nettest.pid)");
     println!("    -log LEVEL      Set log level: info, debug, trace");
+    println!("    -log-file PATH  Log file (default: /var/log/nettest/nettest.log)");
+    println!("    -log-target T   Log to: file (and stdout), stderr, journald");
+    println!("    -log-format F   Log line format: text, json");
     println!("    -register       Enable server registration with control server");
     println!("    -mdns           Enable mDNS service discovery for local network");
+    println!("    --check-config  Validate configuration, certificates and ports, then exit");
+    println!("                    (exit code 0 when valid, 1 on errors)");
     println!("    -h, --help      Show this help message");
     println!("    -v, --version   Print version and exit");
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        std::iter::once("-s").chain(args.iter().copied()).map(String::from).collect()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let config = parse_args(args(&["-l", "127.0.0.1:5006", "-t", "4"]), FileConfig::default()).unwrap();
+        assert_eq!(config.tcp_addresses, vec!["127.0.0.1:5006".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.num_workers, Some(4));
+        assert!(config.socket_activation);
+    }
+
+    #[test]
+    fn test_unknown_option_is_an_error() {
+        let error = parse_args(args(&["-x"]), FileConfig::default()).err().unwrap();
+        assert_eq!(error.to_string(), "Unknown option '-x', see 'nettest -s -h'");
+        assert!(parse_args(args(&["-t", "0"]), FileConfig::default()).is_err());
+    }
+}
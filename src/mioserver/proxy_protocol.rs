@@ -0,0 +1,376 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+/// Longest possible v1 header including CRLF, see the PROXY protocol spec section 2.1
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Upper bound of bytes needed to decide on a header: v2 header with IPv6 addresses
+/// plus room for TLVs the load balancer may append
+pub const MAX_HEADER_LENGTH: usize = 536;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProxyHeader {
+    /// Connection proxied on behalf of `source`
+    Proxied {
+        source: SocketAddr,
+        destination: SocketAddr,
+    },
+    /// Health check or connection initiated by the proxy itself (v2 LOCAL, v1 UNKNOWN)
+    Local,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyProtocolError(String);
+
+impl fmt::Display for ProxyProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid PROXY protocol header: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProxyProtocolError {}
+
+fn invalid(message: &str) -> ProxyProtocolError {
+    ProxyProtocolError(message.to_string())
+}
+
+/// Parses a PROXY protocol v1 or v2 header at the start of `buf`.
+///
+/// Returns `Ok(None)` while more data is needed, otherwise the header and its
+/// length in bytes, so the caller can consume exactly that much from the socket.
+pub fn parse_header(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyProtocolError> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if buf[0] == V2_SIGNATURE[0] {
+        let n = buf.len().min(V2_SIGNATURE.len());
+        if buf[..n] != V2_SIGNATURE[..n] {
+            return Err(invalid("bad v2 signature"));
+        }
+        return parse_v2(buf);
+    }
+    if b"PROXY "[..buf.len().min(6)] != buf[..buf.len().min(6)] {
+        return Err(invalid("missing PROXY prefix"));
+    }
+    parse_v1(buf)
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyProtocolError> {
+    let end = match buf.windows(2).position(|w| w == b"\r\n") {
+        Some(end) => end,
+        None if buf.len() >= V1_MAX_LENGTH => return Err(invalid("v1 header too long")),
+        None => return Ok(None),
+    };
+    let line = std::str::from_utf8(&buf[..end]).map_err(|_| invalid("v1 header is not ASCII"))?;
+    let parts: Vec<&str> = line.split(' ').collect();
+    let header = match parts.get(1).copied() {
+        Some("UNKNOWN") => ProxyHeader::Local,
+        Some(family @ ("TCP4" | "TCP6")) => {
+            if parts.len() != 6 {
+                return Err(invalid("v1 header needs 4 address fields"));
+            }
+            let src_ip: IpAddr = parts[2].parse().map_err(|_| invalid("bad source address"))?;
+            let dst_ip: IpAddr = parts[3]
+                .parse()
+                .map_err(|_| invalid("bad destination address"))?;
+            if (family == "TCP4") != src_ip.is_ipv4() || src_ip.is_ipv4() != dst_ip.is_ipv4() {
+                return Err(invalid("address family mismatch"));
+            }
+            let src_port: u16 = parts[4].parse().map_err(|_| invalid("bad source port"))?;
+            let dst_port: u16 = parts[5]
+                .parse()
+                .map_err(|_| invalid("bad destination port"))?;
+            ProxyHeader::Proxied {
+                source: SocketAddr::new(src_ip, src_port),
+                destination: SocketAddr::new(dst_ip, dst_port),
+            }
+        }
+        _ => return Err(invalid("unsupported v1 protocol")),
+    };
+    Ok(Some((header, end + 2)))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(ProxyHeader, usize)>, ProxyProtocolError> {
+    if buf.len() < V2_HEADER_LENGTH {
+        return Ok(None);
+    }
+    let version_command = buf[12];
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported v2 version"));
+    }
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let total = V2_HEADER_LENGTH + length;
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let payload = &buf[V2_HEADER_LENGTH..total];
+
+    match version_command & 0x0F {
+        0x0 => return Ok(Some((ProxyHeader::Local, total))),
+        0x1 => {}
+        _ => return Err(invalid("unsupported v2 command")),
+    }
+
+    let header = match buf[13] {
+        // TCP over IPv4
+        0x11 => {
+            if payload.len() < 12 {
+                return Err(invalid("short v2 IPv4 address block"));
+            }
+            let src = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let dst = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+            ProxyHeader::Proxied {
+                source: SocketAddr::new(
+                    IpAddr::V4(src),
+                    u16::from_be_bytes([payload[8], payload[9]]),
+                ),
+                destination: SocketAddr::new(
+                    IpAddr::V4(dst),
+                    u16::from_be_bytes([payload[10], payload[11]]),
+                ),
+            }
+        }
+        // TCP over IPv6
+        0x21 => {
+            if payload.len() < 36 {
+                return Err(invalid("short v2 IPv6 address block"));
+            }
+            let mut src = [0u8; 16];
+            let mut dst = [0u8; 16];
+            src.copy_from_slice(&payload[0..16]);
+            dst.copy_from_slice(&payload[16..32]);
+            ProxyHeader::Proxied {
+                source: SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(src)),
+                    u16::from_be_bytes([payload[32], payload[33]]),
+                ),
+                destination: SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(dst)),
+                    u16::from_be_bytes([payload[34], payload[35]]),
+                ),
+            }
+        }
+        // UNSPEC, UDP and unix sockets carry no usable client address
+        _ => ProxyHeader::Local,
+    };
+    Ok(Some((header, total)))
+}
+
+/// Network in CIDR notation, e.g. `10.0.0.0/8` or `2001:db8::/32`.
+/// A plain address is treated as a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("Invalid network address: {}", value))?;
+        let max_prefix = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("Invalid prefix length: {}", value))?,
+            None => max_prefix,
+        };
+        Ok(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        // Peers on dual-stack listeners show up as IPv4-mapped IPv6 addresses
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+            _ => *ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Parses a comma separated list of networks
+pub fn parse_cidr_list(value: &str) -> Result<Vec<Cidr>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(Cidr::parse)
+        .collect()
+}
+
+pub fn is_trusted(trusted: &[Cidr], ip: &IpAddr) -> bool {
+    trusted.iter().any(|cidr| cidr.contains(ip))
+}
+
+/// Determines the client address from `Forwarded` (RFC 7239) or `X-Forwarded-For`
+/// headers of an HTTP request received from `peer`.
+///
+/// Headers are only honoured if `peer` is a trusted proxy. The forwarding chain is
+/// walked from the right and the first hop that is not a trusted proxy is returned,
+/// so clients cannot spoof their address by sending the header themselves.
+pub fn forwarded_client_addr(
+    request: &str,
+    peer: SocketAddr,
+    trusted: &[Cidr],
+) -> Option<SocketAddr> {
+    if !is_trusted(trusted, &peer.ip()) {
+        return None;
+    }
+
+    let mut forwarded = Vec::new();
+    let mut x_forwarded_for = Vec::new();
+    for line in request.lines().skip(1) {
+        let Some((name, value)) = line.split_once(':') else {
+            continue;
+        };
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("forwarded") {
+            for element in value.split(',') {
+                for pair in element.split(';') {
+                    if let Some((key, node)) = pair.trim().split_once('=') {
+                        if key.trim().eq_ignore_ascii_case("for") {
+                            forwarded.push(parse_node(node.trim().trim_matches('"')));
+                        }
+                    }
+                }
+            }
+        } else if name.eq_ignore_ascii_case("x-forwarded-for") {
+            x_forwarded_for.extend(value.split(',').map(|node| parse_node(node.trim())));
+        }
+    }
+
+    let chain = if forwarded.is_empty() {
+        x_forwarded_for
+    } else {
+        forwarded
+    };
+    let mut client = None;
+    for node in chain.into_iter().rev() {
+        // Obfuscated or unknown identifiers end the usable part of the chain
+        let addr = node?;
+        client = Some(addr);
+        if !is_trusted(trusted, &addr.ip()) {
+            break;
+        }
+    }
+    client
+}
+
+fn parse_node(node: &str) -> Option<SocketAddr> {
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = node.trim_start_matches('[').trim_end_matches(']');
+    ip.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_v1_tcp4() {
+        let buf = b"PROXY TCP4 192.0.2.10 198.51.100.1 56324 5005\r\nGET / HTTP/1.1\r\n";
+        let (header, len) = parse_header(buf).unwrap().unwrap();
+        assert_eq!(len, 47);
+        assert_eq!(
+            header,
+            ProxyHeader::Proxied {
+                source: "192.0.2.10:56324".parse().unwrap(),
+                destination: "198.51.100.1:5005".parse().unwrap(),
+            }
+        );
+        assert_eq!(&buf[len..len + 3], b"GET");
+    }
+
+    #[test]
+    fn test_parse_v1_incomplete_and_invalid() {
+        assert_eq!(parse_header(b"PROXY TCP6 2001:db8::1").unwrap(), None);
+        assert_eq!(parse_header(b"PRO").unwrap(), None);
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+        assert!(parse_header(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n").is_err());
+        assert_eq!(
+            parse_header(b"PROXY UNKNOWN\r\n").unwrap(),
+            Some((ProxyHeader::Local, 15))
+        );
+    }
+
+    #[test]
+    fn test_parse_v2_ipv6_and_local() {
+        let mut buf = V2_SIGNATURE.to_vec();
+        buf.extend_from_slice(&[0x21, 0x21, 0x00, 36]);
+        buf.extend_from_slice(&"2001:db8::1".parse::<Ipv6Addr>().unwrap().octets());
+        buf.extend_from_slice(&"2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        buf.extend_from_slice(&1234u16.to_be_bytes());
+        buf.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(parse_header(&buf[..20]).unwrap(), None);
+        let (header, len) = parse_header(&buf).unwrap().unwrap();
+        assert_eq!(len, 52);
+        assert_eq!(
+            header,
+            ProxyHeader::Proxied {
+                source: "[2001:db8::1]:1234".parse().unwrap(),
+                destination: "[2001:db8::2]:443".parse().unwrap(),
+            }
+        );
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        assert_eq!(parse_header(&local).unwrap(), Some((ProxyHeader::Local, 16)));
+    }
+
+    #[test]
+    fn test_forwarded_only_from_trusted_proxies() {
+        let trusted = parse_cidr_list("10.0.0.0/8, ::1").unwrap();
+        let request = "GET /rmbt HTTP/1.1\r\nUpgrade: websocket\r\n\
+                       X-Forwarded-For: 203.0.113.9, 198.51.100.7, 10.1.2.3\r\n\r\n";
+
+        let client = forwarded_client_addr(request, "10.0.0.1:80".parse().unwrap(), &trusted);
+        assert_eq!(client, Some("198.51.100.7:0".parse().unwrap()));
+
+        let untrusted = forwarded_client_addr(request, "192.0.2.1:80".parse().unwrap(), &trusted);
+        assert_eq!(untrusted, None);
+
+        let request = "GET / HTTP/1.1\r\nForwarded: for=\"[2001:db8::5]:4711\";proto=https\r\n\r\n";
+        let client = forwarded_client_addr(request, "[::1]:80".parse().unwrap(), &trusted);
+        assert_eq!(client, Some("[2001:db8::5]:4711".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_contains() {
+        let net = Cidr::parse("192.168.0.0/16").unwrap();
+        assert!(net.contains(&"192.168.4.2".parse().unwrap()));
+        assert!(net.contains(&"::ffff:192.168.4.2".parse().unwrap()));
+        assert!(!net.contains(&"192.169.0.1".parse().unwrap()));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&"8.8.8.8".parse().unwrap()));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+    }
+}
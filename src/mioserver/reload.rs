@@ -0,0 +1,181 @@
+//! Configuration reload on SIGHUP. Settings that are read per connection are
+//! replaced in place, everything bound or started once at start-up is only
+//! reported as needing a restart.
+
+use crate::mioserver::server::ServerConfig;
+
+/// Outcome of merging a re-read configuration into the running one
+pub struct Reload {
+    pub config: ServerConfig,
+    pub applied: Vec<&'static str>,
+    pub needs_restart: Vec<&'static str>,
+    /// The registration token or signing key changed, the server has to
+    /// register again
+    pub reregister: bool,
+}
+
+/// Takes the reloadable settings from `reloaded` and keeps the running values
+/// for everything else. `reloaded.tls_config` is expected to be loaded already,
+/// `None` while TLS is configured means loading failed.
+pub fn merge(current: &ServerConfig, reloaded: ServerConfig, socket_activated: bool) -> Reload {
+    let mut config = current.clone();
+    let mut applied = Vec::new();
+    let mut needs_restart = Vec::new();
+
+    if reloaded.log_level != current.log_level {
+        config.log_level = reloaded.log_level;
+        applied.push("log level");
+    }
+    if reloaded.proxy_protocol != current.proxy_protocol {
+        config.proxy_protocol = reloaded.proxy_protocol;
+        applied.push("proxy_protocol");
+    }
+    if reloaded.trusted_proxies != current.trusted_proxies {
+        config.trusted_proxies = reloaded.trusted_proxies.clone();
+        applied.push("trusted_proxies");
+    }
+    if reloaded.max_chunk_size != current.max_chunk_size {
+        config.max_chunk_size = reloaded.max_chunk_size;
+        applied.push("max_chunk_size");
+    }
+    if reloaded.max_transfer_secs != current.max_transfer_secs {
+        config.max_transfer_secs = reloaded.max_transfer_secs;
+        applied.push("max_transfer_secs");
+    }
+    let mut reregister = false;
+    if reloaded.registration_token != current.registration_token {
+        config.registration_token = reloaded.registration_token.clone();
+        applied.push("registration_token");
+        reregister = true;
+    }
+    // A generated key is new on every parse, only a configured one replaces it
+    if (reloaded.secret_key_configured || current.secret_key_configured)
+        && reloaded.secret_key != current.secret_key
+    {
+        config.secret_key = reloaded.secret_key.clone();
+        config.secret_key_configured = reloaded.secret_key_configured;
+        applied.push("signing_key");
+        reregister = true;
+    }
+
+    if reloaded.self_signed != current.self_signed {
+        needs_restart.push("self_signed");
+    } else if current.tls_config.is_none() {
+        // No TLS listener was bound at start-up
+        if reloaded.tls_config.is_some() {
+            needs_restart.push("TLS certificate (TLS was disabled at start-up)");
+        }
+    } else if reloaded.tls_config.is_some() {
+        // Always taken over, renewed certificates usually keep their path
+        config.cert_path = reloaded.cert_path.clone();
+        config.key_path = reloaded.key_path.clone();
+        config.tls_config = reloaded.tls_config.clone();
+        config.tls_fingerprint = reloaded.tls_fingerprint.clone();
+        applied.push("TLS certificate");
+    }
+
+    // Socket activation replaces the configured addresses with the inherited ones
+    if !socket_activated
+        && (reloaded.tcp_addresses != current.tcp_addresses
+            || reloaded.tls_addresses != current.tls_addresses)
+    {
+        needs_restart.push("listen addresses");
+    }
+    if reloaded.num_workers != current.num_workers {
+        needs_restart.push("server_workers");
+    }
+    if reloaded.user != current.user || reloaded.group != current.group {
+        needs_restart.push("user/group");
+    }
+    if reloaded.daemon != current.daemon || reloaded.pid_file != current.pid_file {
+        needs_restart.push("daemonize/pid_file");
+    }
+    if reloaded.log_options != current.log_options {
+        needs_restart.push("log destination");
+    }
+    if reloaded.enable_mdns != current.enable_mdns {
+        needs_restart.push("mDNS");
+    }
+    if reloaded.server_registration != current.server_registration
+        || reloaded.control_server != current.control_server
+        || reloaded.hostname != current.hostname
+        || reloaded.server_name != current.server_name
+    {
+        needs_restart.push("registration settings");
+    }
+
+    Reload {
+        config,
+        applied,
+        needs_restart,
+        reregister,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::FileConfig;
+    use crate::mioserver::parser::parse_args;
+    use log::LevelFilter;
+
+    fn config(args: &[&str]) -> ServerConfig {
+        let args = args.iter().map(|arg| arg.to_string()).collect();
+        parse_args(args, FileConfig::default()).unwrap()
+    }
+
+    fn reload() -> (ServerConfig, Reload) {
+        let current = config(&["-s"]);
+        let mut reloaded = config(&["-s", "-l", "6000", "-t", "4", "-trusted-proxy", "10.0.0.0/8"]);
+        reloaded.log_level = Some(LevelFilter::Warn);
+        let reload = merge(&current, reloaded, false);
+        (current, reload)
+    }
+
+    #[test]
+    fn test_applies_reloadable_settings() {
+        let (_, reload) = reload();
+        assert_eq!(reload.config.log_level, Some(LevelFilter::Warn));
+        assert_eq!(reload.config.trusted_proxies.len(), 1);
+        assert_eq!(reload.applied, vec!["log level", "trusted_proxies"]);
+        assert!(!reload.reregister);
+    }
+
+    #[test]
+    fn test_keeps_settings_that_need_a_restart() {
+        let (current, reload) = reload();
+        assert_eq!(reload.needs_restart, vec!["listen addresses", "server_workers"]);
+        assert_eq!(reload.config.tcp_addresses, current.tcp_addresses);
+        assert_eq!(reload.config.num_workers, current.num_workers);
+        // The generated key registered with the control server must not change
+        assert_eq!(reload.config.secret_key, current.secret_key);
+    }
+
+    #[test]
+    fn test_applies_configured_signing_key() {
+        let current = config(&["-s"]);
+        let mut reloaded = config(&["-s"]);
+        reloaded.secret_key = "c2lnbmluZyBrZXk=".to_string();
+        reloaded.secret_key_configured = true;
+        reloaded.max_transfer_secs = 600;
+        reloaded.max_chunk_size = Some(65536);
+
+        let reload = merge(&current, reloaded, false);
+        assert_eq!(reload.config.secret_key, "c2lnbmluZyBrZXk=");
+        assert!(reload.config.secret_key_configured);
+        assert_eq!(reload.config.max_transfer_secs, 600);
+        assert_eq!(reload.config.chunk_size_limit(), 65536);
+        assert_eq!(
+            reload.applied,
+            vec!["max_chunk_size", "max_transfer_secs", "signing_key"]
+        );
+        assert!(reload.reregister);
+
+        // Removing the key from the config generates a new one
+        let reloaded = config(&["-s"]);
+        let removed = merge(&reload.config, reloaded.clone(), false);
+        assert_eq!(removed.config.secret_key, reloaded.secret_key);
+        assert!(!removed.config.secret_key_configured);
+        assert!(removed.reregister);
+    }
+}
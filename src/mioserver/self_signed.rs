@@ -0,0 +1,259 @@
+use anyhow::{Context, Result};
+use log::{debug, info};
+use openssl::asn1::{Asn1Integer, Asn1Time};
+use openssl::bn::{BigNum, MsbOption};
+use openssl::ec::{EcGroup, EcKey};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::PKey;
+use openssl::x509::extension::{
+    BasicConstraints, ExtendedKeyUsage, KeyUsage, SubjectAlternativeName,
+};
+use openssl::x509::{X509NameBuilder, X509};
+use std::fs;
+use std::net::IpAddr;
+use std::path::Path;
+
+/// Validity of a generated certificate. Long enough for lab setups,
+/// regenerate by deleting the persisted files.
+const CERT_VALIDITY_DAYS: u32 = 825;
+
+/// PEM encoded certificate and key created (or reloaded) for `-selfsigned` mode
+#[derive(Debug, Clone)]
+pub struct SelfSignedCertificate {
+    pub cert_pem: Vec<u8>,
+    pub key_pem: Vec<u8>,
+    pub fingerprint: String,
+    pub names: Vec<String>,
+}
+
+/// Returns the certificate stored at `cert_path`/`key_path` when both files exist,
+/// otherwise generates a new one and writes it there so the fingerprint stays stable
+/// across restarts.
+pub fn load_or_generate(
+    cert_path: &str,
+    key_path: &str,
+    hostname: Option<&str>,
+) -> Result<SelfSignedCertificate> {
+    if Path::new(cert_path).exists() && Path::new(key_path).exists() {
+        let cert_pem = fs::read(cert_path)
+            .with_context(|| format!("Failed to read certificate {}", cert_path))?;
+        let key_pem =
+            fs::read(key_path).with_context(|| format!("Failed to read key {}", key_path))?;
+        let cert = X509::from_pem(&cert_pem)?;
+        info!("Reusing self-signed certificate from {}", cert_path);
+        return Ok(SelfSignedCertificate {
+            fingerprint: fingerprint_sha256(&cert)?,
+            names: vec![],
+            cert_pem,
+            key_pem,
+        });
+    }
+
+    let generated = generate(hostname)?;
+    write_file(cert_path, &generated.cert_pem, 0o644)?;
+    write_file(key_path, &generated.key_pem, 0o600)?;
+    info!(
+        "Stored self-signed certificate in {} and key in {}",
+        cert_path, key_path
+    );
+    Ok(generated)
+}
+
+/// Generates an ECDSA P-256 key and a certificate valid for `localhost`,
+/// the configured and system hostname and every local interface address.
+pub fn generate(hostname: Option<&str>) -> Result<SelfSignedCertificate> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut dns_names = vec!["localhost".to_string()];
+    for name in hostname
+        .map(|h| h.to_string())
+        .into_iter()
+        .chain(system_hostname())
+    {
+        if !name.is_empty() && !dns_names.contains(&name) {
+            dns_names.push(name);
+        }
+    }
+    let mut ips: Vec<IpAddr> = vec!["127.0.0.1".parse()?, "::1".parse()?];
+    for ip in local_ip_addresses() {
+        if !ips.contains(&ip) {
+            ips.push(ip);
+        }
+    }
+
+    let common_name = hostname.unwrap_or("nettest");
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, common_name)?;
+    name.append_entry_by_nid(Nid::ORGANIZATIONNAME, "nettest self-signed")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(Asn1Integer::from_bn(&serial)?.as_ref())?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(Asn1Time::days_from_now(0)?.as_ref())?;
+    builder.set_not_after(Asn1Time::days_from_now(CERT_VALIDITY_DAYS)?.as_ref())?;
+    builder.append_extension(BasicConstraints::new().critical().build()?)?;
+    builder.append_extension(
+        KeyUsage::new()
+            .critical()
+            .digital_signature()
+            .key_encipherment()
+            .build()?,
+    )?;
+    builder.append_extension(ExtendedKeyUsage::new().server_auth().build()?)?;
+
+    let mut san = SubjectAlternativeName::new();
+    for dns in &dns_names {
+        san.dns(dns);
+    }
+    for ip in &ips {
+        san.ip(&ip.to_string());
+    }
+    let san = san.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+    let cert = builder.build();
+
+    let mut names = dns_names;
+    names.extend(ips.iter().map(|ip| ip.to_string()));
+    debug!("Generated self-signed certificate for {:?}", names);
+
+    Ok(SelfSignedCertificate {
+        cert_pem: cert.to_pem()?,
+        key_pem: key.private_key_to_pem_pkcs8()?,
+        fingerprint: fingerprint_sha256(&cert)?,
+        names,
+    })
+}
+
+/// SHA-256 digest of the DER certificate as colon separated upper-case hex,
+/// the format printed by `openssl x509 -fingerprint -sha256`.
+pub fn fingerprint_sha256(cert: &X509) -> Result<String> {
+    let digest = cert.digest(MessageDigest::sha256())?;
+    Ok(digest
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+fn write_file(path: &str, content: &[u8], mode: u32) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    fs::write(path, content).with_context(|| format!("Failed to write {}", path))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn system_hostname() -> Option<String> {
+    nix::unistd::gethostname()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+}
+
+#[cfg(not(unix))]
+fn system_hostname() -> Option<String> {
+    std::env::var("COMPUTERNAME").ok()
+}
+
+/// Addresses of all local interfaces except loopback and link-local ones
+#[cfg(unix)]
+fn local_ip_addresses() -> Vec<IpAddr> {
+    let mut result = Vec::new();
+    let addrs = match nix::ifaddrs::getifaddrs() {
+        Ok(addrs) => addrs,
+        Err(e) => {
+            debug!("Failed to list interface addresses: {}", e);
+            return result;
+        }
+    };
+    for ifaddr in addrs {
+        let Some(address) = ifaddr.address else {
+            continue;
+        };
+        let ip = if let Some(v4) = address.as_sockaddr_in() {
+            IpAddr::V4(std::net::Ipv4Addr::from(v4.ip()))
+        } else if let Some(v6) = address.as_sockaddr_in6() {
+            IpAddr::V6(v6.ip())
+        } else {
+            continue;
+        };
+        let link_local = match ip {
+            IpAddr::V4(v4) => v4.is_link_local(),
+            IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+        };
+        if !ip.is_loopback() && !link_local && !result.contains(&ip) {
+            result.push(ip);
+        }
+    }
+    result
+}
+
+#[cfg(not(unix))]
+fn local_ip_addresses() -> Vec<IpAddr> {
+    crate::mioserver::control_server::mdns::get_local_network_ip()
+        .and_then(|ip| ip.parse().ok())
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generated_certificate_covers_localhost() {
+        let generated = generate(Some("nettest.example")).unwrap();
+        let cert = X509::from_pem(&generated.cert_pem).unwrap();
+        let sans: Vec<String> = cert
+            .subject_alt_names()
+            .unwrap()
+            .iter()
+            .filter_map(|name| name.dnsname().map(|s| s.to_string()))
+            .collect();
+        assert!(sans.contains(&"localhost".to_string()));
+        assert!(sans.contains(&"nettest.example".to_string()));
+        assert_eq!(generated.fingerprint, fingerprint_sha256(&cert).unwrap());
+        assert_eq!(generated.fingerprint.len(), 32 * 3 - 1);
+    }
+
+    #[test]
+    fn test_persisted_certificate_is_reused() {
+        let dir = std::env::temp_dir().join(format!("nettest-selfsigned-{}", std::process::id()));
+        let cert_path = dir.join("cert.pem");
+        let key_path = dir.join("key.pem");
+        let first = load_or_generate(
+            cert_path.to_str().unwrap(),
+            key_path.to_str().unwrap(),
+            None,
+        )
+        .unwrap();
+        let second = load_or_generate(
+            cert_path.to_str().unwrap(),
+            key_path.to_str().unwrap(),
+            None,
+        )
+        .unwrap();
+        assert_eq!(first.fingerprint, second.fingerprint);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -2,22 +2,29 @@ use crate::mioserver::control_server::auto_registration::{
     deregister_server, register_server, start_ping_job,
 };
 use crate::mioserver::control_server::mdns::start_mdns_service;
+use crate::mioserver::proxy_protocol::Cidr;
+use crate::mioserver::self_signed;
+use crate::mioserver::systemd::{self, Heartbeats, ListenFd};
+use crate::tokio_server::utils::daemon::daemonize;
+use crate::tokio_server::utils::user::UserPrivileges;
+use crate::stream::rustls_server::{load_server_config, server_config_from_pem};
 use bytes::BytesMut;
-use log::{debug, info, LevelFilter};
+use log::{debug, info, warn, LevelFilter};
 use mio::net::{TcpListener, TcpStream};
 use mio::Token;
 use std::collections::VecDeque;
 use std::io::{self, Read};
 use std::net::SocketAddr;
-use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 #[cfg(unix)]
 use libc;
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    Arc, Mutex,
+    Arc, Mutex, RwLock,
 };
 use std::thread;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
 #[derive(Debug)]
 pub enum ConnectionType {
@@ -25,24 +32,50 @@ pub enum ConnectionType {
     Tls(TcpStream, SocketAddr), // Same TcpStream but with TLS flag
 }
 
+use crate::config::constants::{MAX_CHUNK_SIZE, MIN_CHUNK_SIZE};
+use crate::config::parser::read_config_file;
 use crate::config::FileConfig;
+use crate::logger::{self, ConnectionContext, LogOptions};
+use crate::mioserver::builder::ServerBuilder;
+use crate::mioserver::handlers::signed_result::generate_secret_key;
+use crate::mioserver::handlers::timeout_utils::MAX_TRANSFER_SECS;
+use crate::mioserver::parser::{parse_args, parse_reload_args};
+use crate::mioserver::reload;
 use crate::mioserver::worker::WorkerThread;
 use crate::mioserver::ServerTestPhase;
 use crate::stream::stream::Stream;
 
+pub(crate) const DEFAULT_PID_FILE: &str = "/run/nettest/nettest.pid";
+
 pub struct MioServer {
     tcp_listeners: Vec<TcpListener>,
     tls_listeners: Vec<TcpListener>,
     static_files_listener: Option<TcpListener>,
-    _worker_threads: Vec<WorkerThread>,
+    worker_threads: Vec<WorkerThread>,
+    worker_connection_counts: Arc<Mutex<Vec<usize>>>,
+    heartbeats: Arc<Heartbeats>,
     global_queue: Arc<Mutex<VecDeque<(ConnectionType, Instant)>>>, // Global queue with timestamps
     server_config: ServerConfig,
+    /// Configuration the workers pick up for every new connection, replaced on reload
+    live_config: Arc<RwLock<ServerConfig>>,
+    /// Server arguments and config file, re-read on SIGHUP
+    args: Vec<String>,
+    config_source: Option<PathBuf>,
+    socket_activated: bool,
+    /// Written by `daemonize_and_drop_privileges`, removed again on shutdown
+    pid_file: Option<PathBuf>,
     shutdown_signal: Arc<AtomicBool>,
+    reload_signal: Arc<AtomicBool>,
 }
 
 pub struct TestState {
     pub token: Token,
+    /// Process-wide ID shown in the log lines of this connection
+    pub connection_id: u64,
     pub connection_start: Instant,
+    /// How long the connection may stay open, extended by downloads and
+    /// uploads up to `timeout_utils::max_connection_secs`
+    pub time_limit: Duration,
     pub stream: Stream,
     pub measurement_state: ServerTestPhase,
     pub read_buffer: [u8; 1024 * 8],
@@ -67,6 +100,20 @@ pub struct TestState {
     pub bytes_received: VecDeque<(u64, u64)>,
     pub client_addr: Option<SocketAddr>,
     pub sig_key: Option<String>,
+    /// Chunk size limit announced in the greeting and transfer time limit of
+    /// the configuration the connection started with
+    pub max_chunk_size: usize,
+    pub max_transfer_secs: u64,
+}
+
+impl TestState {
+    pub fn log_context(&self) -> ConnectionContext {
+        ConnectionContext {
+            id: self.connection_id,
+            client_ip: self.client_addr.map(|addr| addr.ip()),
+            phase: self.measurement_state,
+        }
+    }
 }
 
 #[derive(Clone)]
@@ -77,9 +124,14 @@ pub struct ServerConfig {
     pub key_path: Option<String>,
     pub num_workers: Option<usize>,
     pub user: Option<String>,
+    pub group: Option<String>,
     pub daemon: bool,
+    pub pid_file: Option<String>,
     pub version: Option<String>,
+    /// Base64 key signed results are signed with
     pub secret_key: String,
+    /// `secret_key` is the configured `signing_key` instead of a generated one
+    pub secret_key_configured: bool,
     pub log_level: Option<LevelFilter>,
     pub server_registration: bool,
     pub control_server: String,
@@ -88,17 +140,178 @@ pub struct ServerConfig {
     pub registration_token: Option<String>,
     pub server_name: Option<String>,
     pub enable_mdns: bool,
+    pub self_signed: bool,
+    /// rustls configuration shared by all workers, built once at start-up
+    pub tls_config: Option<Arc<rustls::ServerConfig>>,
+    /// SHA-256 fingerprint of the generated certificate in `-selfsigned` mode
+    pub tls_fingerprint: Option<String>,
+    /// Expect a PROXY protocol v1/v2 header on TCP and TLS connections
+    pub proxy_protocol: bool,
+    /// Proxies allowed to send PROXY headers and `Forwarded`/`X-Forwarded-For`
+    pub trusted_proxies: Vec<Cidr>,
+    /// `--check-config`: validate and report instead of starting the server
+    pub check_config: bool,
+    pub max_chunk_size: Option<u32>,
+    /// Longest download or upload in seconds a connection gets time for
+    pub max_transfer_secs: u64,
+    pub log_options: LogOptions,
+    /// Use the listening sockets passed by systemd (`LISTEN_FDS`) instead of
+    /// binding the listen addresses
+    pub socket_activation: bool,
+}
+
+impl ServerConfig {
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_config.is_some()
+    }
+
+    /// Largest chunk size clients may request, `max_chunk_size` within the
+    /// protocol limits
+    pub fn chunk_size_limit(&self) -> usize {
+        self.max_chunk_size
+            .map_or(MAX_CHUNK_SIZE, |size| size as usize)
+            .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+    }
+}
+
+impl Default for ServerConfig {
+    /// Plain TCP on port 5005 (IPv6 and IPv4), no TLS, registration or mDNS and
+    /// the logger left alone
+    fn default() -> Self {
+        let defaults = FileConfig::default();
+        Self {
+            tcp_addresses: vec![
+                SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), defaults.server_tcp_port),
+                SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), defaults.server_tcp_port),
+            ],
+            tls_addresses: vec![],
+            cert_path: None,
+            key_path: None,
+            num_workers: None,
+            user: None,
+            group: None,
+            daemon: false,
+            pid_file: None,
+            version: Some("2.0.0".to_string()),
+            secret_key: generate_secret_key(),
+            secret_key_configured: false,
+            log_level: None,
+            server_registration: false,
+            control_server: defaults.control_server,
+            hostname: None,
+            x_nettest_client: defaults.x_nettest_client,
+            registration_token: None,
+            server_name: None,
+            enable_mdns: false,
+            self_signed: false,
+            tls_config: None,
+            tls_fingerprint: None,
+            proxy_protocol: false,
+            trusted_proxies: vec![],
+            check_config: false,
+            max_chunk_size: None,
+            max_transfer_secs: MAX_TRANSFER_SECS,
+            log_options: LogOptions::default(),
+            socket_activation: false,
+        }
+    }
+}
+
+/// Stops a running server from another thread or task
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    signal: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    /// Makes `run()` return after the current accept loop iteration. Tests in
+    /// progress are dropped.
+    pub fn shutdown(&self) {
+        self.signal.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.signal.load(Ordering::Relaxed)
+    }
 }
 
 impl MioServer {
+    /// Server configured in code, see [`ServerBuilder`] for the common settings
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder::new()
+    }
+
+    /// Server configured from the command line arguments and the config file,
+    /// as started by `nettest -s`: the logger is initialized and the TLS setup
+    /// is printed
     pub fn new(args: Vec<String>, config: FileConfig) -> io::Result<Self> {
-        let server_config = crate::mioserver::parser::parse_args(args, config.clone())
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let config_source = config.source.clone();
+        let mut server_config = parse_args(args.clone(), config).map_err(io::Error::other)?;
+        // Once per process, a reload only changes the level
+        if let Some(level) = server_config.log_level.filter(|level| *level != LevelFilter::Off) {
+            println!("Initializing logger with level: {:?}", level);
+            logger::set_log_options(server_config.log_options.clone());
+            logger::init_logger(level)
+                .map_err(|e| io::Error::other(format!("Failed to initialize logger: {}", e)))?;
+        }
+        if server_config.self_signed {
+            let names = Self::load_tls_config(&mut server_config)?;
+            if !names.is_empty() {
+                println!("Self-signed certificate valid for: {}", names.join(", "));
+            }
+            if let Some(fingerprint) = &server_config.tls_fingerprint {
+                println!("TLS certificate SHA-256 fingerprint: {}", fingerprint);
+            }
+        } else if let Err(e) = Self::load_tls_config(&mut server_config) {
+            println!("Failed to load TLS certificate or key, skipping TLS listener: {}", e);
+        }
+        if !server_config.tls_enabled() && !server_config.tls_addresses.is_empty() {
+            println!("Key and certificate files are not provided, skipping TLS listener");
+        }
+
+        let mut server = Self::bind(server_config)?;
+        server.args = args;
+        server.config_source = config_source;
+        Ok(server)
+    }
 
+    /// Server configured in code. Unlike `new` nothing is printed, the logger is
+    /// not touched and a certificate that cannot be loaded is an error instead of
+    /// disabling TLS. A `tls_config` set by the caller is used as is.
+    pub fn from_config(mut server_config: ServerConfig) -> io::Result<Self> {
+        if !server_config.tls_enabled() {
+            Self::load_tls_config(&mut server_config)?;
+        }
+        Self::bind(server_config)
+    }
+
+    fn bind(mut server_config: ServerConfig) -> io::Result<Self> {
         let mut tcp_listeners = Vec::new();
         let mut tls_listeners = Vec::new();
 
-        for addr in &server_config.tcp_addresses {
+        // With socket activation systemd owns the addresses, nothing else is bound
+        let inherited = if server_config.socket_activation {
+            systemd::listen_fds()
+        } else {
+            vec![]
+        };
+        let socket_activated = !inherited.is_empty();
+        let (tcp_addresses, tls_addresses) = if inherited.is_empty() {
+            (
+                server_config.tcp_addresses.clone(),
+                server_config.tls_addresses.clone(),
+            )
+        } else {
+            Self::adopt_inherited_listeners(
+                inherited,
+                &mut server_config,
+                &mut tcp_listeners,
+                &mut tls_listeners,
+            )?;
+            (vec![], vec![])
+        };
+
+        for addr in &tcp_addresses {
             match if addr.is_ipv6() {
                 Self::bind_ipv6_with_v6only(*addr)
             } else {
@@ -114,8 +327,8 @@ impl MioServer {
             };
         }
 
-        for addr in &server_config.tls_addresses {
-            if server_config.cert_path.is_some() && server_config.key_path.is_some() {
+        for addr in &tls_addresses {
+            if server_config.tls_enabled() {
                 match if addr.is_ipv6() {
                     Self::bind_ipv6_with_v6only(*addr)
                 } else {
@@ -130,10 +343,9 @@ impl MioServer {
                     }
                 }
             } else {
-                println!("Key and certificate files are not provided, skipping TLS listener");
+                info!("No TLS certificate, skipping TLS listener {}", addr);
             }
         }
-
         let static_files_listener = if server_config.enable_mdns {
             info!("Static files server listening on {}", 5006);
             match TcpListener::bind(SocketAddr::from((IpAddr::V4(Ipv4Addr::UNSPECIFIED), 5006))) {
@@ -151,31 +363,206 @@ impl MioServer {
 
         let worker_connection_counts = Arc::new(Mutex::new(vec![0; logical]));
         let global_queue = Arc::new(Mutex::new(VecDeque::new()));
-
-        let mut worker_threads = Vec::new();
-
-        for i in 0..logical {
-            let worker = WorkerThread::new(
-                i,
-                worker_connection_counts.clone(),
-                global_queue.clone(),
-                server_config.clone(),
-            )?;
-            worker_threads.push(worker);
-        }
+        // One slot per worker plus one for the accept loop
+        let heartbeats = Arc::new(Heartbeats::new(logical + 1));
 
         Ok(Self {
             tcp_listeners,
             tls_listeners,
             static_files_listener,
-            _worker_threads: worker_threads,
+            worker_threads: Vec::new(),
+            worker_connection_counts,
+            heartbeats,
             global_queue,
+            live_config: Arc::new(RwLock::new(server_config.clone())),
             server_config,
+            args: Vec::new(),
+            config_source: None,
+            socket_activated,
+            pid_file: None,
             shutdown_signal: Arc::new(AtomicBool::new(false)),
+            reload_signal: Arc::new(AtomicBool::new(false)),
         })
     }
 
+    /// Builds the shared rustls configuration from the configured PEM files or,
+    /// in `-selfsigned` mode, from a generated certificate. Returns the names a
+    /// generated certificate is valid for.
+    fn load_tls_config(server_config: &mut ServerConfig) -> io::Result<Vec<String>> {
+        if server_config.self_signed {
+            let generated = match (&server_config.cert_path, &server_config.key_path) {
+                (Some(cert_path), Some(key_path)) => self_signed::load_or_generate(
+                    cert_path,
+                    key_path,
+                    server_config.hostname.as_deref(),
+                ),
+                _ => self_signed::generate(server_config.hostname.as_deref()),
+            }
+            .map_err(io::Error::other)?;
+
+            let tls_config = server_config_from_pem(&generated.cert_pem, &generated.key_pem)
+                .map_err(io::Error::other)?;
+            info!("TLS certificate SHA-256 fingerprint: {}", generated.fingerprint);
+            server_config.tls_config = Some(tls_config);
+            server_config.tls_fingerprint = Some(generated.fingerprint);
+            return Ok(generated.names);
+        } else if let (Some(cert_path), Some(key_path)) =
+            (&server_config.cert_path, &server_config.key_path)
+        {
+            let tls_config = load_server_config(Path::new(cert_path), Path::new(key_path))
+                .map_err(io::Error::other)?;
+            server_config.tls_config = Some(tls_config);
+        }
+        Ok(vec![])
+    }
+
+    /// Forks into the background (`-d`), writes the PID file and switches to the
+    /// configured user and group (`-u`).
+    ///
+    /// Must be called after the listeners are bound and the certificates are loaded,
+    /// but before `run()`: fork() keeps only the calling thread, so the workers, the
+    /// mDNS daemon and the tokio runtime are started afterwards.
+    pub fn daemonize_and_drop_privileges(&mut self) -> io::Result<()> {
+        if self.server_config.daemon && std::env::var_os("NOTIFY_SOCKET").is_some() {
+            info!("Started by systemd, ignoring daemonize option");
+        } else if self.server_config.daemon {
+            info!("Daemonizing server...");
+            daemonize().map_err(io::Error::other)?;
+        }
+
+        let pid_file = self.server_config.pid_file.clone().or_else(|| {
+            self.server_config
+                .daemon
+                .then(|| DEFAULT_PID_FILE.to_string())
+        });
+        let privileges = match self.server_config.user {
+            Some(ref user) => {
+                let mut privileges = UserPrivileges::new(user)?;
+                if let Some(ref group) = self.server_config.group {
+                    privileges = privileges.with_group(group)?;
+                }
+                Some(privileges)
+            }
+            None => None,
+        };
+
+        // The default directory belongs to the service user, so the PID file
+        // written below can be removed again on shutdown
+        if pid_file.as_deref() == Some(DEFAULT_PID_FILE) {
+            let directory = Path::new(DEFAULT_PID_FILE).parent().unwrap();
+            prepare_directory(directory, privileges.as_ref())?;
+        }
+
+        // Rotating renames files next to the log file, and SIGHUP reopens it by path
+        let logging = self.server_config.log_level.is_some_and(|level| level != LevelFilter::Off);
+        let log_file = logger::log_file_path(&self.server_config.log_options).filter(|_| logging);
+        if let (Some(privileges), Some(log_file)) = (&privileges, log_file) {
+            // Only the default directory is nettest's own, others are left to the operator
+            if self.server_config.log_options.path.is_none() {
+                prepare_directory(log_file.parent().unwrap(), Some(privileges))?;
+            }
+            if log_file.exists() {
+                privileges.chown(&log_file)?;
+            }
+        }
+
+        if let (Some(privileges), Some(user)) = (&privileges, &self.server_config.user) {
+            privileges.drop_privileges()?;
+            info!("Dropped privileges to user {}", user);
+        }
+
+        if let Some(pid_file) = pid_file {
+            std::fs::write(&pid_file, format!("{}\n", std::process::id())).map_err(|e| {
+                io::Error::new(e.kind(), format!("Could not write PID file {}: {}", pid_file, e))
+            })?;
+            info!("PID file location: {}", pid_file);
+            self.pid_file = Some(PathBuf::from(pid_file));
+        }
+        Ok(())
+    }
+
+    /// Uses sockets passed by systemd socket activation instead of binding.
+    /// Sockets named `tls` in the socket unit (`FileDescriptorName=tls`) or bound to
+    /// a configured TLS port serve TLS, all others plain TCP.
+    fn adopt_inherited_listeners(
+        inherited: Vec<ListenFd>,
+        server_config: &mut ServerConfig,
+        tcp_listeners: &mut Vec<TcpListener>,
+        tls_listeners: &mut Vec<TcpListener>,
+    ) -> io::Result<()> {
+        let tls_ports: Vec<u16> = server_config.tls_addresses.iter().map(|a| a.port()).collect();
+        let mut tcp_addresses = Vec::new();
+        let mut tls_addresses = Vec::new();
+
+        for fd in inherited {
+            fd.listener.set_nonblocking(true)?;
+            let addr = fd.listener.local_addr()?;
+            let is_tls = match fd.name.as_deref() {
+                Some(name) => name.eq_ignore_ascii_case("tls"),
+                None => tls_ports.contains(&addr.port()),
+            };
+            if is_tls && !server_config.tls_enabled() {
+                warn!("No TLS certificate, ignoring inherited TLS socket {}", addr);
+                continue;
+            }
+            info!(
+                "{} Server listening on {} (socket activation)",
+                if is_tls { "TLS" } else { "TCP" },
+                addr
+            );
+            if is_tls {
+                tls_listeners.push(TcpListener::from_std(fd.listener));
+                tls_addresses.push(addr);
+            } else {
+                tcp_listeners.push(TcpListener::from_std(fd.listener));
+                tcp_addresses.push(addr);
+            }
+        }
+
+        // mDNS and registration announce the ports actually in use
+        if !tcp_addresses.is_empty() {
+            server_config.tcp_addresses = tcp_addresses;
+        }
+        if !tls_addresses.is_empty() {
+            server_config.tls_addresses = tls_addresses;
+        }
+        Ok(())
+    }
+
+    fn start_workers(&mut self) -> io::Result<()> {
+        let logical = self.worker_connection_counts.lock().unwrap().len();
+        for i in 0..logical {
+            let worker = WorkerThread::new(
+                i,
+                self.worker_connection_counts.clone(),
+                self.global_queue.clone(),
+                self.live_config.clone(),
+                self.heartbeats.clone(),
+                self.shutdown_signal.clone(),
+            )?;
+            self.worker_threads.push(worker);
+        }
+        Ok(())
+    }
+
+    /// Accepts connections until shutdown is requested. Registration and mDNS
+    /// are started on the tokio runtime of the calling thread.
     pub fn run(&mut self) -> io::Result<()> {
+        if (self.server_config.server_registration || self.server_config.enable_mdns)
+            && tokio::runtime::Handle::try_current().is_err()
+        {
+            return Err(io::Error::other(
+                "server registration and mDNS need to run inside a tokio runtime",
+            ));
+        }
+        self.start_workers()?;
+        if let Some(interval) = systemd::watchdog_interval() {
+            systemd::start_watchdog(
+                interval,
+                self.heartbeats.clone(),
+                self.shutdown_signal.clone(),
+            );
+        }
         info!(
             "server_config.server_registration: {:?}",
             self.server_config.server_registration
@@ -189,13 +576,17 @@ impl MioServer {
                 match register_server(&config_clone).await {
                     Ok(_) => {
                         info!("Server registration successful, starting ping job...");
+                        systemd::notify("READY=1\nSTATUS=Accepting connections, registered");
                         start_ping_job(config_clone, shutdown_signal).await;
                     }
                     Err(e) => {
                         info!("Server registration failed: {}", e);
+                        systemd::notify("READY=1\nSTATUS=Accepting connections, registration failed");
                     }
                 }
             });
+        } else {
+            systemd::notify("READY=1\nSTATUS=Accepting connections");
         }
 
         if self.server_config.enable_mdns {
@@ -211,12 +602,18 @@ impl MioServer {
             debug!("mDNS service disabled (use -mdns flag to enable)");
         }
 
+        let accept_loop_index = self.worker_threads.len();
         loop {
             // Check shutdown signal
             if self.shutdown_signal.load(Ordering::Relaxed) {
                 info!("Shutdown signal received, stopping server...");
+                systemd::notify("STOPPING=1");
                 break;
             }
+            self.heartbeats.beat(accept_loop_index);
+            if self.reload_signal.swap(false, Ordering::Relaxed) {
+                self.reload_config();
+            }
 
             // Accept TCP connections
             let mut tcp_connections = Vec::new();
@@ -313,6 +710,9 @@ impl MioServer {
             thread::sleep(std::time::Duration::from_millis(10));
         }
 
+        for worker in self.worker_threads.drain(..) {
+            worker.join();
+        }
         Ok(())
     }
 
@@ -323,6 +723,11 @@ impl MioServer {
             info!("Deregistering server from control server...");
             let _ = deregister_server(&self.server_config).await;
         }
+        if let Some(pid_file) = self.pid_file.take() {
+            if let Err(e) = std::fs::remove_file(&pid_file) {
+                info!("Failed to remove PID file {}: {}", pid_file.display(), e);
+            }
+        }
 
         info!("Server shutdown complete");
         Ok(())
@@ -337,6 +742,103 @@ impl MioServer {
         self.shutdown_signal.clone()
     }
 
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle {
+            signal: self.shutdown_signal.clone(),
+        }
+    }
+
+    /// Addresses the TCP listeners are bound to, with the actual port when
+    /// port 0 was configured
+    pub fn tcp_local_addrs(&self) -> Vec<SocketAddr> {
+        self.tcp_listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    pub fn tls_local_addrs(&self) -> Vec<SocketAddr> {
+        self.tls_listeners
+            .iter()
+            .filter_map(|listener| listener.local_addr().ok())
+            .collect()
+    }
+
+    pub fn config(&self) -> &ServerConfig {
+        &self.server_config
+    }
+
+    /// Flag set by the SIGHUP handler, checked by the accept loop
+    pub fn get_reload_signal(&self) -> Arc<AtomicBool> {
+        self.reload_signal.clone()
+    }
+
+    /// Re-reads the config file and arguments and applies what can change
+    /// without a restart. Running tests keep the settings they started with.
+    fn reload_config(&mut self) {
+        println!("Reloading configuration");
+        info!("Reloading configuration");
+        systemd::notify(&format!("RELOADING=1\nMONOTONIC_USEC={}", systemd::monotonic_usec()));
+        if let Err(e) = logger::reopen_log_file() {
+            println!("Failed to reopen log file: {}", e);
+        }
+
+        let reloaded = read_config_file(self.config_source.as_deref()).and_then(|file_config| {
+            for warning in &file_config.warnings {
+                warn!("{}", warning);
+            }
+            parse_reload_args(self.args.clone(), file_config)
+        });
+        let mut reloaded = match reloaded {
+            Ok(reloaded) => reloaded,
+            Err(e) => {
+                warn!("Reload failed, keeping the current configuration: {}", e);
+                systemd::notify("READY=1");
+                return;
+            }
+        };
+
+        // A certificate generated in memory would change its fingerprint
+        if reloaded.self_signed && (reloaded.cert_path.is_none() || reloaded.key_path.is_none()) {
+            reloaded.tls_config = self.server_config.tls_config.clone();
+            reloaded.tls_fingerprint = self.server_config.tls_fingerprint.clone();
+        } else if let Err(e) = Self::load_tls_config(&mut reloaded) {
+            warn!("Failed to reload TLS certificate: {}", e);
+        }
+        if self.server_config.tls_enabled() && !reloaded.tls_enabled() {
+            warn!("Keeping the current TLS certificate");
+        }
+
+        let reload = reload::merge(&self.server_config, reloaded, self.socket_activated);
+        if let Err(e) = logger::set_log_level(reload.config.log_level.unwrap_or(LevelFilter::Off)) {
+            warn!("Failed to change log level: {}", e);
+        }
+
+        self.server_config = reload.config;
+        *self.live_config.write().unwrap() = self.server_config.clone();
+
+        for setting in &reload.applied {
+            info!("Reloaded {}", setting);
+        }
+        for setting in &reload.needs_restart {
+            warn!("Changed {} requires a restart, keeping the current value", setting);
+        }
+        if reload.applied.is_empty() && reload.needs_restart.is_empty() {
+            info!("Configuration unchanged");
+        }
+
+        if reload.reregister && self.server_config.server_registration {
+            let config_clone = self.server_config.clone();
+            tokio::spawn(async move {
+                match register_server(&config_clone).await {
+                    Ok(_) => info!("Server registered with the new token"),
+                    Err(e) => info!("Server registration failed: {}", e),
+                }
+            });
+        }
+        systemd::notify("READY=1");
+    }
+
     fn check_global_queue(&mut self) -> io::Result<()> {
         Ok(())
     }
@@ -455,7 +957,7 @@ impl MioServer {
     }
 
     #[cfg(unix)]
-    fn bind_ipv6_with_v6only(addr: SocketAddr) -> io::Result<TcpListener> {
+    pub(crate) fn bind_ipv6_with_v6only(addr: SocketAddr) -> io::Result<TcpListener> {
         use std::os::unix::io::FromRawFd;
         
         // Extract IPv6 address and port
@@ -570,7 +1072,7 @@ impl MioServer {
     }
     
     #[cfg(not(unix))]
-    fn bind_ipv6_with_v6only(addr: SocketAddr) -> io::Result<TcpListener> {
+    pub(crate) fn bind_ipv6_with_v6only(addr: SocketAddr) -> io::Result<TcpListener> {
         // On Windows, IPV6_V6ONLY is set by default, so just bind normally
         TcpListener::bind(addr)
     }
@@ -582,3 +1084,46 @@ impl Drop for MioServer {
         debug!("MIO TCP Server shutting down");
     }
 }
+
+/// Creates `directory` if needed and hands it to the service user, also when
+/// it was left behind by an earlier run as root
+fn prepare_directory(directory: &Path, privileges: Option<&UserPrivileges>) -> io::Result<()> {
+    if !directory.is_dir() {
+        let mut builder = std::fs::DirBuilder::new();
+        // Daemonizing cleared the umask
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o755);
+        builder.recursive(true).create(directory)?;
+    }
+    if let Some(privileges) = privileges {
+        privileges.chown(directory)?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::MetadataExt;
+
+    #[test]
+    fn test_prepare_directory_hands_existing_directory_to_user() {
+        // Changing the owner needs root
+        if UserPrivileges::check_root().is_err() {
+            return;
+        }
+        let privileges = UserPrivileges::new("nobody").unwrap();
+        let root = std::env::temp_dir().join(format!("nettest-run-{}", std::process::id()));
+        let directory = root.join("nettest");
+        std::fs::create_dir_all(&directory).unwrap();
+        assert_eq!(std::fs::metadata(&directory).unwrap().uid(), 0);
+
+        prepare_directory(&directory, Some(&privileges)).unwrap();
+        assert_eq!(std::fs::metadata(&directory).unwrap().uid(), privileges.uid());
+
+        let created = root.join("created");
+        prepare_directory(&created, Some(&privileges)).unwrap();
+        assert_eq!(std::fs::metadata(&created).unwrap().uid(), privileges.uid());
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
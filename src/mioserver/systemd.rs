@@ -0,0 +1,326 @@
+//! Minimal sd_listen_fds(3) / sd_notify(3) implementation, so the server can run
+//! as a `Type=notify` service with socket activation and a watchdog without
+//! linking against libsystemd.
+
+use log::{debug, info, warn};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// First file descriptor passed by systemd (SD_LISTEN_FDS_START)
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// A listening socket inherited from systemd together with its
+/// `FileDescriptorName=` from the socket unit
+#[derive(Debug)]
+pub struct ListenFd {
+    pub listener: std::net::TcpListener,
+    pub name: Option<String>,
+}
+
+/// Takes over the sockets passed via `LISTEN_FDS`. The variables are removed
+/// from the environment so they are not inherited by child processes.
+#[cfg(unix)]
+pub fn listen_fds() -> Vec<ListenFd> {
+    use std::os::unix::io::FromRawFd;
+
+    let fds = inherited_fds(
+        std::env::var("LISTEN_PID").ok().as_deref(),
+        std::env::var("LISTEN_FDS").ok().as_deref(),
+        std::env::var("LISTEN_FDNAMES").ok().as_deref(),
+        std::process::id(),
+    );
+
+    std::env::remove_var("LISTEN_PID");
+    std::env::remove_var("LISTEN_FDS");
+    std::env::remove_var("LISTEN_FDNAMES");
+
+    let mut result = Vec::new();
+    for (fd, name) in fds {
+        unsafe {
+            libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+        }
+        // systemd only hands over the sockets configured in the socket unit
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(fd) };
+        debug!("Inherited socket fd {} (name: {:?})", fd, name);
+        result.push(ListenFd { listener, name });
+    }
+    result
+}
+
+/// File descriptors and names described by `LISTEN_PID`, `LISTEN_FDS` and
+/// `LISTEN_FDNAMES`, empty when they are meant for another process
+#[cfg(unix)]
+fn inherited_fds(
+    pid: Option<&str>,
+    count: Option<&str>,
+    names: Option<&str>,
+    own_pid: u32,
+) -> Vec<(i32, Option<String>)> {
+    let pid_matches = pid
+        .and_then(|pid| pid.parse::<u32>().ok())
+        .map(|pid| pid == own_pid)
+        .unwrap_or(false);
+    let count = count.and_then(|n| n.parse::<i32>().ok()).unwrap_or(0);
+    if !pid_matches || count <= 0 {
+        return vec![];
+    }
+    let names: Vec<&str> = names.map(|names| names.split(':').collect()).unwrap_or_default();
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .enumerate()
+        .map(|(i, fd)| {
+            let name = names
+                .get(i)
+                .filter(|name| !name.is_empty() && **name != "unknown")
+                .map(|name| name.to_string());
+            (fd, name)
+        })
+        .collect()
+}
+
+#[cfg(not(unix))]
+pub fn listen_fds() -> Vec<ListenFd> {
+    vec![]
+}
+
+/// Sends a state change like `READY=1` to the service manager.
+/// Does nothing when not started by systemd (`NOTIFY_SOCKET` unset).
+#[cfg(unix)]
+pub fn notify(state: &str) -> bool {
+    match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) => notify_socket(&path, state),
+        Err(_) => false,
+    }
+}
+
+/// Sends `state` to the socket at `path`, `@` prefixes an abstract name
+#[cfg(unix)]
+fn notify_socket(path: &str, state: &str) -> bool {
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(e) => {
+            debug!("Failed to create notify socket: {}", e);
+            return false;
+        }
+    };
+
+    let result = if let Some(abstract_name) = path.strip_prefix('@') {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            std::os::unix::net::SocketAddr::from_abstract_name(abstract_name.as_bytes())
+                .and_then(|addr| socket.send_to_addr(state.as_bytes(), &addr))
+        }
+        #[cfg(not(any(target_os = "linux", target_os = "android")))]
+        {
+            let _ = abstract_name;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "abstract sockets are only supported on Linux",
+            ))
+        }
+    } else {
+        socket.send_to(state.as_bytes(), path)
+    };
+
+    match result {
+        Ok(_) => {
+            debug!("sd_notify: {}", state.replace('\n', " "));
+            true
+        }
+        Err(e) => {
+            debug!("Failed to notify systemd ({}): {}", path, e);
+            false
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn notify(_state: &str) -> bool {
+    false
+}
+
+/// CLOCK_MONOTONIC in microseconds, sent along with `RELOADING=1`
+#[cfg(unix)]
+pub fn monotonic_usec() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe {
+        libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts);
+    }
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1_000
+}
+
+#[cfg(not(unix))]
+pub fn monotonic_usec() -> u64 {
+    0
+}
+
+/// Watchdog timeout requested by the unit's `WatchdogSec=`
+pub fn watchdog_interval() -> Option<Duration> {
+    parse_watchdog(
+        std::env::var("WATCHDOG_USEC").ok().as_deref(),
+        std::env::var("WATCHDOG_PID").ok().as_deref(),
+        std::process::id(),
+    )
+}
+
+/// `WATCHDOG_USEC`, ignored when `WATCHDOG_PID` names another process
+fn parse_watchdog(usec: Option<&str>, pid: Option<&str>, own_pid: u32) -> Option<Duration> {
+    let usec = usec?.parse::<u64>().ok()?;
+    if let Some(pid) = pid.and_then(|pid| pid.parse::<u32>().ok()) {
+        if pid != own_pid {
+            return None;
+        }
+    }
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Liveness timestamps of the threads the watchdog keeps an eye on
+/// (every worker plus the accept loop)
+pub struct Heartbeats {
+    start: Instant,
+    beats: Vec<AtomicU64>,
+}
+
+impl Heartbeats {
+    pub fn new(count: usize) -> Self {
+        Self {
+            start: Instant::now(),
+            beats: (0..count).map(|_| AtomicU64::new(0)).collect(),
+        }
+    }
+
+    pub fn beat(&self, index: usize) {
+        let now = self.start.elapsed().as_millis() as u64;
+        self.beats[index].store(now, Ordering::Relaxed);
+    }
+
+    /// Index of the first thread that hasn't reported for longer than `max_age`
+    pub fn stalled(&self, max_age: Duration) -> Option<usize> {
+        let now = self.start.elapsed().as_millis() as u64;
+        let max_age = max_age.as_millis() as u64;
+        self.beats
+            .iter()
+            .position(|beat| now.saturating_sub(beat.load(Ordering::Relaxed)) > max_age)
+    }
+}
+
+/// Sends `WATCHDOG=1` at half the watchdog interval for as long as all threads
+/// keep beating. A stalled worker stops the pings, so systemd restarts the service.
+pub fn start_watchdog(
+    interval: Duration,
+    heartbeats: Arc<Heartbeats>,
+    shutdown_signal: Arc<AtomicBool>,
+) {
+    info!("systemd watchdog enabled, interval {:?}", interval);
+    let spawned = thread::Builder::new()
+        .name("watchdog".to_string())
+        .spawn(move || {
+            let mut stalled_reported = false;
+            while !shutdown_signal.load(Ordering::Relaxed) {
+                match heartbeats.stalled(interval / 2) {
+                    None => {
+                        notify("WATCHDOG=1");
+                        stalled_reported = false;
+                    }
+                    Some(index) if !stalled_reported => {
+                        warn!("Thread {} stalled, withholding watchdog ping", index);
+                        stalled_reported = true;
+                    }
+                    Some(_) => {}
+                }
+                thread::sleep(interval / 4);
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to start watchdog thread: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_inherited_fds() {
+        assert_eq!(
+            inherited_fds(Some("42"), Some("3"), Some("rmbt:unknown:"), 42),
+            vec![(3, Some("rmbt".to_string())), (4, None), (5, None)]
+        );
+        assert_eq!(inherited_fds(Some("42"), Some("2"), None, 42), vec![(3, None), (4, None)]);
+        // Meant for another process, or nothing passed
+        assert!(inherited_fds(Some("41"), Some("2"), None, 42).is_empty());
+        assert!(inherited_fds(None, Some("2"), None, 42).is_empty());
+        assert!(inherited_fds(Some("42"), Some("0"), None, 42).is_empty());
+        assert!(inherited_fds(Some("42"), Some("x"), None, 42).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_notify_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let dir = std::env::temp_dir().join(format!("nettest-notify-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        assert!(notify_socket(path.to_str().unwrap(), "READY=1\nSTATUS=Serving"));
+        let mut buffer = [0u8; 64];
+        let n = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"READY=1\nSTATUS=Serving");
+
+        drop(receiver);
+        assert!(!notify_socket(path.to_str().unwrap(), "WATCHDOG=1"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn test_notify_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+
+        let name = format!("nettest-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let receiver = UnixDatagram::bind_addr(&addr).unwrap();
+
+        assert!(notify_socket(&format!("@{}", name), "WATCHDOG=1"));
+        let mut buffer = [0u8; 16];
+        let n = receiver.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"WATCHDOG=1");
+    }
+
+    #[test]
+    fn test_parse_watchdog() {
+        assert_eq!(parse_watchdog(Some("30000000"), None, 42), Some(Duration::from_secs(30)));
+        assert_eq!(parse_watchdog(Some("500"), Some("42"), 42), Some(Duration::from_micros(500)));
+        assert_eq!(parse_watchdog(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(parse_watchdog(Some("0"), None, 42), None);
+        assert_eq!(parse_watchdog(Some("soon"), None, 42), None);
+        assert_eq!(parse_watchdog(None, Some("42"), 42), None);
+    }
+
+    #[test]
+    fn test_heartbeats_stall_detection() {
+        let heartbeats = Heartbeats::new(2);
+        assert_eq!(heartbeats.stalled(Duration::from_secs(10)), None);
+
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(heartbeats.stalled(Duration::from_millis(10)), Some(0));
+        heartbeats.beat(0);
+        assert_eq!(heartbeats.stalled(Duration::from_millis(10)), Some(1));
+        heartbeats.beat(1);
+        assert_eq!(heartbeats.stalled(Duration::from_millis(10)), None);
+    }
+}
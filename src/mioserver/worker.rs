@@ -3,26 +3,34 @@ use log::{debug, info, trace};
 use mio::{Events, Interest, Poll, Token};
 use regex::Regex;
 use std::collections::{HashMap, VecDeque};
-use std::io::{self};
-use std::sync::{Arc, Mutex};
+use mio::net::TcpStream;
+use std::io::{self, Read};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
 use std::thread;
 use std::time::{Duration, Instant};
 
 use crate::config::constants::MIN_CHUNK_SIZE;
+use crate::logger::{self, ConnectionContext};
 use crate::mioserver::handlers::basic_handler::{
     handle_client_readable_data, handle_client_writable_data,
 };
-
-// Connection processing timeout constant
-const CONNECTION_PROCESSING_TIMEOUT: u64 = 60;
+use crate::mioserver::proxy_protocol::{
+    forwarded_client_addr, is_trusted, parse_header, ProxyHeader, MAX_HEADER_LENGTH,
+};
 use crate::mioserver::server::{ConnectionType, ServerConfig, TestState};
+use crate::mioserver::systemd::Heartbeats;
 use crate::mioserver::ServerTestPhase;
 use crate::stream::stream::Stream;
 use crate::tokio_server::utils::use_http::RMBT_UPGRADE;
 use crate::tokio_server::utils::websocket::Handshake;
 
+// Connection processing timeout constant
+const CONNECTION_PROCESSING_TIMEOUT: u64 = 60;
+
 pub struct WorkerThread {
-    _thread: thread::JoinHandle<()>,
+    thread: thread::JoinHandle<()>,
 }
 
 struct Worker {
@@ -32,8 +40,12 @@ struct Worker {
     events: Events,
     worker_connection_counts: Arc<Mutex<Vec<usize>>>,
     global_queue: Arc<Mutex<VecDeque<(ConnectionType, Instant)>>>, // Global queue
+    live_config: Arc<RwLock<ServerConfig>>,
+    /// Snapshot of `live_config` taken when a connection is picked up
     server_config: ServerConfig,
     next_token: usize,
+    heartbeats: Arc<Heartbeats>,
+    shutdown_signal: Arc<AtomicBool>,
 }
 
 impl WorkerThread {
@@ -41,21 +53,36 @@ impl WorkerThread {
         id: usize,
         worker_connection_counts: Arc<Mutex<Vec<usize>>>,
         global_queue: Arc<Mutex<VecDeque<(ConnectionType, Instant)>>>,
-        server_config: ServerConfig,
+        live_config: Arc<RwLock<ServerConfig>>,
+        heartbeats: Arc<Heartbeats>,
+        shutdown_signal: Arc<AtomicBool>,
     ) -> io::Result<Self> {
         let thread = thread::Builder::new()
             .stack_size(8 * 1024 * 1024) // 8MB stack
             .spawn(move || {
                 debug!("Worker {}: starting", id);
-                let mut worker =
-                    Worker::new(id, worker_connection_counts, global_queue, server_config)
-                        .expect("Failed to create worker");
+                let mut worker = Worker::new(
+                    id,
+                    worker_connection_counts,
+                    global_queue,
+                    live_config,
+                    heartbeats,
+                    shutdown_signal,
+                )
+                .expect("Failed to create worker");
                 if let Err(e) = worker.run() {
                     info!("Worker {} error: {}", id, e);
                 }
             })?;
 
-        Ok(WorkerThread { _thread: thread })
+        Ok(WorkerThread { thread })
+    }
+
+    /// Waits for the worker to stop after the shutdown signal was set
+    pub fn join(self) {
+        if self.thread.join().is_err() {
+            info!("Worker thread panicked");
+        }
     }
 }
 
@@ -64,8 +91,11 @@ impl Worker {
         id: usize,
         worker_connection_counts: Arc<Mutex<Vec<usize>>>,
         global_queue: Arc<Mutex<VecDeque<(ConnectionType, Instant)>>>,
-        server_config: ServerConfig,
+        live_config: Arc<RwLock<ServerConfig>>,
+        heartbeats: Arc<Heartbeats>,
+        shutdown_signal: Arc<AtomicBool>,
     ) -> io::Result<Self> {
+        let server_config = live_config.read().unwrap().clone();
         let poll = Poll::new()?;
         let events = Events::with_capacity(1024);
         let connections = HashMap::new();
@@ -77,14 +107,20 @@ impl Worker {
             events,
             worker_connection_counts,
             global_queue,
-            server_config: server_config.clone(),
+            live_config,
+            server_config,
             next_token: 1,
+            heartbeats,
+            shutdown_signal,
         })
     }
 
     fn run(&mut self) -> io::Result<()> {
-        loop {
+        while !self.shutdown_signal.load(Ordering::Relaxed) {
+            self.heartbeats.beat(self.id);
             let maybe_connection = if self.connections.is_empty() {
+                // Left over from a connection that failed during setup
+                logger::set_connection_context(None);
                 let mut global_queue = self.global_queue.lock().unwrap();
                 if let Some((connection, _)) = global_queue.pop_front() {
                     info!(
@@ -111,25 +147,63 @@ impl Worker {
             };
 
             if let Some(connection) = maybe_connection {
+                self.server_config = self.live_config.read().unwrap().clone();
+                let token = Token(self.next_token);
+                self.next_token += 1;
+                let connection_id = logger::next_connection_id();
+                let peer = match &connection {
+                    ConnectionType::Tcp(_, addr) | ConnectionType::Tls(_, addr) => *addr,
+                };
+                logger::set_connection_context(Some(ConnectionContext {
+                    id: connection_id,
+                    client_ip: Some(peer.ip()),
+                    phase: ServerTestPhase::GreetingReceiveConnectionType,
+                }));
                 info!("Worker {}: processing connection", self.id);
-                let (mut stream, ip) = match connection {
+
+                let connection = if self.server_config.proxy_protocol {
+                    match self.apply_proxy_header(connection) {
+                        Ok(connection) => connection,
+                        Err(e) => {
+                            info!("Worker {}: Rejected connection: {}", self.id, e);
+                            self.release_connection_slot();
+                            continue;
+                        }
+                    }
+                } else {
+                    connection
+                };
+
+                let (mut stream, mut ip) = match connection {
                     ConnectionType::Tcp(stream, client_addr) => {
                         (Stream::Tcp(stream), Some(client_addr))
                     }
                     ConnectionType::Tls(stream, client_addr) => {
-                        let stream = Stream::new_rustls_server(
-                            stream,
-                            self.server_config.cert_path.clone().unwrap(),
-                            self.server_config.key_path.clone().unwrap(),
-                        )
-                        .unwrap();
-                        (stream, Some(client_addr))
+                        let tls_config = match self.server_config.tls_config.clone() {
+                            Some(tls_config) => tls_config,
+                            None => {
+                                info!("Worker {}: TLS connection without TLS configuration", self.id);
+                                self.release_connection_slot();
+                                continue;
+                            }
+                        };
+                        match Stream::new_rustls_server_with_config(stream, tls_config) {
+                            Ok(stream) => (stream, Some(client_addr)),
+                            Err(e) => {
+                                info!("Worker {}: Failed to create TLS stream: {}", self.id, e);
+                                self.release_connection_slot();
+                                continue;
+                            }
+                        }
                     }
                 };
 
-                let token = Token(self.next_token);
-                self.next_token += 1;
-
+                // The PROXY header may have replaced the peer address
+                logger::set_connection_context(Some(ConnectionContext {
+                    id: connection_id,
+                    client_ip: ip.map(|addr| addr.ip()),
+                    phase: ServerTestPhase::GreetingReceiveConnectionType,
+                }));
                 info!("Worker {}: registering connection", self.id);
 
                 // Register new connection
@@ -140,13 +214,15 @@ impl Worker {
                     continue;
                 }
 
-                match self.handle_greeting_receive_connection_type(stream, token) {
+                match self.handle_greeting_receive_connection_type(stream, token, &mut ip) {
                     Ok(stream) => {
                         self.connections.insert(
                             token,
                             TestState {
                                 token,
+                                connection_id,
                                 connection_start: Instant::now(), // Connection processing start time
+                                time_limit: Duration::from_secs(CONNECTION_PROCESSING_TIMEOUT),
                                 // stream: Stream::new_rustls_server(stream, None, None).unwrap(),
                                 stream: stream,
                                 measurement_state: ServerTestPhase::GreetingSendVersion,
@@ -171,6 +247,8 @@ impl Worker {
                                 bytes_received: VecDeque::new(),
                                 client_addr: ip,
                                 sig_key: Some(self.server_config.secret_key.clone()),
+                                max_chunk_size: self.server_config.chunk_size_limit(),
+                                max_transfer_secs: self.server_config.max_transfer_secs,
                                 loop_iteration_count: 0,
                             },
                         );
@@ -224,6 +302,8 @@ impl Worker {
                 thread::sleep(Duration::from_millis(100));
             }
         }
+        debug!("Worker {}: stopping", self.id);
+        Ok(())
     }
 
     fn process_all_connections(&mut self) -> io::Result<()> {
@@ -243,6 +323,7 @@ impl Worker {
             );
             let event_token = event.token();
             if let Some(state) = self.connections.get_mut(&event_token) {
+                logger::set_connection_context(Some(state.log_context()));
                 let mut should_remove: Result<usize, io::Error> = Ok(0);
                 if event.is_readable() {
                     trace!(
@@ -296,11 +377,10 @@ impl Worker {
         }
 
         for (token, state) in self.connections.iter_mut() {
-            if state.connection_start.elapsed() > Duration::from_secs(CONNECTION_PROCESSING_TIMEOUT)
-            {
+            if state.connection_start.elapsed() > state.time_limit {
                 debug!(
                     "Worker {}: connection {:?} processing timeout after {} seconds",
-                    self.id, token, CONNECTION_PROCESSING_TIMEOUT
+                    self.id, token, state.time_limit.as_secs()
                 );
                 connections_to_remove.push(token.clone());
             }
@@ -309,6 +389,7 @@ impl Worker {
         for token in connections_to_remove {
             // Explicitly close the connection before removing
             if let Some(mut connection) = self.connections.remove(&token) {
+                logger::set_connection_context(Some(connection.log_context()));
                 if let Err(close_err) = connection.stream.close() {
                     debug!("Failed to close stream: {}", close_err);
                 }
@@ -330,6 +411,7 @@ impl Worker {
                 token,
                 self.connections.len()
             );
+            logger::set_connection_context(None);
         }
 
         trace!("Worker {}: finished processing events", self.id);
@@ -337,10 +419,99 @@ impl Worker {
         Ok(())
     }
 
+    /// Reads the PROXY protocol header from a freshly accepted connection and
+    /// replaces the peer address with the client address it carries. The header
+    /// is peeked first, so no TLS or HTTP bytes behind it are consumed.
+    fn apply_proxy_header(&self, connection: ConnectionType) -> io::Result<ConnectionType> {
+        let (mut stream, peer, is_tls) = match connection {
+            ConnectionType::Tcp(stream, peer) => (stream, peer, false),
+            ConnectionType::Tls(stream, peer) => (stream, peer, true),
+        };
+
+        if !self.server_config.trusted_proxies.is_empty()
+            && !is_trusted(&self.server_config.trusted_proxies, &peer.ip())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("PROXY header from untrusted peer {}", peer),
+            ));
+        }
+
+        // A poll of its own, waiting on the worker's poll would take the
+        // events of the other connections
+        let mut poll = Poll::new()?;
+        poll.registry()
+            .register(&mut stream, Token(0), Interest::READABLE)?;
+        let header = Self::read_proxy_header(&mut poll, &mut stream);
+        poll.registry().deregister(&mut stream)?;
+
+        let client_addr = match header? {
+            ProxyHeader::Proxied { source, .. } => {
+                debug!(
+                    "Worker {}: PROXY header from {}, client address {}",
+                    self.id, peer, source
+                );
+                source
+            }
+            ProxyHeader::Local => peer,
+        };
+
+        Ok(if is_tls {
+            ConnectionType::Tls(stream, client_addr)
+        } else {
+            ConnectionType::Tcp(stream, client_addr)
+        })
+    }
+
+    fn read_proxy_header(poll: &mut Poll, stream: &mut TcpStream) -> io::Result<ProxyHeader> {
+        let timeout = Duration::from_millis(2000);
+        let start_time = Instant::now();
+        let mut buffer = [0u8; MAX_HEADER_LENGTH];
+        let mut events = Events::with_capacity(1);
+
+        loop {
+            match stream.peek(&mut buffer) {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "Connection closed before PROXY header",
+                    ))
+                }
+                Ok(n) => match parse_header(&buffer[..n]) {
+                    Ok(Some((header, length))) => {
+                        // The header bytes are already buffered, so this doesn't block
+                        stream.read_exact(&mut buffer[..length])?;
+                        return Ok(header);
+                    }
+                    Ok(None) if n < buffer.len() => {}
+                    Ok(None) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "PROXY header too long",
+                        ))
+                    }
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                },
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e),
+            }
+
+            let elapsed = start_time.elapsed();
+            if elapsed > timeout {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "Timeout waiting for PROXY header",
+                ));
+            }
+            poll.poll(&mut events, Some(timeout - elapsed))?;
+        }
+    }
+
     fn handle_greeting_receive_connection_type(
         &mut self,
         mut stream: Stream,
         token: Token,
+        client_addr: &mut Option<SocketAddr>,
     ) -> io::Result<Stream> {
         info!(
             "Worker {}: handle_greeting_receive_connection_type",
@@ -390,6 +561,19 @@ impl Worker {
                                 let is_websocket = ws_regex.is_match(&request);
                                 debug!("Worker {}: is_websocket: {}", self.id, is_websocket);
                                 if is_websocket {
+                                    if let Some(peer) = *client_addr {
+                                        if let Some(forwarded) = forwarded_client_addr(
+                                            &request,
+                                            peer,
+                                            &self.server_config.trusted_proxies,
+                                        ) {
+                                            debug!(
+                                                "Worker {}: forwarded client address {} via proxy {}",
+                                                self.id, forwarded, peer
+                                            );
+                                            *client_addr = Some(forwarded);
+                                        }
+                                    }
                                     stream = stream.upgrade_to_websocket().unwrap();
                                     let handshake = Handshake::parse(&request).unwrap();
                                     stream.finish_server_handshake(handshake).unwrap();
@@ -432,6 +616,16 @@ impl Worker {
         Ok(stream)
     }
 
+    fn release_connection_slot(&self) {
+        let mut counts = self.worker_connection_counts.lock().unwrap();
+        counts[self.id] -= 1;
+        trace!(
+            "Worker {}: connection count decreased to {}",
+            self.id,
+            counts[self.id]
+        );
+    }
+
     fn check_global_queue_timeout(&self) {
         let mut global_queue = self.global_queue.lock().unwrap();
         let now = Instant::now();
@@ -9,6 +9,8 @@ use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::Arc;
 
+use crate::client::source::SourceBinding;
+
 
 #[derive(Debug)]
 pub struct RustlsStream {
@@ -22,10 +24,11 @@ pub struct RustlsStream {
 impl RustlsStream {
     pub fn new(
         addr: SocketAddr,
+        source: &SourceBinding,
         cert_path: Option<&Path>,
         key_path: Option<&Path>,
     ) -> Result<Self> {
-        let stream = TcpStream::connect(addr)?;
+        let stream = source.connect(addr)?;
         if let Err(_) = stream.set_nodelay(true) {
             std::thread::sleep(std::time::Duration::from_millis(1000));
             if let Err(e) = stream.set_nodelay(true) {
@@ -18,6 +18,13 @@ pub struct RustlsServerStream {
 
 impl RustlsServerStream {
     pub fn new(stream: TcpStream, cert_path: String, key_path: String) -> Result<Self> {
+        let config = load_server_config(Path::new(&cert_path), Path::new(&key_path))?;
+        Self::with_config(stream, config)
+    }
+
+    /// Creates the stream from an already built rustls configuration, so that the
+    /// certificate does not have to be read from disk for every connection
+    pub fn with_config(stream: TcpStream, config: Arc<ServerConfig>) -> Result<Self> {
         if let Err(_) = stream.set_nodelay(true) {
             std::thread::sleep(std::time::Duration::from_millis(1000));
             if let Err(e) = stream.set_nodelay(true) {
@@ -25,18 +32,7 @@ impl RustlsServerStream {
             }
         }
 
-        let certs = load_certs(Path::new(&cert_path))?;
-        let key = load_private_key(Path::new(&key_path))?;
-
-        let mut config = ServerConfig::builder()
-            .with_no_client_auth()
-            .with_single_cert(certs, key)
-            .map_err(|e| Error::msg(format!("Failed to create server config: {}", e)))?;
-
-            config.alpn_protocols = vec![b"http/1.1".to_vec()];
-
-
-        let conn = ServerConnection::new(Arc::new(config))?;
+        let conn = ServerConnection::new(config)?;
 
         info!("RustlsServerStream created");
 
@@ -269,6 +265,34 @@ impl RustlsServerStream {
     }
 }
 
+pub fn load_server_config(cert_path: &Path, key_path: &Path) -> Result<Arc<ServerConfig>> {
+    let certs = load_certs(cert_path)?;
+    let key = load_private_key(key_path)?;
+    build_server_config(certs, key)
+}
+
+/// Builds the server configuration from PEM encoded certificate chain and key
+pub fn server_config_from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Arc<ServerConfig>> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(cert_pem))
+        .collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut BufReader::new(key_pem))?
+        .ok_or_else(|| Error::msg("No private keys found in PEM data"))?;
+    build_server_config(certs, key)
+}
+
+fn build_server_config(
+    certs: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+) -> Result<Arc<ServerConfig>> {
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| Error::msg(format!("Failed to create server config: {}", e)))?;
+
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(Arc::new(config))
+}
+
 pub fn load_certs(cert_path: &Path) -> Result<Vec<CertificateDer<'static>>, Error> {
     let cert_path = cert_path.to_str().unwrap();
     debug!("Loading certificates from {}", cert_path);
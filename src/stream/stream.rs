@@ -4,8 +4,11 @@ use mio::{net::TcpStream, Interest, Poll, Token};
 use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::path::Path;
+use std::sync::Arc;
 
 use crate::client::constants::RMBT_UPGRADE_REQUEST;
+use crate::client::setup::SetupMarks;
+use crate::client::source::SourceBinding;
 use crate::stream::{
     websocket::WebSocketClient,
     websocket_tls_openssl::WebSocketTlsClient,
@@ -29,9 +32,9 @@ pub enum Stream {
 }
 
 impl Stream {
-    pub fn new_tcp(addr: SocketAddr) -> Result<Self> {
+    pub fn new_tcp(addr: SocketAddr, source: &SourceBinding) -> Result<Self> {
         debug!("Connecting to TCP at {}", addr);
-        let stream = TcpStream::connect(addr)?;
+        let stream = source.connect(addr)?;
         if let Err(_) = stream.set_nodelay(true) {
             std::thread::sleep(std::time::Duration::from_millis(1000));
             if let Err(e) = stream.set_nodelay(true) {
@@ -79,14 +82,19 @@ impl Stream {
 
 
 
-    pub fn new_websocket(addr: SocketAddr) -> Result<Self> {
-        let ws_client = WebSocketClient::new(addr)?;
+    pub fn new_websocket(addr: SocketAddr, source: &SourceBinding) -> Result<Self> {
+        let ws_client = WebSocketClient::new(addr, source)?;
         Ok(Self::WebSocket(ws_client))
     }
 
-    pub fn new_rustls(addr: SocketAddr, cert_path: Option<&Path>, key_path: Option<&Path>) -> Result<Self> {
+    pub fn new_rustls(
+        addr: SocketAddr,
+        source: &SourceBinding,
+        cert_path: Option<&Path>,
+        key_path: Option<&Path>,
+    ) -> Result<Self> {
         debug!("Creating Rustls stream {:?}", addr);
-        let stream = RustlsStream::new(addr, cert_path, key_path)?;
+        let stream = RustlsStream::new(addr, source, cert_path, key_path)?;
         Ok(Self::Rustls(stream))
     }
 
@@ -95,6 +103,14 @@ impl Stream {
         Ok(Self::RustlsServer(stream))
     }
 
+    pub fn new_rustls_server_with_config(
+        stream: TcpStream,
+        config: Arc<rustls::ServerConfig>,
+    ) -> Result<Self> {
+        let stream = RustlsServerStream::with_config(stream, config)?;
+        Ok(Self::RustlsServer(stream))
+    }
+
     pub fn close(&mut self) -> Result<()> {
         match self {
             Stream::Tcp(_) => Ok(()),
@@ -107,6 +123,15 @@ impl Stream {
         }
     }
 
+    /// Setup steps the stream completed while it was created
+    pub fn setup_marks(&self) -> SetupMarks {
+        match self {
+            Stream::WebSocket(stream) => stream.setup.clone(),
+            Stream::WebSocketTls(stream) => stream.setup.clone(),
+            _ => SetupMarks::default(),
+        }
+    }
+
     pub fn get_greeting(&mut self) -> Vec<u8> {
         match self {
             Stream::Tcp(_) => RMBT_UPGRADE_REQUEST.as_bytes().to_vec(),
@@ -132,8 +157,8 @@ impl Stream {
     }
     
 
-    pub fn new_websocket_tls(addr: SocketAddr) -> Result<Self> {
-        let stream1 = TcpStream::connect(addr)?;
+    pub fn new_websocket_tls(addr: SocketAddr, source: &SourceBinding) -> Result<Self> {
+        let stream1 = source.connect(addr)?;
         if let Err(_) = stream1.set_nodelay(true) {
             std::thread::sleep(std::time::Duration::from_millis(1000));
             if let Err(e) = stream1.set_nodelay(true) {
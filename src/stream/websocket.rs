@@ -5,10 +5,12 @@ use mio::{net::TcpStream, Interest, Poll, Token};
 use sha1::{Digest, Sha1};
 use std::io::{self, Read, Write};
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tungstenite::protocol::WebSocketConfig;
 use tungstenite::{protocol::WebSocket, Message};
 
+use crate::client::setup::SetupMarks;
+use crate::client::source::SourceBinding;
 use crate::config::constants::CHUNK_SIZE;
 use crate::tokio_server::utils::websocket::{generate_handshake_response, Handshake};
 
@@ -21,6 +23,8 @@ pub struct WebSocketClient {
     handshake_rrequest: Vec<u8>,
     flushed: bool,
     read_buffer: Vec<u8>,
+    /// Connect and upgrade, done by `new`
+    pub setup: SetupMarks,
 }
 
 impl WebSocketClient {
@@ -43,12 +47,13 @@ impl WebSocketClient {
             handshake_rrequest: vec![],
             flushed: true,
             read_buffer: vec![],
+            setup: SetupMarks::default(),
         })
     }
 
-    pub fn new(addr: SocketAddr) -> Result<Self> {
+    pub fn new(addr: SocketAddr, source: &SourceBinding) -> Result<Self> {
         debug!("Connecting to WebSocket server WS at {}", addr);
-        let mut stream = TcpStream::connect(addr)?;
+        let mut stream = source.connect(addr)?;
         if let Err(_) = stream.set_nodelay(true) {
             std::thread::sleep(std::time::Duration::from_millis(1000));
             if let Err(e) = stream.set_nodelay(true) {
@@ -84,6 +89,7 @@ impl WebSocketClient {
         debug!("WebSocket handshake request: {}", request);
 
         // Send handshake request
+        let mut setup = SetupMarks::default();
         loop {
             poll.poll(&mut events, None)?;
             let mut connection_ready = false;
@@ -95,6 +101,7 @@ impl WebSocketClient {
             }
 
             if connection_ready {
+                setup.connected.get_or_insert_with(Instant::now);
                 match stream.write(request.as_bytes()) {
                     Ok(n) => {
                         if n == 0 {
@@ -139,6 +146,7 @@ impl WebSocketClient {
                         }
                         debug!("WebSocket handshake response loop 3");
                         response.extend_from_slice(&buffer[..n]);
+                        setup.mark_upgrade_response(&response);
 
                         let line = String::from_utf8_lossy(&buffer);
 
@@ -194,6 +202,7 @@ impl WebSocketClient {
         if accept_key != expected_accept {
             return Err(anyhow::anyhow!("Invalid Sec-WebSocket-Accept key"));
         }
+        setup.upgraded.get_or_insert_with(Instant::now);
 
         poll.registry().deregister(&mut stream)?;
         // Create WebSocket with the established connection
@@ -208,6 +217,7 @@ impl WebSocketClient {
             handshake_rrequest: request.as_bytes().to_vec(),
             flushed: true,
             read_buffer: vec![],
+            setup,
         })
     }
 
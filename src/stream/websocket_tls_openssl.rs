@@ -9,10 +9,12 @@ use sha1::{Digest, Sha1};
 use std::io::{self, Read, Write};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Instant;
 use tungstenite::protocol::WebSocketConfig;
 use tungstenite::Message;
 use tungstenite::WebSocket;
 
+use crate::client::setup::SetupMarks;
 use crate::config::constants::CHUNK_SIZE;
 
 const WS_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
@@ -23,6 +25,8 @@ pub struct WebSocketTlsClient {
     handshake_rrequest: Vec<u8>,
     flushed: bool,
     read_buffer: Vec<u8>,
+    /// Connect, TLS handshake and upgrade, done by `new`
+    pub setup: SetupMarks,
 }
 
 impl WebSocketTlsClient {
@@ -76,6 +80,10 @@ impl WebSocketTlsClient {
                 break;
             }
         }
+        let mut setup = SetupMarks {
+            connected: Some(Instant::now()),
+            ..SetupMarks::default()
+        };
 
         // TLS handshake via complete_io
         let mut conn = conn;
@@ -84,6 +92,7 @@ impl WebSocketTlsClient {
                 Ok((_, _)) => {
                     if !conn.is_handshaking() {
                         debug!("TLS handshake completed");
+                        setup.mark_tls(&conn);
                         break;
                     }
                 }
@@ -174,6 +183,7 @@ impl WebSocketTlsClient {
                         return Err(anyhow::anyhow!("Connection closed during handshake"));
                     }
                     response.extend_from_slice(&buffer[current_pos..current_pos + n]);
+                    setup.mark_upgrade_response(&response);
                     current_pos += n;
 
                     let line = String::from_utf8_lossy(&buffer[..current_pos]);
@@ -214,6 +224,7 @@ impl WebSocketTlsClient {
         if accept_key != expected_accept {
             return Err(anyhow::anyhow!("Invalid Sec-WebSocket-Accept key"));
         }
+        setup.upgraded.get_or_insert_with(Instant::now);
 
         let config = WebSocketConfig::default();
         poll.registry().deregister(tls_stream.get_mut())?;
@@ -228,6 +239,7 @@ impl WebSocketTlsClient {
             handshake_rrequest: request.as_bytes().to_vec(),
             flushed: true,
             read_buffer: vec![],
+            setup,
         })
     }
 
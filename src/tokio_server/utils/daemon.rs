@@ -37,8 +37,9 @@ pub fn daemonize() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             dup2(null_fd, 1)?;
             dup2(null_fd, 2)?;
 
+            // null_fd itself is closed when `devnull` is dropped, closing it here
+            // as well is a double close that aborts debug builds
             unsafe {
-                libc::close(null_fd);
                 libc::close(stdin_fd);
                 libc::close(stdout_fd);
                 libc::close(stderr_fd);
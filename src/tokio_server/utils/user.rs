@@ -1,8 +1,10 @@
 use std::ffi::CString;
 use std::io;
+use std::path::Path;
 
 #[derive(Debug)]
 pub struct UserPrivileges {
+    username: String,
     uid: u32,
     gid: u32,
 }
@@ -23,25 +25,79 @@ impl UserPrivileges {
             }
 
             Ok(Self {
+                username: username.to_string(),
                 uid: (*pw).pw_uid,
                 gid: (*pw).pw_gid,
             })
         }
     }
 
+    /// Uses `group` as primary group instead of the user's login group
+    #[cfg(unix)]
+    pub fn with_group(mut self, group: &str) -> io::Result<Self> {
+        let c_group =
+            CString::new(group).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+        unsafe {
+            let gr = libc::getgrnam(c_group.as_ptr());
+            if gr.is_null() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("Error: could not find group \"{}\"", group),
+                ));
+            }
+            self.gid = (*gr).gr_gid;
+        }
+        Ok(self)
+    }
+
+    #[cfg(windows)]
+    pub fn with_group(self, _group: &str) -> io::Result<Self> {
+        Ok(self)
+    }
+
     #[cfg(windows)]
     pub fn new(username: &str) -> io::Result<Self> {
         // On Windows use fixed values
         // In real application Windows API can be used here
         Ok(Self {
+            username: username.to_string(),
             uid: 1000, // Default user ID
             gid: 1000, // Default group ID
         })
     }
 
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Hands `path` to the user and group, e.g. a directory created as root
+    /// that the user has to write to after dropping privileges
+    #[cfg(unix)]
+    pub fn chown(&self, path: &Path) -> io::Result<()> {
+        std::os::unix::fs::chown(path, Some(self.uid), Some(self.gid))
+    }
+
+    #[cfg(windows)]
+    pub fn chown(&self, _path: &Path) -> io::Result<()> {
+        Ok(())
+    }
+
     #[cfg(unix)]
     pub fn drop_privileges(&self) -> io::Result<()> {
+        let c_username = CString::new(self.username.as_str())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
         unsafe {
+            // Replace root's supplementary groups with the ones of the target user,
+            // this has to happen while we still are root
+            if libc::initgroups(c_username.as_ptr(), self.gid as _) != 0 {
+                return Err(io::Error::other(format!(
+                    "Error: failed to set supplementary groups: {}",
+                    io::Error::last_os_error()
+                )));
+            }
+
             if libc::setgid(self.gid) != 0 {
                 return Err(io::Error::new(
                     io::ErrorKind::Other,
@@ -56,6 +112,12 @@ impl UserPrivileges {
                 ));
             }
 
+            if self.uid != 0 && libc::setuid(0) == 0 {
+                return Err(io::Error::other(
+                    "Error: root privileges could be regained after dropping them",
+                ));
+            }
+
             Ok(())
         }
     }